@@ -0,0 +1,88 @@
+use std::{mem, sync::Arc, time::Duration};
+
+use tonic::transport::Endpoint;
+
+use crate::{
+    key_value_service::{
+        replication_message::Kind, replication_service_client::ReplicationServiceClient,
+        ReplicateRequest,
+    },
+    services::replication_service::replicated_change,
+    storage::{KeyspaceSnapshot, ReplicaStorage},
+};
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps `storage` in sync with the leader at `leader`, reconnecting whenever the stream
+/// breaks. After the first snapshot a reconnecting follower only asks for the writes it
+/// missed, the leader sends a new snapshot if they are no longer retained.
+pub fn spawn_follower(storage: &Arc<ReplicaStorage>, leader: Endpoint) {
+    let storage = Arc::downgrade(storage);
+    tokio::spawn(async move {
+        loop {
+            let Some(storage) = storage.upgrade() else {
+                break;
+            };
+            match follow(&storage, &leader).await {
+                Ok(()) => tracing::warn!("Leader {} ended replication", leader.uri()),
+                Err(err) => tracing::warn!("Replication from {} failed: {:?}", leader.uri(), err),
+            }
+            drop(storage);
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    });
+}
+
+async fn follow(storage: &ReplicaStorage, leader: &Endpoint) -> anyhow::Result<()> {
+    let mut client = ReplicationServiceClient::new(leader.connect().await?);
+    let after_revision = if storage.is_synced() {
+        Some(storage.revision().await)
+    } else {
+        None
+    };
+    tracing::info!(
+        "Replicating from {} after revision {:?}",
+        leader.uri(),
+        after_revision
+    );
+    let mut stream = client
+        .replicate(ReplicateRequest { after_revision })
+        .await?
+        .into_inner();
+
+    let mut entries = Vec::new();
+    while let Some(message) = stream.message().await? {
+        match message.kind {
+            Some(Kind::Snapshot(chunk)) => {
+                for key in chunk.entries {
+                    if let (key, Some(entry)) = replicated_change(key)? {
+                        entries.push((key, entry));
+                    }
+                }
+                if chunk.last {
+                    tracing::info!(
+                        "Loaded snapshot of {} keys at revision {}",
+                        entries.len(),
+                        chunk.revision
+                    );
+                    storage
+                        .restore(KeyspaceSnapshot {
+                            revision: chunk.revision,
+                            entries: mem::take(&mut entries),
+                        })
+                        .await;
+                }
+            }
+            Some(Kind::Write(write)) => {
+                let changes = write
+                    .changes
+                    .into_iter()
+                    .map(replicated_change)
+                    .collect::<Result<_, _>>()?;
+                storage.apply(write.revision, changes).await?;
+            }
+            None => anyhow::bail!("received an empty replication message"),
+        }
+    }
+    Ok(())
+}
@@ -1,19 +1,68 @@
-use std::collections::HashMap;
+use std::{sync::Arc, time::Duration};
 
-use key_value_service::key_value_service_server::KeyValueServiceServer;
-use services::key_value_service::KeyValueService;
-use tonic::transport::{server::Router, Server, ServerTlsConfig};
+use follower::spawn_follower;
+use key_value_service::{
+    admin_service_server::AdminServiceServer, cluster_service_server::ClusterServiceServer,
+    key_value_service_server::KeyValueServiceServer, raft_service_server::RaftServiceServer,
+    replication_service_server::ReplicationServiceServer,
+};
+use services::{
+    admin_service::AdminService, cluster_service::ClusterService,
+    key_value_service::KeyValueService, raft_service::RaftService,
+    replication_service::ReplicationService,
+};
+use storage::{
+    spawn_expiry_reaper, ClusterStorage, InMemoryStorage, ReplicaStorage, StorageEngine,
+};
+use tonic::transport::{server::Router, ClientTlsConfig, Endpoint, Server, ServerTlsConfig};
 
-pub mod key_value_service {
-    tonic::include_proto!("keyvalueservice");
-}
+pub use kv_service_proto::key_value_service;
 
+pub mod follower;
+pub mod raft;
 mod services;
-mod utils;
+pub mod storage;
+pub mod tls;
+
+const EXPIRY_REAPER_INTERVAL: Duration = Duration::from_secs(1);
 
 pub fn create_grpc_server(tls_config: Option<ServerTlsConfig>) -> anyhow::Result<Router> {
-    let storage = HashMap::new();
-    let key_value_service = KeyValueService::new(storage);
+    create_grpc_server_with_storage(Arc::new(InMemoryStorage::default()), tls_config)
+}
+
+/// Creates a read-only server that replicates the keyspace of the leader at `leader_address`.
+pub fn create_follower_grpc_server(
+    leader_address: &str,
+    leader_tls_config: Option<ClientTlsConfig>,
+    tls_config: Option<ServerTlsConfig>,
+) -> anyhow::Result<Router> {
+    let leader = grpc_endpoint(leader_address, leader_tls_config)?;
+    let storage = Arc::new(ReplicaStorage::new(leader_address));
+    spawn_follower(&storage, leader);
+    create_grpc_server_with_storage(storage, tls_config)
+}
+
+/// Creates a server for a node of a Raft cluster, which also serves the RPCs between nodes.
+pub fn create_cluster_grpc_server(
+    storage: Arc<ClusterStorage>,
+    tls_config: Option<ServerTlsConfig>,
+) -> anyhow::Result<Router> {
+    let raft_service = RaftService::new(storage.clone());
+    let cluster_service = ClusterService::new(storage.clone());
+    Ok(create_grpc_server_with_storage(storage, tls_config)?
+        .add_service(RaftServiceServer::new(raft_service))
+        .add_service(ClusterServiceServer::new(cluster_service)))
+}
+
+pub fn create_grpc_server_with_storage<S: StorageEngine>(
+    storage: Arc<S>,
+    tls_config: Option<ServerTlsConfig>,
+) -> anyhow::Result<Router> {
+    spawn_expiry_reaper(&storage, EXPIRY_REAPER_INTERVAL);
+
+    let key_value_service = KeyValueService::new(storage.clone());
+    let replication_service = ReplicationService::new(storage.clone());
+    let admin_service = AdminService::new(storage);
 
     let mut server = Server::builder();
 
@@ -23,5 +72,18 @@ pub fn create_grpc_server(tls_config: Option<ServerTlsConfig>) -> anyhow::Result
 
     Ok(server
         .trace_fn(|_| tracing::info_span!("kv_service_backend_server"))
-        .add_service(KeyValueServiceServer::new(key_value_service)))
+        .add_service(KeyValueServiceServer::new(key_value_service))
+        .add_service(ReplicationServiceServer::new(replication_service))
+        .add_service(AdminServiceServer::new(admin_service)))
+}
+
+pub(crate) fn grpc_endpoint(
+    address: &str,
+    client_tls_config: Option<ClientTlsConfig>,
+) -> anyhow::Result<Endpoint> {
+    Ok(if let Some(client_tls_config) = client_tls_config {
+        Endpoint::from_shared(format!("https://{}", address))?.tls_config(client_tls_config)?
+    } else {
+        Endpoint::from_shared(format!("http://{}", address))?
+    })
 }
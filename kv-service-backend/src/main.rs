@@ -1,8 +1,16 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::Context;
-use kv_service_backend::create_grpc_server;
-use tonic::transport::{Certificate, ServerTlsConfig};
+use kv_service_backend::{
+    create_cluster_grpc_server, create_follower_grpc_server, create_grpc_server,
+    create_grpc_server_with_storage,
+    raft::{parse_peers, ClusterConfig},
+    storage::{spawn_periodic_snapshots, ClusterStorage, FsyncPolicy, PersistentStorage},
+    tls::{tls_incoming, ReloadableServerTls, ServerTlsFiles},
+};
+use kv_service_config::Config;
+use tokio::net::TcpListener;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
@@ -15,40 +23,115 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let addr = dotenvy::var("GRPC_SERVER_ADDRESS")
-        .context("GRPC_SERVER_ADDRESS must be set")?
-        .parse()?;
+    let config = Config::load()?;
 
-    let tls = dotenvy::var("TLS").context("TLS must be set")?.parse()?;
+    let addr: std::net::SocketAddr = config.required("GRPC_SERVER_ADDRESS")?.parse()?;
 
-    let tls_config = if tls {
-        Some(create_tls_config()?)
+    let tls = config.required("TLS")?.parse()?;
+
+    // The server's certificates are reloaded while it runs, so TLS is terminated in front of
+    // tonic instead of through its ServerTlsConfig.
+    let server_tls = if tls {
+        Some(create_tls_config(&config)?)
     } else {
         None
     };
 
-    let server = create_grpc_server(tls_config)?;
+    let persistence: bool = config.required("PERSISTENCE")?.parse()?;
+
+    // Followers replicate the leader's keyspace in memory, so they don't need persistence.
+    let leader_address = config.var("LEADER_ADDRESS").unwrap_or_default();
+
+    let raft_node_id = config.var("RAFT_NODE_ID").unwrap_or_default();
+
+    let server = if !raft_node_id.is_empty() {
+        anyhow::ensure!(
+            leader_address.is_empty(),
+            "LEADER_ADDRESS can't be set on a cluster node, the cluster elects its own leader"
+        );
+        let peers = parse_peers(&config.var("RAFT_PEERS").unwrap_or_default())?;
+        // The Raft log is always synced before a write is acknowledged.
+        let data_dir = if persistence {
+            Some(PathBuf::from(config.required("STORAGE_DIR")?))
+        } else {
+            None
+        };
+        let peer_tls_config = if tls {
+            Some(create_client_tls_config(&config)?)
+        } else {
+            None
+        };
+        let cluster_config = ClusterConfig {
+            node_id: raft_node_id
+                .parse()
+                .context("RAFT_NODE_ID must be a number")?,
+            peers,
+            data_dir,
+            peer_tls_config,
+        };
+        tracing::info!("Starting cluster node {}", cluster_config.node_id);
+        let storage = Arc::new(ClusterStorage::start(cluster_config).await?);
+        create_cluster_grpc_server(storage, None)?
+    } else if !leader_address.is_empty() {
+        anyhow::ensure!(
+            !persistence,
+            "PERSISTENCE can't be enabled on a follower, it keeps its data in memory"
+        );
+        let leader_tls_config = if tls {
+            Some(create_client_tls_config(&config)?)
+        } else {
+            None
+        };
+        tracing::info!("Following leader at {}", leader_address);
+        create_follower_grpc_server(&leader_address, leader_tls_config, None)?
+    } else if persistence {
+        let storage_dir = config.required("STORAGE_DIR")?;
+        let fsync_policy: FsyncPolicy = config.required("STORAGE_FSYNC_POLICY")?.parse()?;
+        let snapshot_interval_secs: u64 = config.required("SNAPSHOT_INTERVAL_SECS")?.parse()?;
+        let storage = Arc::new(PersistentStorage::open(&storage_dir, fsync_policy).await?);
+        if snapshot_interval_secs > 0 {
+            spawn_periodic_snapshots(&storage, Duration::from_secs(snapshot_interval_secs));
+        }
+        create_grpc_server_with_storage(storage, None)?
+    } else {
+        create_grpc_server(None)?
+    };
 
     tracing::info!("Listening on {}", addr);
-    server.serve(addr).await?;
+    match server_tls {
+        Some(server_tls) => {
+            let listener = TcpListener::bind(addr).await?;
+            server
+                .serve_with_incoming(tls_incoming(listener, server_tls))
+                .await?
+        }
+        None => server.serve(addr).await?,
+    }
 
     Ok(())
 }
 
-fn create_tls_config() -> anyhow::Result<ServerTlsConfig> {
-    let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap_or(&PathBuf::new())
-        .join("tls");
-    let cert = std::fs::read_to_string(data_dir.join("server.crt"))?;
-    let key = std::fs::read_to_string(data_dir.join("server.key"))?;
-
-    let server_identity = tonic::transport::Identity::from_pem(cert, key);
-
-    let client_ca_cert = std::fs::read_to_string(data_dir.join("root.crt"))?;
-    let client_ca_cert = Certificate::from_pem(client_ca_cert);
+fn create_tls_config(config: &Config) -> anyhow::Result<ReloadableServerTls> {
+    let files = ServerTlsFiles {
+        certificate_chain: config.tls_path("TLS_CERT_FILE", "server.crt"),
+        private_key: config.tls_path("TLS_KEY_FILE", "server.key"),
+        client_ca: config.tls_path("TLS_CLIENT_CA_FILE", "root.crt"),
+    };
+    let reload_interval_secs: u64 = config
+        .var("TLS_RELOAD_INTERVAL_SECS")
+        .unwrap_or("10".to_string())
+        .parse()
+        .context("TLS_RELOAD_INTERVAL_SECS must be a number of seconds")?;
+    ReloadableServerTls::watch(files, Duration::from_secs(reload_interval_secs))
+}
 
-    Ok(ServerTlsConfig::new()
-        .identity(server_identity)
-        .client_ca_root(client_ca_cert))
+fn create_client_tls_config(config: &Config) -> anyhow::Result<ClientTlsConfig> {
+    let root_cert = config.certificates("TLS_CA_FILE", "root.crt")?;
+    let client_cert = config.certificates("TLS_CLIENT_CERT_FILE", "client.crt")?;
+    let client_key = config.private_key("TLS_CLIENT_KEY_FILE", "client.key")?;
+    let ca_domain_name = config.required("CA_DOMAIN_NAME")?;
+    Ok(ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(root_cert.pem))
+        .identity(Identity::from_pem(client_cert.pem, client_key.pem))
+        .domain_name(ca_domain_name))
 }
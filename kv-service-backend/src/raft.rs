@@ -0,0 +1,169 @@
+use anyhow::Context;
+use openraft::{BasicNode, Config, SnapshotPolicy};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc},
+};
+use tonic::transport::ClientTlsConfig;
+
+use crate::storage::{
+    BatchOperation, BatchResult, Condition, Entry, InMemoryStorage, Precondition, SetResult,
+    TransactionResult,
+};
+
+mod log_store;
+mod network;
+mod state_machine;
+
+use log_store::LogStore;
+use network::Network;
+use state_machine::StateMachine;
+
+const HEARTBEAT_INTERVAL_MS: u64 = 100;
+const ELECTION_TIMEOUT_MIN_MS: u64 = 500;
+const ELECTION_TIMEOUT_MAX_MS: u64 = 1000;
+const SNAPSHOT_LOGS: u64 = 10_000;
+/// Keeps the JSON encoded `InstallSnapshot` messages below the default gRPC message size limit.
+const SNAPSHOT_CHUNK_SIZE: u64 = 512 * 1024;
+
+pub type NodeId = u64;
+
+openraft::declare_raft_types!(
+    pub TypeConfig:
+        D = Request,
+        R = Option<CommandResult>,
+);
+
+pub type Raft = openraft::Raft<TypeConfig>;
+
+type LogEntry = openraft::Entry<TypeConfig>;
+
+/// A write that goes through the Raft log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    Set {
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+    },
+    Delete {
+        key: String,
+    },
+    Batch {
+        operations: Vec<BatchOperation>,
+    },
+    Transaction {
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub command: Command,
+    /// Milliseconds since the Unix epoch on the node that proposed the command. Every node
+    /// applies the command at this time, so they all agree on which entries have expired.
+    pub now: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommandResult {
+    Set(SetResult),
+    Delete(Option<Value>),
+    Batch(Vec<BatchResult>),
+    Transaction(TransactionResult),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub node_id: NodeId,
+    /// gRPC addresses of the voters of a new cluster, by node id. The node with the lowest id
+    /// initializes the cluster. Empty for nodes that join an existing cluster as learners.
+    pub peers: BTreeMap<NodeId, String>,
+    /// Directory for the Raft log, vote and snapshot, which are only kept in memory if unset.
+    pub data_dir: Option<PathBuf>,
+    pub peer_tls_config: Option<ClientTlsConfig>,
+}
+
+/// Parses peers in the `1=host:port,2=host:port` format.
+pub fn parse_peers(peers: &str) -> anyhow::Result<BTreeMap<NodeId, String>> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| {
+            let (id, address) = peer
+                .split_once('=')
+                .with_context(|| format!("invalid peer {peer:?}, expected <id>=<address>"))?;
+            let id = id
+                .parse()
+                .with_context(|| format!("invalid node id in peer {peer:?}"))?;
+            Ok((id, address.to_string()))
+        })
+        .collect()
+}
+
+/// Starts the Raft node described by `config`, which applies committed commands to `memory`
+/// and stores the time of the last one in `applied_now`.
+pub(crate) async fn start(
+    config: &ClusterConfig,
+    memory: Arc<InMemoryStorage>,
+    applied_now: Arc<AtomicU64>,
+) -> anyhow::Result<Raft> {
+    let raft_config = Config {
+        cluster_name: "kv-service".to_string(),
+        heartbeat_interval: HEARTBEAT_INTERVAL_MS,
+        election_timeout_min: ELECTION_TIMEOUT_MIN_MS,
+        election_timeout_max: ELECTION_TIMEOUT_MAX_MS,
+        snapshot_policy: SnapshotPolicy::LogsSinceLast(SNAPSHOT_LOGS),
+        snapshot_max_chunk_size: SNAPSHOT_CHUNK_SIZE,
+        ..Default::default()
+    }
+    .validate()?;
+    let data_dir = config.data_dir.as_deref();
+    let log_store = LogStore::open(data_dir).await?;
+    let state_machine = StateMachine::open(data_dir, memory, applied_now).await?;
+    let network = Network::new(config.peer_tls_config.clone());
+    let raft = Raft::new(
+        config.node_id,
+        Arc::new(raft_config),
+        network,
+        log_store,
+        state_machine,
+    )
+    .await?;
+
+    if config.peers.keys().next() == Some(&config.node_id) && !raft.is_initialized().await? {
+        let members: BTreeMap<NodeId, BasicNode> = config
+            .peers
+            .iter()
+            .map(|(id, address)| (*id, BasicNode::new(address)))
+            .collect();
+        tracing::info!("Initializing cluster with members {:?}", members);
+        raft.initialize(members).await?;
+    }
+    Ok(raft)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peers() {
+        assert_eq!(
+            parse_peers("1=127.0.0.1:8081, 2=127.0.0.1:8082,").unwrap(),
+            BTreeMap::from([
+                (1, "127.0.0.1:8081".to_string()),
+                (2, "127.0.0.1:8082".to_string())
+            ])
+        );
+        assert!(parse_peers("").unwrap().is_empty());
+        assert!(parse_peers("127.0.0.1:8081").is_err());
+        assert!(parse_peers("a=127.0.0.1:8081").is_err());
+    }
+}
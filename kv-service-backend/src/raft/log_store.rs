@@ -0,0 +1,304 @@
+use anyhow::Context;
+use openraft::{
+    storage::{LogFlushed, LogState, RaftLogStorage},
+    LogId, OptionalSend, RaftLogReader, StorageError, StorageIOError, Vote,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::{LogEntry, NodeId, TypeConfig};
+
+const LOG_FILE_NAME: &str = "raft.log";
+const STATE_FILE_NAME: &str = "raft-state.json";
+
+/// Everything besides the log entries that has to survive a restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HardState {
+    vote: Option<Vote<NodeId>>,
+    last_purged: Option<LogId<NodeId>>,
+}
+
+#[derive(Debug)]
+struct Log {
+    state: HardState,
+    entries: BTreeMap<u64, LogEntry>,
+    /// Open for appending, `None` if the log is only kept in memory.
+    file: Option<File>,
+    dir: Option<PathBuf>,
+}
+
+impl Log {
+    async fn append(&mut self, entries: Vec<LogEntry>) -> anyhow::Result<()> {
+        if let Some(file) = &mut self.file {
+            let mut lines = Vec::new();
+            for entry in &entries {
+                serde_json::to_writer(&mut lines, entry)?;
+                lines.push(b'\n');
+            }
+            file.write_all(&lines).await?;
+            file.flush().await?;
+            file.sync_data().await?;
+        }
+        self.entries
+            .extend(entries.into_iter().map(|entry| (entry.log_id.index, entry)));
+        Ok(())
+    }
+
+    async fn save_state(&self) -> anyhow::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        write_atomically(dir, STATE_FILE_NAME, &serde_json::to_vec(&self.state)?).await
+    }
+
+    /// Replaces the log file with the entries that are currently kept.
+    async fn rewrite(&mut self) -> anyhow::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let mut contents = Vec::new();
+        for entry in self.entries.values() {
+            serde_json::to_writer(&mut contents, entry)?;
+            contents.push(b'\n');
+        }
+        write_atomically(dir, LOG_FILE_NAME, &contents).await?;
+        self.file = Some(open_log(dir).await?);
+        Ok(())
+    }
+}
+
+/// Raft log and vote of a node.
+///
+/// With a data directory, appended entries are synced to an append-only file before they are
+/// acknowledged, and the file is rewritten when the log is truncated or purged.
+#[derive(Debug, Clone)]
+pub(crate) struct LogStore {
+    log: Arc<Mutex<Log>>,
+}
+
+impl LogStore {
+    pub(crate) async fn open(dir: Option<&Path>) -> anyhow::Result<Self> {
+        let mut log = Log {
+            state: HardState::default(),
+            entries: BTreeMap::new(),
+            file: None,
+            dir: dir.map(Path::to_path_buf),
+        };
+        if let Some(dir) = dir {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .with_context(|| format!("couldn't create Raft directory {}", dir.display()))?;
+            if let Some(contents) = read_if_exists(&dir.join(STATE_FILE_NAME)).await? {
+                log.state = serde_json::from_slice(&contents).context("corrupted Raft state")?;
+            }
+            let first_index = log.state.last_purged.map_or(0, |log_id| log_id.index + 1);
+            let path = dir.join(LOG_FILE_NAME);
+            if let Some(contents) = read_if_exists(&path).await? {
+                log.entries = parse_log(&contents, &path)?
+                    .into_iter()
+                    .filter(|entry| entry.log_id.index >= first_index)
+                    .map(|entry| (entry.log_id.index, entry))
+                    .collect();
+            }
+            // Drops an incomplete last line and entries left behind by an interrupted purge.
+            log.rewrite().await?;
+            tracing::info!(
+                "Loaded {} Raft log entries from {}",
+                log.entries.len(),
+                dir.display()
+            );
+        }
+        Ok(Self {
+            log: Arc::new(Mutex::new(log)),
+        })
+    }
+}
+
+async fn read_if_exists(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match tokio::fs::read(path).await {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("couldn't read {}", path.display())),
+    }
+}
+
+fn parse_log(contents: &[u8], path: &Path) -> anyhow::Result<Vec<LogEntry>> {
+    let mut entries = Vec::new();
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        let Some(line) = line.strip_suffix(b"\n") else {
+            // A crash in the middle of an append leaves a partial last line behind, the entry
+            // was never acknowledged.
+            tracing::warn!(
+                "Discarding incomplete entry at the end of {}",
+                path.display()
+            );
+            break;
+        };
+        entries.push(
+            serde_json::from_slice(line)
+                .with_context(|| format!("corrupted entry in {}", path.display()))?,
+        );
+    }
+    Ok(entries)
+}
+
+async fn open_log(dir: &Path) -> anyhow::Result<File> {
+    let path = dir.join(LOG_FILE_NAME);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_context(|| format!("couldn't open Raft log {}", path.display()))
+}
+
+pub(super) async fn write_atomically(
+    dir: &Path,
+    file_name: &str,
+    contents: &[u8],
+) -> anyhow::Result<()> {
+    let path = dir.join(file_name);
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
+
+impl RaftLogReader<TypeConfig> for LogStore {
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug + OptionalSend>(
+        &mut self,
+        range: RB,
+    ) -> Result<Vec<LogEntry>, StorageError<NodeId>> {
+        let log = self.log.lock().await;
+        Ok(log
+            .entries
+            .range(range)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+}
+
+impl RaftLogStorage<TypeConfig> for LogStore {
+    type LogReader = Self;
+
+    async fn get_log_state(&mut self) -> Result<LogState<TypeConfig>, StorageError<NodeId>> {
+        let log = self.log.lock().await;
+        let last_purged_log_id = log.state.last_purged;
+        let last_log_id = log
+            .entries
+            .values()
+            .next_back()
+            .map(|entry| entry.log_id)
+            .or(last_purged_log_id);
+        Ok(LogState {
+            last_purged_log_id,
+            last_log_id,
+        })
+    }
+
+    async fn get_log_reader(&mut self) -> Self::LogReader {
+        self.clone()
+    }
+
+    async fn save_vote(&mut self, vote: &Vote<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let mut log = self.log.lock().await;
+        log.state.vote = Some(*vote);
+        log.save_state().await.map_err(StorageIOError::write_vote)?;
+        Ok(())
+    }
+
+    async fn read_vote(&mut self) -> Result<Option<Vote<NodeId>>, StorageError<NodeId>> {
+        Ok(self.log.lock().await.state.vote)
+    }
+
+    async fn append<I>(
+        &mut self,
+        entries: I,
+        callback: LogFlushed<TypeConfig>,
+    ) -> Result<(), StorageError<NodeId>>
+    where
+        I: IntoIterator<Item = LogEntry> + OptionalSend,
+        I::IntoIter: OptionalSend,
+    {
+        let mut log = self.log.lock().await;
+        log.append(entries.into_iter().collect())
+            .await
+            .map_err(StorageIOError::write_logs)?;
+        callback.log_io_completed(Ok(()));
+        Ok(())
+    }
+
+    async fn truncate(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let mut log = self.log.lock().await;
+        log.entries.split_off(&log_id.index);
+        log.rewrite().await.map_err(StorageIOError::write_logs)?;
+        Ok(())
+    }
+
+    async fn purge(&mut self, log_id: LogId<NodeId>) -> Result<(), StorageError<NodeId>> {
+        let mut log = self.log.lock().await;
+        // The purge point is saved first, entries up to it are skipped when the log is loaded.
+        log.state.last_purged = Some(log_id);
+        log.save_state().await.map_err(StorageIOError::write_logs)?;
+        log.entries = log.entries.split_off(&(log_id.index + 1));
+        log.rewrite().await.map_err(StorageIOError::write_logs)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openraft::{CommittedLeaderId, EntryPayload};
+
+    use super::*;
+
+    fn entry(index: u64) -> LogEntry {
+        LogEntry {
+            log_id: LogId::new(CommittedLeaderId::new(1, 1), index),
+            payload: EntryPayload::Blank,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LogStore::open(Some(dir.path())).await.unwrap();
+        {
+            let mut log = store.log.lock().await;
+            log.append((1..=5).map(entry).collect()).await.unwrap();
+        }
+        let mut writer = store.clone();
+        writer.save_vote(&Vote::new(1, 1)).await.unwrap();
+        writer.truncate(entry(5).log_id).await.unwrap();
+        writer.purge(entry(2).log_id).await.unwrap();
+        drop((store, writer));
+
+        let mut store = LogStore::open(Some(dir.path())).await.unwrap();
+        assert_eq!(store.read_vote().await.unwrap(), Some(Vote::new(1, 1)));
+        let state = store.get_log_state().await.unwrap();
+        assert_eq!(state.last_purged_log_id, Some(entry(2).log_id));
+        assert_eq!(state.last_log_id, Some(entry(4).log_id));
+        let entries = store.try_get_log_entries(0..10).await.unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.log_id.index)
+                .collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use openraft::{
+    error::{InstallSnapshotError, NetworkError, RPCError, RaftError, RemoteError, Unreachable},
+    network::{RPCOption, RaftNetwork, RaftNetworkFactory},
+    raft::{
+        AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest,
+        InstallSnapshotResponse, VoteRequest, VoteResponse,
+    },
+    AnyError, BasicNode,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::{
+    transport::{Channel, ClientTlsConfig},
+    Code, Response, Status,
+};
+
+use super::{NodeId, TypeConfig};
+use crate::{
+    grpc_endpoint,
+    key_value_service::{raft_service_client::RaftServiceClient, RaftMessage},
+};
+
+type RpcError<E = openraft::error::Infallible> = RPCError<NodeId, BasicNode, RaftError<NodeId, E>>;
+type RpcResult<T, E = openraft::error::Infallible> = Result<T, RpcError<E>>;
+
+/// Connects to the other nodes of the cluster through their `RaftService`.
+#[derive(Debug, Clone)]
+pub(crate) struct Network {
+    tls_config: Option<ClientTlsConfig>,
+}
+
+impl Network {
+    pub(crate) fn new(tls_config: Option<ClientTlsConfig>) -> Self {
+        Self { tls_config }
+    }
+}
+
+impl RaftNetworkFactory<TypeConfig> for Network {
+    type Network = Connection;
+
+    async fn new_client(&mut self, target: NodeId, node: &BasicNode) -> Self::Network {
+        Connection {
+            target,
+            address: node.addr.clone(),
+            tls_config: self.tls_config.clone(),
+            client: None,
+        }
+    }
+}
+
+pub(crate) struct Connection {
+    target: NodeId,
+    address: String,
+    tls_config: Option<ClientTlsConfig>,
+    client: Option<RaftServiceClient<Channel>>,
+}
+
+impl Connection {
+    fn client(&mut self) -> Result<RaftServiceClient<Channel>, Unreachable> {
+        if let Some(client) = &self.client {
+            return Ok(client.clone());
+        }
+        let endpoint = grpc_endpoint(&self.address, self.tls_config.clone())
+            .map_err(|err| Unreachable::from(AnyError::from(err)))?;
+        let client = RaftServiceClient::new(endpoint.connect_lazy());
+        self.client = Some(client.clone());
+        Ok(client)
+    }
+
+    fn request<T: Serialize>(
+        &self,
+        message: &T,
+        option: &RPCOption,
+    ) -> Result<tonic::Request<RaftMessage>, NetworkError> {
+        let data = serde_json::to_vec(message).map_err(|err| NetworkError::new(&err))?;
+        let mut request = tonic::Request::new(RaftMessage { data });
+        request.set_timeout(option.hard_ttl());
+        Ok(request)
+    }
+
+    fn remote_error<E: std::error::Error>(&self, err: RaftError<NodeId, E>) -> RpcError<E> {
+        RPCError::RemoteError(RemoteError::new(self.target, err))
+    }
+}
+
+fn status_error<E: std::error::Error>(status: Status) -> RpcError<E> {
+    match status.code() {
+        Code::Unavailable => RPCError::Unreachable(Unreachable::new(&status)),
+        _ => RPCError::Network(NetworkError::new(&status)),
+    }
+}
+
+/// Decodes the result returned by the remote node, whose error is the remote `RaftError`.
+fn decode<T: DeserializeOwned, E: DeserializeOwned>(
+    response: Response<RaftMessage>,
+) -> Result<Result<T, RaftError<NodeId, E>>, NetworkError> {
+    serde_json::from_slice(&response.into_inner().data).map_err(|err| NetworkError::new(&err))
+}
+
+impl RaftNetwork<TypeConfig> for Connection {
+    async fn append_entries(
+        &mut self,
+        rpc: AppendEntriesRequest<TypeConfig>,
+        option: RPCOption,
+    ) -> RpcResult<AppendEntriesResponse<NodeId>> {
+        let request = self.request(&rpc, &option)?;
+        let response = self.client()?.append_entries(request).await;
+        decode(response.map_err(status_error)?)?.map_err(|err| self.remote_error(err))
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        rpc: InstallSnapshotRequest<TypeConfig>,
+        option: RPCOption,
+    ) -> RpcResult<InstallSnapshotResponse<NodeId>, InstallSnapshotError> {
+        let request = self.request(&rpc, &option)?;
+        let response = self.client()?.install_snapshot(request).await;
+        decode(response.map_err(status_error)?)?.map_err(|err| self.remote_error(err))
+    }
+
+    async fn vote(
+        &mut self,
+        rpc: VoteRequest<NodeId>,
+        option: RPCOption,
+    ) -> RpcResult<VoteResponse<NodeId>> {
+        let request = self.request(&rpc, &option)?;
+        let response = self.client()?.vote(request).await;
+        decode(response.map_err(status_error)?)?.map_err(|err| self.remote_error(err))
+    }
+}
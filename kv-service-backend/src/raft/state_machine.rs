@@ -0,0 +1,339 @@
+use anyhow::Context;
+use openraft::{
+    storage::{RaftStateMachine, Snapshot},
+    BasicNode, EntryPayload, LogId, OptionalSend, RaftSnapshotBuilder, SnapshotMeta, StorageError,
+    StorageIOError, StoredMembership,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{
+    log_store::write_atomically, Command, CommandResult, LogEntry, NodeId, Request, TypeConfig,
+};
+use crate::storage::{InMemoryStorage, KeyspaceSnapshot};
+
+const SNAPSHOT_FILE_NAME: &str = "raft-snapshot";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotData {
+    keyspace: KeyspaceSnapshot,
+    now: u64,
+}
+
+#[derive(Debug, Clone)]
+struct StoredSnapshot {
+    meta: SnapshotMeta<NodeId, BasicNode>,
+    data: Vec<u8>,
+}
+
+impl From<StoredSnapshot> for Snapshot<TypeConfig> {
+    fn from(snapshot: StoredSnapshot) -> Self {
+        Self {
+            meta: snapshot.meta,
+            snapshot: Box::new(Cursor::new(snapshot.data)),
+        }
+    }
+}
+
+impl StoredSnapshot {
+    /// The snapshot file holds a line with the JSON encoded metadata followed by the data.
+    async fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        let mut contents = serde_json::to_vec(&self.meta)?;
+        contents.push(b'\n');
+        contents.extend_from_slice(&self.data);
+        write_atomically(dir, SNAPSHOT_FILE_NAME, &contents).await
+    }
+
+    async fn load(dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = dir.join(SNAPSHOT_FILE_NAME);
+        let mut contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("couldn't read snapshot {}", path.display()))
+            }
+        };
+        let meta_len = contents
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .with_context(|| format!("corrupted snapshot {}", path.display()))?;
+        let data = contents.split_off(meta_len + 1);
+        let meta = serde_json::from_slice(&contents[..meta_len])
+            .with_context(|| format!("corrupted snapshot {}", path.display()))?;
+        Ok(Some(Self { meta, data }))
+    }
+}
+
+/// Applies committed commands to the keyspace served by [`crate::storage::ClusterStorage`].
+pub(crate) struct StateMachine {
+    memory: Arc<InMemoryStorage>,
+    /// Largest [`Request::now`] applied so far.
+    applied_now: Arc<AtomicU64>,
+    last_applied: Option<LogId<NodeId>>,
+    membership: StoredMembership<NodeId, BasicNode>,
+    /// Shared with snapshot builders, which run in the background.
+    current_snapshot: Arc<Mutex<Option<StoredSnapshot>>>,
+    dir: Option<PathBuf>,
+}
+
+impl StateMachine {
+    /// Creates a state machine on top of `memory`, loading the snapshot in `dir` if there is one.
+    pub(crate) async fn open(
+        dir: Option<&Path>,
+        memory: Arc<InMemoryStorage>,
+        applied_now: Arc<AtomicU64>,
+    ) -> anyhow::Result<Self> {
+        let mut state_machine = Self {
+            memory,
+            applied_now,
+            last_applied: None,
+            membership: StoredMembership::default(),
+            current_snapshot: Arc::default(),
+            dir: dir.map(Path::to_path_buf),
+        };
+        if let Some(dir) = dir {
+            if let Some(snapshot) = StoredSnapshot::load(dir).await? {
+                tracing::info!("Loading Raft snapshot {}", snapshot.meta.snapshot_id);
+                state_machine.restore(snapshot).await?;
+            }
+        }
+        Ok(state_machine)
+    }
+
+    async fn restore(&mut self, snapshot: StoredSnapshot) -> anyhow::Result<()> {
+        let data: SnapshotData = serde_json::from_slice(&snapshot.data)?;
+        self.memory.restore(data.keyspace).await;
+        self.applied_now.store(data.now, Ordering::Release);
+        self.last_applied = snapshot.meta.last_log_id;
+        self.membership = snapshot.meta.last_membership.clone();
+        *self.current_snapshot.lock().unwrap() = Some(snapshot);
+        Ok(())
+    }
+
+    async fn execute(&self, request: Request) -> CommandResult {
+        // The clock of a new leader may be behind the previous one's, but an entry that expired
+        // must stay expired.
+        let now = self
+            .applied_now
+            .fetch_max(request.now, Ordering::AcqRel)
+            .max(request.now);
+        match request.command {
+            Command::Set {
+                key,
+                entry,
+                condition,
+            } => CommandResult::Set(self.memory.set_at(key, entry, condition, now).await),
+            Command::Delete { key } => {
+                CommandResult::Delete(self.memory.delete_at(&key, now).await)
+            }
+            Command::Batch { operations } => {
+                CommandResult::Batch(self.memory.batch_at(operations, now).await)
+            }
+            Command::Transaction {
+                preconditions,
+                operations,
+            } => CommandResult::Transaction(
+                self.memory
+                    .transaction_at(preconditions, operations, now)
+                    .await,
+            ),
+        }
+    }
+}
+
+/// Holds a copy of the keyspace taken between two applied entries.
+pub(crate) struct SnapshotBuilder {
+    data: SnapshotData,
+    last_applied: Option<LogId<NodeId>>,
+    membership: StoredMembership<NodeId, BasicNode>,
+    current_snapshot: Arc<Mutex<Option<StoredSnapshot>>>,
+    dir: Option<PathBuf>,
+}
+
+impl RaftSnapshotBuilder<TypeConfig> for SnapshotBuilder {
+    async fn build_snapshot(&mut self) -> Result<Snapshot<TypeConfig>, StorageError<NodeId>> {
+        let data = serde_json::to_vec(&self.data)
+            .map_err(|err| StorageIOError::write_snapshot(None, &err))?;
+        let snapshot_id = self
+            .last_applied
+            .map_or_else(|| "empty".to_string(), |log_id| log_id.to_string());
+        let snapshot = StoredSnapshot {
+            meta: SnapshotMeta {
+                last_log_id: self.last_applied,
+                last_membership: self.membership.clone(),
+                snapshot_id,
+            },
+            data,
+        };
+        if let Some(dir) = &self.dir {
+            snapshot.save(dir).await.map_err(|err| {
+                StorageIOError::write_snapshot(Some(snapshot.meta.signature()), err)
+            })?;
+        }
+        *self.current_snapshot.lock().unwrap() = Some(snapshot.clone());
+        Ok(snapshot.into())
+    }
+}
+
+impl RaftStateMachine<TypeConfig> for StateMachine {
+    type SnapshotBuilder = SnapshotBuilder;
+
+    async fn applied_state(
+        &mut self,
+    ) -> Result<(Option<LogId<NodeId>>, StoredMembership<NodeId, BasicNode>), StorageError<NodeId>>
+    {
+        Ok((self.last_applied, self.membership.clone()))
+    }
+
+    async fn apply<I>(
+        &mut self,
+        entries: I,
+    ) -> Result<Vec<Option<CommandResult>>, StorageError<NodeId>>
+    where
+        I: IntoIterator<Item = LogEntry> + OptionalSend,
+        I::IntoIter: OptionalSend,
+    {
+        let mut results = Vec::new();
+        for entry in entries {
+            self.last_applied = Some(entry.log_id);
+            let result = match entry.payload {
+                EntryPayload::Blank => None,
+                EntryPayload::Normal(request) => Some(self.execute(request).await),
+                EntryPayload::Membership(membership) => {
+                    self.membership = StoredMembership::new(Some(entry.log_id), membership);
+                    None
+                }
+            };
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    async fn get_snapshot_builder(&mut self) -> Self::SnapshotBuilder {
+        SnapshotBuilder {
+            data: SnapshotData {
+                keyspace: self.memory.keyspace().await,
+                now: self.applied_now.load(Ordering::Acquire),
+            },
+            last_applied: self.last_applied,
+            membership: self.membership.clone(),
+            current_snapshot: self.current_snapshot.clone(),
+            dir: self.dir.clone(),
+        }
+    }
+
+    async fn begin_receiving_snapshot(
+        &mut self,
+    ) -> Result<Box<Cursor<Vec<u8>>>, StorageError<NodeId>> {
+        Ok(Box::new(Cursor::new(Vec::new())))
+    }
+
+    async fn install_snapshot(
+        &mut self,
+        meta: &SnapshotMeta<NodeId, BasicNode>,
+        snapshot: Box<Cursor<Vec<u8>>>,
+    ) -> Result<(), StorageError<NodeId>> {
+        let snapshot = StoredSnapshot {
+            meta: meta.clone(),
+            data: snapshot.into_inner(),
+        };
+        tracing::info!("Installing Raft snapshot {}", meta.snapshot_id);
+        if let Some(dir) = &self.dir {
+            snapshot
+                .save(dir)
+                .await
+                .map_err(|err| StorageIOError::write_snapshot(Some(meta.signature()), err))?;
+        }
+        self.restore(snapshot)
+            .await
+            .map_err(|err| StorageIOError::read_snapshot(Some(meta.signature()), err))?;
+        Ok(())
+    }
+
+    async fn get_current_snapshot(
+        &mut self,
+    ) -> Result<Option<Snapshot<TypeConfig>>, StorageError<NodeId>> {
+        Ok(self
+            .current_snapshot
+            .lock()
+            .unwrap()
+            .clone()
+            .map(Snapshot::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openraft::{CommittedLeaderId, RaftSnapshotBuilder};
+
+    use super::*;
+    use crate::storage::{StorageEngine, VersionedValue};
+
+    fn entry(index: u64, request: Request) -> LogEntry {
+        LogEntry {
+            log_id: LogId::new(CommittedLeaderId::new(1, 1), index),
+            payload: EntryPayload::Normal(request),
+        }
+    }
+
+    fn set(key: &str, value: &str, now: u64) -> Request {
+        Request {
+            command: Command::Set {
+                key: key.to_string(),
+                entry: serde_json::json!(value).into(),
+                condition: None,
+            },
+            now,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_and_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = Arc::new(InMemoryStorage::default());
+        let mut state_machine =
+            StateMachine::open(Some(dir.path()), memory.clone(), Arc::default())
+                .await
+                .unwrap();
+        let results = state_machine
+            .apply(vec![
+                entry(1, set("a", "a", 50)),
+                entry(2, set("b", "b", 10)),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        // The second command was stamped earlier, it's applied at the time of the first.
+        assert_eq!(state_machine.applied_now.load(Ordering::Acquire), 50);
+        let mut builder = state_machine.get_snapshot_builder().await;
+        builder.build_snapshot().await.unwrap();
+
+        let restored = Arc::new(InMemoryStorage::default());
+        let mut reopened = StateMachine::open(Some(dir.path()), restored.clone(), Arc::default())
+            .await
+            .unwrap();
+        let (last_applied, _) = reopened.applied_state().await.unwrap();
+        assert_eq!(
+            last_applied,
+            Some(LogId::new(CommittedLeaderId::new(1, 1), 2))
+        );
+        assert_eq!(reopened.applied_now.load(Ordering::Acquire), 50);
+        assert_eq!(restored.keyspace().await, memory.keyspace().await);
+        assert_eq!(
+            restored.get("b").await.unwrap(),
+            Some(VersionedValue {
+                value: serde_json::json!("b"),
+                version: 2,
+                expires_at: None
+            })
+        );
+    }
+}
@@ -1 +1,28 @@
+use tonic::Status;
+
+use crate::storage::{ClusterError, ReplicaError};
+
+pub mod admin_service;
+pub mod cluster_service;
 pub mod key_value_service;
+pub mod raft_service;
+pub mod replication_service;
+
+fn storage_error(err: anyhow::Error) -> Status {
+    if let Some(err) = err.downcast_ref::<ReplicaError>() {
+        return Status::unavailable(err.to_string());
+    }
+    if let Some(err) = err.downcast_ref::<ClusterError>() {
+        return match err {
+            ClusterError::Timeout => Status::deadline_exceeded(err.to_string()),
+            ClusterError::Membership(_) => Status::failed_precondition(err.to_string()),
+            _ => Status::unavailable(err.to_string()),
+        };
+    }
+    // Returned by the leader a request was forwarded to.
+    if let Some(status) = err.downcast_ref::<Status>() {
+        return status.clone();
+    }
+    tracing::error!("Storage error: {:?}", err);
+    Status::internal(err.to_string())
+}
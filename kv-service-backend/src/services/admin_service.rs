@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+
+use super::storage_error;
+use crate::{
+    key_value_service::{
+        admin_service_server::AdminService as AdminServiceTrait, SnapshotRequest, SnapshotResponse,
+    },
+    storage::StorageEngine,
+};
+
+#[derive(Debug)]
+pub struct AdminService<S: StorageEngine> {
+    storage: Arc<S>,
+}
+
+impl<S: StorageEngine> AdminService<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+#[tonic::async_trait]
+impl<S: StorageEngine> AdminServiceTrait for AdminService<S> {
+    async fn snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<SnapshotResponse>, Status> {
+        tracing::info!("Received snapshot request: {:?}", request.get_ref());
+        let Some(snapshot) = self.storage.snapshot().await.map_err(storage_error)? else {
+            return Err(Status::unimplemented(
+                "storage engine doesn't support snapshots",
+            ));
+        };
+        let response = SnapshotResponse {
+            sequence: snapshot.sequence,
+            keys: snapshot.keys as u64,
+        };
+        tracing::info!("Sending snapshot response: {:?}", response);
+        Ok(Response::new(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{FsyncPolicy, InMemoryStorage, PersistentStorage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        storage
+            .set("key".to_string(), serde_json::json!("value").into(), None)
+            .await
+            .unwrap();
+        let service = AdminService::new(Arc::new(storage));
+        let response = service
+            .snapshot(Request::new(SnapshotRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.sequence, 1);
+        assert_eq!(response.keys, 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_unsupported() {
+        let service = AdminService::new(Arc::new(InMemoryStorage::default()));
+        let status = service
+            .snapshot(Request::new(SnapshotRequest {}))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::Unimplemented);
+    }
+}
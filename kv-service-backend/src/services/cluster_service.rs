@@ -0,0 +1,103 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use openraft::{BasicNode, RaftMetrics};
+use tonic::{Request, Response, Status};
+
+use super::storage_error;
+use crate::{
+    key_value_service::{
+        cluster_service_server::ClusterService as ClusterServiceTrait, AddLearnerRequest,
+        ChangeMembershipRequest, ClusterMember, ClusterMembershipResponse, ClusterStatusRequest,
+        ClusterStatusResponse,
+    },
+    raft::NodeId,
+    storage::ClusterStorage,
+};
+
+pub struct ClusterService {
+    storage: Arc<ClusterStorage>,
+}
+
+impl ClusterService {
+    pub fn new(storage: Arc<ClusterStorage>) -> Self {
+        Self { storage }
+    }
+
+    fn metrics(&self) -> RaftMetrics<NodeId, BasicNode> {
+        self.storage.raft().metrics().borrow().clone()
+    }
+
+    fn membership_response(&self) -> Response<ClusterMembershipResponse> {
+        Response::new(ClusterMembershipResponse {
+            members: members(&self.metrics()),
+        })
+    }
+}
+
+fn members(metrics: &RaftMetrics<NodeId, BasicNode>) -> Vec<ClusterMember> {
+    let membership = metrics.membership_config.membership();
+    let voters: BTreeSet<NodeId> = membership.voter_ids().collect();
+    membership
+        .nodes()
+        .map(|(id, node)| ClusterMember {
+            node_id: *id,
+            address: node.addr.clone(),
+            voter: voters.contains(id),
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+impl ClusterServiceTrait for ClusterService {
+    async fn add_learner(
+        &self,
+        request: Request<AddLearnerRequest>,
+    ) -> Result<Response<ClusterMembershipResponse>, Status> {
+        tracing::info!("Received add learner request: {:?}", request.get_ref());
+        let AddLearnerRequest { node_id, address } = request.into_inner();
+        if address.is_empty() {
+            return Err(Status::invalid_argument("address must be set"));
+        }
+        self.storage
+            .add_learner(node_id, address)
+            .await
+            .map_err(storage_error)?;
+        Ok(self.membership_response())
+    }
+
+    async fn change_membership(
+        &self,
+        request: Request<ChangeMembershipRequest>,
+    ) -> Result<Response<ClusterMembershipResponse>, Status> {
+        tracing::info!(
+            "Received change membership request: {:?}",
+            request.get_ref()
+        );
+        let voters = request.into_inner().voters;
+        if voters.is_empty() {
+            return Err(Status::invalid_argument(
+                "a cluster needs at least one voter",
+            ));
+        }
+        self.storage
+            .change_membership(voters.into_iter().collect())
+            .await
+            .map_err(storage_error)?;
+        Ok(self.membership_response())
+    }
+
+    async fn status(
+        &self,
+        _request: Request<ClusterStatusRequest>,
+    ) -> Result<Response<ClusterStatusResponse>, Status> {
+        let metrics = self.metrics();
+        Ok(Response::new(ClusterStatusResponse {
+            node_id: metrics.id,
+            leader_id: metrics.current_leader,
+            term: metrics.current_term,
+            last_log_index: metrics.last_log_index,
+            last_applied_index: metrics.last_applied.map(|log_id| log_id.index),
+            members: members(&metrics),
+        }))
+    }
+}
@@ -1,68 +1,311 @@
-use serde_json::Value;
-use std::collections::HashMap;
-use tokio::sync::RwLock;
+use std::sync::Arc;
+
+use kv_service_proto::{prost_to_serde_json, serde_json_to_prost};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
+use super::storage_error;
 use crate::{
     key_value_service::{
-        key_value_service_server::KeyValueService as KeyValueServiceTrait, DeleteResponse,
-        GetResponse, KeyRequest, KeyValueRequest, SetResponse,
+        condition::Check, key_value_service_server::KeyValueService as KeyValueServiceTrait,
+        operation::Kind, watch_event, BatchDeleteRequest, BatchDeleteResponse, BatchGetRequest,
+        BatchGetResponse, BatchSetRequest, BatchSetResponse, Condition, DeleteOperation,
+        DeleteResponse, GetResponse, JsonValue, KeyRequest, KeyValueRequest, Operation,
+        OperationResult, Precondition as PreconditionMessage, ScanRequest, ScanResponse,
+        SetOperation, SetResponse, TransactionRequest, TransactionResponse, WatchEvent,
+        WatchRequest,
+    },
+    storage::{
+        self, now_millis, BatchOperation, BatchResult, Entry, EventKind, KeyRange, Precondition,
+        RevisionCompacted, SetResult, StorageEngine, TransactionResult, VersionedValue,
     },
-    utils::{prost_to_serde_json, serde_json_to_prost},
 };
 
+const SCAN_CHUNK_SIZE: usize = 1000;
+const MAX_BATCH_SIZE: usize = 1000;
+const WATCH_CHANNEL_SIZE: usize = 128;
+
 #[derive(Debug)]
-pub struct KeyValueService {
-    storage: RwLock<HashMap<String, Value>>,
+pub struct KeyValueService<S: StorageEngine> {
+    storage: Arc<S>,
 }
 
-impl KeyValueService {
-    pub fn new(storage: HashMap<String, Value>) -> Self {
-        Self {
-            storage: RwLock::new(storage),
+impl<S: StorageEngine> KeyValueService<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+fn expires_at(
+    ttl_ms: Option<u64>,
+    expires_at_ms: Option<u64>,
+) -> Result<Option<u64>, &'static str> {
+    let now = now_millis();
+    match (ttl_ms, expires_at_ms) {
+        (Some(_), Some(_)) => Err("ttl_ms and expires_at_ms cannot both be set"),
+        (Some(0), None) => Err("ttl_ms must be greater than 0"),
+        (Some(ttl_ms), None) => Ok(Some(now.saturating_add(ttl_ms))),
+        (None, Some(expires_at_ms)) if expires_at_ms <= now => {
+            Err("expires_at_ms must be in the future")
         }
+        (None, expires_at_ms) => Ok(expires_at_ms),
+    }
+}
+
+fn entry(
+    value: Option<JsonValue>,
+    ttl_ms: Option<u64>,
+    expires_at_ms: Option<u64>,
+) -> Result<Entry, String> {
+    let Some(value) = value else {
+        return Err("value must be set".to_string());
+    };
+    let value = prost_to_serde_json(value).map_err(|err| format!("invalid value: {err}"))?;
+    if value.is_null() {
+        return Err("value cannot be null".to_string());
+    }
+    Ok(Entry::new(value, expires_at(ttl_ms, expires_at_ms)?))
+}
+
+fn condition(condition: Option<Condition>) -> Result<Option<storage::Condition>, String> {
+    let Some(condition) = condition else {
+        return Ok(None);
+    };
+    match condition.check {
+        Some(Check::Exists(exists)) => Ok(Some(storage::Condition::Exists(exists))),
+        Some(Check::Version(version)) => Ok(Some(storage::Condition::Version(version))),
+        Some(Check::Value(value)) => prost_to_serde_json(value)
+            .map(|value| Some(storage::Condition::Value(value)))
+            .map_err(|err| format!("invalid condition value: {err}")),
+        None => Err("condition must specify a check".to_string()),
+    }
+}
+
+fn precondition(precondition: PreconditionMessage) -> Result<Precondition, String> {
+    let condition =
+        condition(precondition.condition)?.ok_or("precondition must have a condition")?;
+    Ok(Precondition {
+        key: precondition.key,
+        condition,
+    })
+}
+
+fn operation(operation: Operation) -> Result<BatchOperation, String> {
+    match operation.kind {
+        Some(Kind::Set(SetOperation {
+            key,
+            value,
+            ttl_ms,
+            expires_at_ms,
+        })) => Ok(BatchOperation::Set {
+            key,
+            entry: entry(value, ttl_ms, expires_at_ms)?,
+        }),
+        Some(Kind::Delete(DeleteOperation { key })) => Ok(BatchOperation::Delete { key }),
+        None => Err("operation must be a set or a delete".to_string()),
+    }
+}
+
+fn check_batch_size(len: usize) -> Result<(), String> {
+    if len > MAX_BATCH_SIZE {
+        return Err(format!(
+            "batch cannot contain more than {MAX_BATCH_SIZE} items"
+        ));
+    }
+    Ok(())
+}
+
+fn get_response(value: Option<VersionedValue>) -> GetResponse {
+    match value {
+        Some(value) => GetResponse {
+            value: Some(serde_json_to_prost(value.value)),
+            version: value.version,
+            expires_at_ms: value.expires_at,
+        },
+        None => GetResponse::default(),
+    }
+}
+
+fn watch_event(event: storage::Event) -> WatchEvent {
+    let (event_type, value) = match event.kind {
+        EventKind::Put(entry) => (
+            watch_event::EventType::Put,
+            Some(serde_json_to_prost(entry.value)),
+        ),
+        EventKind::Delete => (watch_event::EventType::Delete, None),
+    };
+    WatchEvent {
+        r#type: event_type.into(),
+        key: event.key,
+        value,
+        revision: event.revision,
     }
 }
 
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<String> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
 #[tonic::async_trait]
-impl KeyValueServiceTrait for KeyValueService {
+impl<S: StorageEngine> KeyValueServiceTrait for KeyValueService<S> {
     async fn get(&self, request: Request<KeyRequest>) -> Result<Response<GetResponse>, Status> {
         tracing::info!("Received get request: {:?}", request.get_ref());
-        let value = {
-            tracing::info!("Reading from storage");
-            let storage = self.storage.read().await;
-            storage.get(request.into_inner().key.as_str()).cloned()
-        };
+        tracing::info!("Reading from storage");
+        let value = self
+            .storage
+            .get(request.into_inner().key.as_str())
+            .await
+            .map_err(storage_error)?;
         tracing::info!("Read from storage");
-        let response = GetResponse {
-            value: value.map(serde_json_to_prost),
-        };
+        let response = get_response(value);
         tracing::info!("Sending get response: {:?}", response);
         Ok(Response::new(response))
     }
 
+    async fn batch_get(
+        &self,
+        request: Request<BatchGetRequest>,
+    ) -> Result<Response<BatchGetResponse>, Status> {
+        let keys = request.into_inner().keys;
+        tracing::info!("Received batch get request for {} keys", keys.len());
+        check_batch_size(keys.len()).map_err(Status::invalid_argument)?;
+        let values = self.storage.get_many(&keys).await.map_err(storage_error)?;
+        let response = BatchGetResponse {
+            values: values.into_iter().map(get_response).collect(),
+        };
+        tracing::info!(
+            "Sending batch get response with {} values",
+            response.values.len()
+        );
+        Ok(Response::new(response))
+    }
+
+    async fn batch_set(
+        &self,
+        request: Request<BatchSetRequest>,
+    ) -> Result<Response<BatchSetResponse>, Status> {
+        let items = request.into_inner().items;
+        tracing::info!("Received batch set request for {} keys", items.len());
+        check_batch_size(items.len()).map_err(Status::invalid_argument)?;
+        let operations = items
+            .into_iter()
+            .map(|item| {
+                Ok(BatchOperation::Set {
+                    entry: entry(item.value, item.ttl_ms, item.expires_at_ms)?,
+                    key: item.key,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(Status::invalid_argument)?;
+        let results = self
+            .storage
+            .batch(operations)
+            .await
+            .map_err(storage_error)?;
+        let response = BatchSetResponse {
+            results: results
+                .into_iter()
+                .map(|result| match result {
+                    BatchResult::Set {
+                        previous_value,
+                        version,
+                    } => Ok(SetResponse {
+                        updated: previous_value.is_some(),
+                        version,
+                    }),
+                    BatchResult::Delete { .. } => Err("storage returned a delete for a set"),
+                })
+                .collect::<Result<_, _>>()
+                .map_err(Status::internal)?,
+        };
+        tracing::info!(
+            "Sending batch set response with {} results",
+            response.results.len()
+        );
+        Ok(Response::new(response))
+    }
+
+    async fn batch_delete(
+        &self,
+        request: Request<BatchDeleteRequest>,
+    ) -> Result<Response<BatchDeleteResponse>, Status> {
+        let keys = request.into_inner().keys;
+        tracing::info!("Received batch delete request for {} keys", keys.len());
+        check_batch_size(keys.len()).map_err(Status::invalid_argument)?;
+        let operations = keys
+            .into_iter()
+            .map(|key| BatchOperation::Delete { key })
+            .collect();
+        let results = self
+            .storage
+            .batch(operations)
+            .await
+            .map_err(storage_error)?;
+        let response = BatchDeleteResponse {
+            results: results
+                .into_iter()
+                .map(|result| match result {
+                    BatchResult::Delete { removed_value } => Ok(DeleteResponse {
+                        deleted: removed_value.is_some(),
+                    }),
+                    BatchResult::Set { .. } => Err("storage returned a set for a delete"),
+                })
+                .collect::<Result<_, _>>()
+                .map_err(Status::internal)?,
+        };
+        tracing::info!(
+            "Sending batch delete response with {} results",
+            response.results.len()
+        );
+        Ok(Response::new(response))
+    }
+
     async fn set(
         &self,
         request: Request<KeyValueRequest>,
     ) -> Result<Response<SetResponse>, Status> {
         tracing::info!("Received set request: {:?}", request.get_ref());
-        let KeyValueRequest { key, value } = request.into_inner();
-        let Some(value) = value else {
-            return Err(Status::invalid_argument("value must be set"));
-        };
-        let value = prost_to_serde_json(value);
-        if value.is_null() {
-            return Err(Status::invalid_argument("value cannot be null"));
-        }
-        let previous_value = {
-            tracing::info!("Writing to storage");
-            let mut storage = self.storage.write().await;
-            storage.insert(key, value)
-        };
+        let KeyValueRequest {
+            key,
+            value,
+            ttl_ms,
+            expires_at_ms,
+            condition: set_condition,
+        } = request.into_inner();
+        let entry = entry(value, ttl_ms, expires_at_ms).map_err(Status::invalid_argument)?;
+        let condition = condition(set_condition).map_err(Status::invalid_argument)?;
+        tracing::info!("Writing to storage");
+        let result = self
+            .storage
+            .set(key, entry, condition)
+            .await
+            .map_err(storage_error)?;
         tracing::info!("Wrote to storage");
-        let response = match previous_value {
-            Some(_) => SetResponse { updated: true },
-            None => SetResponse { updated: false },
+        let response = match result {
+            SetResult::Written { previous, version } => SetResponse {
+                updated: previous.is_some(),
+                version,
+            },
+            SetResult::ConditionFailed { current_version } => {
+                let message = match current_version {
+                    Some(version) => format!("condition failed, current version is {version}"),
+                    None => "condition failed, key doesn't exist".to_string(),
+                };
+                tracing::info!("Rejecting set request: {}", message);
+                return Err(Status::failed_precondition(message));
+            }
         };
         tracing::info!("Sending set response: {:?}", response);
         Ok(Response::new(response))
@@ -73,11 +316,12 @@ impl KeyValueServiceTrait for KeyValueService {
         request: Request<KeyRequest>,
     ) -> Result<Response<DeleteResponse>, Status> {
         tracing::info!("Received delete request: {:?}", request.get_ref());
-        let removed_value = {
-            tracing::info!("Deleting from storage");
-            let mut storage = self.storage.write().await;
-            storage.remove(request.into_inner().key.as_str())
-        };
+        tracing::info!("Deleting from storage");
+        let removed_value = self
+            .storage
+            .delete(request.into_inner().key.as_str())
+            .await
+            .map_err(storage_error)?;
         tracing::info!("Deleted from storage");
         let response = match removed_value {
             Some(_) => DeleteResponse { deleted: true },
@@ -86,17 +330,307 @@ impl KeyValueServiceTrait for KeyValueService {
         tracing::info!("Sending delete response: {:?}", response);
         Ok(Response::new(response))
     }
+
+    async fn transaction(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> Result<Response<TransactionResponse>, Status> {
+        tracing::info!("Received transaction request: {:?}", request.get_ref());
+        let TransactionRequest {
+            preconditions,
+            operations,
+        } = request.into_inner();
+        if operations.is_empty() {
+            return Err(Status::invalid_argument(
+                "transaction must contain at least one operation",
+            ));
+        }
+        check_batch_size(preconditions.len()).map_err(Status::invalid_argument)?;
+        check_batch_size(operations.len()).map_err(Status::invalid_argument)?;
+        let preconditions = preconditions
+            .into_iter()
+            .map(precondition)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+        let operations = operations
+            .into_iter()
+            .map(operation)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+        tracing::info!("Running transaction in storage");
+        let result = self
+            .storage
+            .transaction(preconditions, operations)
+            .await
+            .map_err(storage_error)?;
+        tracing::info!("Ran transaction in storage");
+        let response = match result {
+            TransactionResult::Committed { version, results } => TransactionResponse {
+                committed: true,
+                version,
+                results: results
+                    .into_iter()
+                    .map(|result| OperationResult {
+                        existed: match result {
+                            BatchResult::Set { previous_value, .. } => previous_value.is_some(),
+                            BatchResult::Delete { removed_value } => removed_value.is_some(),
+                        },
+                    })
+                    .collect(),
+                failed_precondition: None,
+            },
+            TransactionResult::Failed { precondition } => TransactionResponse {
+                failed_precondition: Some(precondition as u32),
+                ..Default::default()
+            },
+        };
+        tracing::info!("Sending transaction response: {:?}", response);
+        Ok(Response::new(response))
+    }
+
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        tracing::info!("Received watch request: {:?}", request.get_ref());
+        let WatchRequest {
+            key,
+            prefix,
+            start_revision,
+        } = request.into_inner();
+        let mut watcher = self.storage.watch(start_revision).await.map_err(|err| {
+            match err.downcast_ref::<RevisionCompacted>() {
+                Some(compacted) => Status::out_of_range(compacted.to_string()),
+                None => storage_error(err),
+            }
+        })?;
+
+        let (tx, rx) = mpsc::channel(WATCH_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    event = watcher.next() => event,
+                    _ = tx.closed() => break,
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        tracing::info!("Ending watch: {:?}", err);
+                        let _ = tx.send(Err(Status::aborted(err.to_string()))).await;
+                        break;
+                    }
+                };
+                let matches = if prefix {
+                    event.key.starts_with(&key)
+                } else {
+                    event.key == key
+                };
+                if matches && tx.send(Ok(watch_event(event))).await.is_err() {
+                    break;
+                }
+            }
+            tracing::info!("Watch stream closed");
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ScanStream = ReceiverStream<Result<ScanResponse, Status>>;
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        tracing::info!("Received scan request: {:?}", request.get_ref());
+        let ScanRequest {
+            prefix,
+            start,
+            end,
+            limit,
+            cursor,
+        } = request.into_inner();
+        let after = if cursor.is_empty() {
+            None
+        } else {
+            Some(decode_cursor(&cursor).ok_or_else(|| Status::invalid_argument("invalid cursor"))?)
+        };
+        let mut range = KeyRange {
+            prefix,
+            start,
+            end,
+            after,
+        };
+        let mut remaining = if limit == 0 {
+            usize::MAX
+        } else {
+            limit as usize
+        };
+
+        // Scanning in chunks avoids holding the storage lock while the client reads the stream.
+        let storage = self.storage.clone();
+        let (tx, rx) = mpsc::channel(SCAN_CHUNK_SIZE);
+        tokio::spawn(async move {
+            while remaining > 0 {
+                let chunk_size = remaining.min(SCAN_CHUNK_SIZE);
+                let entries = match storage.scan(&range, chunk_size).await {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        let _ = tx.send(Err(storage_error(err))).await;
+                        return;
+                    }
+                };
+                let last_chunk = entries.len() < chunk_size;
+                remaining -= entries.len();
+                for (key, value) in entries {
+                    let response = ScanResponse {
+                        cursor: encode_cursor(&key),
+                        value: Some(serde_json_to_prost(value)),
+                        key: key.clone(),
+                    };
+                    range.after = Some(key);
+                    if tx.send(Ok(response)).await.is_err() {
+                        tracing::info!("Scan stream closed by client");
+                        return;
+                    }
+                }
+                if last_chunk {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use crate::{
+        key_value_service::{json_value, JsonArray, JsonObject},
+        storage::InMemoryStorage,
+    };
+
     use super::*;
 
+    /// Any tree a gRPC client could send, including numbers that have no JSON representation.
+    fn json_message() -> impl Strategy<Value = JsonValue> {
+        let leaf = prop_oneof![
+            Just(None),
+            any::<i32>().prop_map(|n| Some(json_value::Kind::NullValue(n))),
+            any::<bool>().prop_map(|b| Some(json_value::Kind::BoolValue(b))),
+            any::<i64>().prop_map(|n| Some(json_value::Kind::IntValue(n))),
+            any::<u64>().prop_map(|n| Some(json_value::Kind::UintValue(n))),
+            prop_oneof![
+                any::<f64>(),
+                Just(f64::NAN),
+                Just(f64::INFINITY),
+                Just(f64::NEG_INFINITY)
+            ]
+            .prop_map(|n| Some(json_value::Kind::DoubleValue(n))),
+            prop_oneof![".*", "-?[0-9]{1,30}(\\.[0-9]{1,10})?"]
+                .prop_map(|n| Some(json_value::Kind::DecimalValue(n))),
+            ".*".prop_map(|s| Some(json_value::Kind::StringValue(s))),
+        ]
+        .prop_map(|kind| JsonValue { kind });
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8)
+                    .prop_map(|values| json_value::Kind::ArrayValue(JsonArray { values })),
+                prop::collection::hash_map(".*", inner, 0..8)
+                    .prop_map(|fields| json_value::Kind::ObjectValue(JsonObject { fields })),
+            ]
+            .prop_map(|kind| JsonValue { kind: Some(kind) })
+        })
+    }
+
+    fn has_non_finite_number(value: &JsonValue) -> bool {
+        match &value.kind {
+            Some(json_value::Kind::DoubleValue(n)) => !n.is_finite(),
+            Some(json_value::Kind::ArrayValue(array)) => {
+                array.values.iter().any(has_non_finite_number)
+            }
+            Some(json_value::Kind::ObjectValue(object)) => {
+                object.fields.values().any(has_non_finite_number)
+            }
+            _ => false,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_fuzz_set(value in json_message()) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+            let request = Request::new(KeyValueRequest {
+                key: "key".to_string(),
+                value: Some(value.clone()),
+                ..Default::default()
+            });
+            let result = runtime.block_on(service.set(request));
+            match prost_to_serde_json(value.clone()) {
+                Ok(expected) if !expected.is_null() => {
+                    prop_assert!(!has_non_finite_number(&value));
+                    prop_assert!(result.is_ok());
+                    let stored = runtime.block_on(service.storage.get("key")).unwrap().unwrap();
+                    prop_assert_eq!(stored.value, expected);
+                }
+                Ok(_) => {
+                    prop_assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+                }
+                Err(err) => {
+                    let status = result.unwrap_err();
+                    prop_assert_eq!(status.code(), tonic::Code::InvalidArgument);
+                    prop_assert!(status.message().contains(&err.path));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_set_non_finite_number() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let prices = JsonArray {
+            values: vec![
+                serde_json_to_prost(serde_json::json!(1.5)),
+                JsonValue {
+                    kind: Some(json_value::Kind::DoubleValue(f64::NAN)),
+                },
+            ],
+        };
+        let value = JsonValue {
+            kind: Some(json_value::Kind::ObjectValue(JsonObject {
+                fields: [(
+                    "prices".to_string(),
+                    JsonValue {
+                        kind: Some(json_value::Kind::ArrayValue(prices)),
+                    },
+                )]
+                .into(),
+            })),
+        };
+        let request = Request::new(KeyValueRequest {
+            key: "key".to_string(),
+            value: Some(value),
+            ..Default::default()
+        });
+        let status = service.set(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "invalid value: number is not finite at $.prices[1]"
+        );
+        assert_eq!(service.storage.get("key").await.unwrap(), None);
+    }
+
     #[tokio::test]
     async fn test_get() {
-        let mut storage = HashMap::new();
-        storage.insert("key".to_string(), serde_json::json!("value"));
-        let service = KeyValueService::new(storage);
+        let mut storage = BTreeMap::new();
+        storage.insert("key".to_string(), serde_json::json!("value").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
         let request = Request::new(KeyRequest {
             key: "key".to_string(),
         });
@@ -108,46 +642,527 @@ mod tests {
     }
 
     #[tokio::test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn test_set() {
-        let storage = HashMap::new();
-        let service = KeyValueService::new(storage);
+        let storage = BTreeMap::new();
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
         let request = Request::new(KeyValueRequest {
             key: "key".to_string(),
             value: Some(serde_json_to_prost(serde_json::json!("value"))),
+            ..Default::default()
         });
         let response = service.set(request).await.unwrap().into_inner();
         assert_eq!(response.updated, false);
         assert_eq!(
-            service.storage.read().await.get("key"),
-            Some(&serde_json::json!("value"))
+            service.storage.get("key").await.unwrap().unwrap().value,
+            serde_json::json!("value")
         );
     }
 
     #[tokio::test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn test_delete() {
-        let mut storage = HashMap::new();
-        storage.insert("key".to_string(), serde_json::json!("value"));
-        let service = KeyValueService::new(storage);
+        let mut storage = BTreeMap::new();
+        storage.insert("key".to_string(), serde_json::json!("value").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
         let request = Request::new(KeyRequest {
             key: "key".to_string(),
         });
         let response = service.delete(request).await.unwrap().into_inner();
         assert_eq!(response.deleted, true);
-        assert_eq!(service.storage.read().await.get("key"), None);
+        assert_eq!(service.storage.get("key").await.unwrap(), None);
     }
 
     #[tokio::test]
     async fn test_set_null() {
-        let storage = HashMap::new();
-        let service = KeyValueService::new(storage);
+        let storage = BTreeMap::new();
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
         let request = Request::new(KeyValueRequest {
             key: "key".to_string(),
-            value: Some(prost_types::Value {
-                kind: Some(prost_types::value::Kind::NullValue(0)),
-            }),
+            value: Some(serde_json_to_prost(serde_json::Value::Null)),
+            ..Default::default()
         });
         let status = service.set(request).await.err().unwrap();
         assert_eq!(status.code(), tonic::Code::InvalidArgument);
-        assert_eq!(service.storage.read().await.get("key"), None);
+        assert_eq!(service.storage.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_set_with_ttl() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let request = Request::new(KeyValueRequest {
+            key: "key".to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!("value"))),
+            ttl_ms: Some(60_000),
+            ..Default::default()
+        });
+        service.set(request).await.unwrap();
+        let request = Request::new(KeyRequest {
+            key: "key".to_string(),
+        });
+        let response = service.get(request).await.unwrap().into_inner();
+        assert_eq!(
+            response.value,
+            Some(serde_json_to_prost(serde_json::json!("value")))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_expired() {
+        let mut storage = BTreeMap::new();
+        storage.insert(
+            "key".to_string(),
+            Entry::new(serde_json::json!("value"), Some(now_millis() - 1)),
+        );
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
+        let request = Request::new(KeyRequest {
+            key: "key".to_string(),
+        });
+        let response = service.get(request).await.unwrap().into_inner();
+        assert_eq!(response.value, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_invalid_expiry() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        for (ttl_ms, expires_at_ms) in [
+            (Some(0), None),
+            (None, Some(1)),
+            (Some(1000), Some(now_millis() + 1000)),
+        ] {
+            let request = Request::new(KeyValueRequest {
+                key: "key".to_string(),
+                value: Some(serde_json_to_prost(serde_json::json!("value"))),
+                ttl_ms,
+                expires_at_ms,
+                ..Default::default()
+            });
+            let status = service.set(request).await.err().unwrap();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_versions() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let set = |value: &str| KeyValueRequest {
+            key: "key".to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!(value))),
+            ..Default::default()
+        };
+        assert_eq!(
+            service
+                .set(Request::new(set("a")))
+                .await
+                .unwrap()
+                .into_inner()
+                .version,
+            1
+        );
+        assert_eq!(
+            service
+                .set(Request::new(set("b")))
+                .await
+                .unwrap()
+                .into_inner()
+                .version,
+            2
+        );
+        let response = service
+            .get(Request::new(KeyRequest {
+                key: "key".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_set() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let set = |value: &str, check| KeyValueRequest {
+            key: "key".to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!(value))),
+            condition: Some(Condition { check }),
+            ..Default::default()
+        };
+
+        let response = service
+            .set(Request::new(set("a", Some(Check::Exists(false)))))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.version, 1);
+        let status = service
+            .set(Request::new(set("b", Some(Check::Exists(false)))))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = service
+            .set(Request::new(set("b", Some(Check::Version(2)))))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let response = service
+            .set(Request::new(set("b", Some(Check::Version(1)))))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.updated);
+        assert_eq!(response.version, 2);
+
+        let status = service
+            .set(Request::new(set("c", None)))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let mut storage = BTreeMap::new();
+        storage.insert("a".to_string(), serde_json::json!("a").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
+        let request = |value: &str| TransactionRequest {
+            preconditions: vec![PreconditionMessage {
+                key: "a".to_string(),
+                condition: Some(Condition {
+                    check: Some(Check::Value(serde_json_to_prost(serde_json::json!(value)))),
+                }),
+            }],
+            operations: vec![
+                Operation {
+                    kind: Some(Kind::Set(SetOperation {
+                        key: "b".to_string(),
+                        value: Some(serde_json_to_prost(serde_json::json!("b"))),
+                        ..Default::default()
+                    })),
+                },
+                Operation {
+                    kind: Some(Kind::Delete(DeleteOperation {
+                        key: "a".to_string(),
+                    })),
+                },
+            ],
+        };
+
+        let response = service
+            .transaction(Request::new(request("x")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.committed);
+        assert_eq!(response.failed_precondition, Some(0));
+
+        let response = service
+            .transaction(Request::new(request("a")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.committed);
+        assert_eq!(response.version, 1);
+        assert_eq!(
+            response.results,
+            vec![
+                OperationResult { existed: false },
+                OperationResult { existed: true }
+            ]
+        );
+        assert_eq!(service.storage.get("a").await.unwrap(), None);
+        assert_eq!(service.storage.get("b").await.unwrap().unwrap().version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_invalid() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let set = |value| Operation {
+            kind: Some(Kind::Set(SetOperation {
+                key: "a".to_string(),
+                value,
+                ..Default::default()
+            })),
+        };
+        for request in [
+            TransactionRequest::default(),
+            TransactionRequest {
+                operations: vec![set(None)],
+                ..Default::default()
+            },
+            TransactionRequest {
+                operations: vec![Operation { kind: None }],
+                ..Default::default()
+            },
+            TransactionRequest {
+                preconditions: vec![PreconditionMessage {
+                    key: "a".to_string(),
+                    condition: None,
+                }],
+                operations: vec![set(Some(serde_json_to_prost(serde_json::json!(1))))],
+            },
+            TransactionRequest {
+                operations: vec![
+                    set(Some(serde_json_to_prost(serde_json::json!(1))));
+                    MAX_BATCH_SIZE + 1
+                ],
+                ..Default::default()
+            },
+            TransactionRequest {
+                preconditions: vec![
+                    PreconditionMessage {
+                        key: "b".to_string(),
+                        condition: None,
+                    };
+                    MAX_BATCH_SIZE + 1
+                ],
+                operations: vec![set(Some(serde_json_to_prost(serde_json::json!(1))))],
+            },
+        ] {
+            let status = service
+                .transaction(Request::new(request))
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        assert_eq!(service.storage.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_operations() {
+        let mut storage = BTreeMap::new();
+        storage.insert("a".to_string(), serde_json::json!("a").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
+        let set = |key: &str, value: &str| SetOperation {
+            key: key.to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!(value))),
+            ..Default::default()
+        };
+
+        let response = service
+            .batch_set(Request::new(BatchSetRequest {
+                items: vec![set("a", "a2"), set("b", "b")],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.results,
+            vec![
+                SetResponse {
+                    updated: true,
+                    version: 1
+                },
+                SetResponse {
+                    updated: false,
+                    version: 1
+                },
+            ]
+        );
+
+        let keys = ["b", "c", "a"].map(str::to_string).to_vec();
+        let response = service
+            .batch_get(Request::new(BatchGetRequest { keys: keys.clone() }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.values,
+            vec![
+                GetResponse {
+                    value: Some(serde_json_to_prost(serde_json::json!("b"))),
+                    version: 1,
+                    expires_at_ms: None
+                },
+                GetResponse::default(),
+                GetResponse {
+                    value: Some(serde_json_to_prost(serde_json::json!("a2"))),
+                    version: 1,
+                    expires_at_ms: None
+                },
+            ]
+        );
+
+        let response = service
+            .batch_delete(Request::new(BatchDeleteRequest { keys }))
+            .await
+            .unwrap()
+            .into_inner();
+        let deleted: Vec<_> = response.results.iter().map(|r| r.deleted).collect();
+        assert_eq!(deleted, vec![true, false, true]);
+        assert_eq!(service.storage.get("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_set_invalid() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let items = vec![
+            SetOperation {
+                key: "a".to_string(),
+                value: Some(serde_json_to_prost(serde_json::json!("a"))),
+                ..Default::default()
+            },
+            SetOperation {
+                key: "b".to_string(),
+                ..Default::default()
+            },
+        ];
+        let status = service
+            .batch_set(Request::new(BatchSetRequest { items }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(service.storage.get("a").await.unwrap(), None);
+
+        let keys = vec![String::new(); MAX_BATCH_SIZE + 1];
+        let status = service
+            .batch_get(Request::new(BatchGetRequest { keys }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        use tokio_stream::StreamExt;
+
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let set = |key: &str| KeyValueRequest {
+            key: key.to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!(key))),
+            ..Default::default()
+        };
+        service.set(Request::new(set("a/1"))).await.unwrap();
+        let mut stream = service
+            .watch(Request::new(WatchRequest {
+                key: "a/".to_string(),
+                prefix: true,
+                start_revision: Some(1),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        service.set(Request::new(set("b"))).await.unwrap();
+        service
+            .delete(Request::new(KeyRequest {
+                key: "a/1".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            WatchEvent {
+                r#type: watch_event::EventType::Put.into(),
+                key: "a/1".to_string(),
+                value: Some(serde_json_to_prost(serde_json::json!("a/1"))),
+                revision: 1,
+            }
+        );
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            WatchEvent {
+                r#type: watch_event::EventType::Delete.into(),
+                key: "a/1".to_string(),
+                value: None,
+                revision: 3,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_compacted() {
+        let mut storage = BTreeMap::new();
+        storage.insert("a".to_string(), serde_json::json!("a").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
+        let status = service
+            .watch(Request::new(WatchRequest {
+                key: "a".to_string(),
+                start_revision: Some(0),
+                ..Default::default()
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+    }
+
+    async fn scan_keys(
+        service: &KeyValueService<InMemoryStorage>,
+        request: ScanRequest,
+    ) -> Vec<ScanResponse> {
+        use tokio_stream::StreamExt;
+
+        let stream = service
+            .scan(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        stream.map(Result::unwrap).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let mut storage = BTreeMap::new();
+        for i in 0..(SCAN_CHUNK_SIZE + 10) {
+            storage.insert(format!("a/{i:05}"), serde_json::json!(i).into());
+        }
+        storage.insert("b".to_string(), serde_json::json!("b").into());
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::new(storage)));
+
+        let responses = scan_keys(
+            &service,
+            ScanRequest {
+                prefix: "a/".to_string(),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(responses.len(), SCAN_CHUNK_SIZE + 10);
+        assert_eq!(responses[0].key, "a/00000");
+        assert_eq!(
+            responses[0].value,
+            Some(serde_json_to_prost(serde_json::json!(0)))
+        );
+
+        let responses = scan_keys(
+            &service,
+            ScanRequest {
+                prefix: "a/".to_string(),
+                limit: 2,
+                cursor: responses[SCAN_CHUNK_SIZE].cursor.clone(),
+                ..Default::default()
+            },
+        )
+        .await;
+        let keys: Vec<_> = responses.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(keys, vec!["a/01001", "a/01002"]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_invalid_cursor() {
+        let service = KeyValueService::new(Arc::new(InMemoryStorage::default()));
+        let status = service
+            .scan(Request::new(ScanRequest {
+                cursor: "zz".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        for key in ["", "key", "ключ/with spaces?&="] {
+            assert_eq!(decode_cursor(&encode_cursor(key)).as_deref(), Some(key));
+        }
+        assert_eq!(decode_cursor("abc"), None);
     }
 }
@@ -0,0 +1,92 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+
+use super::storage_error;
+use crate::{
+    key_value_service::{
+        raft_service_server::RaftService as RaftServiceTrait, RaftMessage, ReadIndexRequest,
+        ReadIndexResponse,
+    },
+    storage::ClusterStorage,
+};
+
+/// Receives the Raft messages of the other nodes of the cluster and the requests they forward
+/// to the leader.
+pub struct RaftService {
+    storage: Arc<ClusterStorage>,
+}
+
+impl RaftService {
+    pub fn new(storage: Arc<ClusterStorage>) -> Self {
+        Self { storage }
+    }
+}
+
+fn decode<T: DeserializeOwned>(request: Request<RaftMessage>) -> serde_json::Result<T> {
+    serde_json::from_slice(&request.into_inner().data)
+}
+
+fn invalid_message(err: serde_json::Error) -> Status {
+    Status::invalid_argument(format!("invalid Raft message: {err}"))
+}
+
+fn encode<T: Serialize>(message: &T) -> Response<RaftMessage> {
+    let data = serde_json::to_vec(message).expect("Raft messages serialize to JSON");
+    Response::new(RaftMessage { data })
+}
+
+#[tonic::async_trait]
+impl RaftServiceTrait for RaftService {
+    async fn append_entries(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        let result = self
+            .storage
+            .raft()
+            .append_entries(decode(request).map_err(invalid_message)?)
+            .await;
+        Ok(encode(&result))
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        let result = self
+            .storage
+            .raft()
+            .install_snapshot(decode(request).map_err(invalid_message)?)
+            .await;
+        Ok(encode(&result))
+    }
+
+    async fn vote(&self, request: Request<RaftMessage>) -> Result<Response<RaftMessage>, Status> {
+        let result = self
+            .storage
+            .raft()
+            .vote(decode(request).map_err(invalid_message)?)
+            .await;
+        Ok(encode(&result))
+    }
+
+    async fn forward(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        let command = decode(request).map_err(invalid_message)?;
+        tracing::debug!("Received forwarded command: {:?}", command);
+        let result = self.storage.propose(command).await.map_err(storage_error)?;
+        Ok(encode(&result))
+    }
+
+    async fn read_index(
+        &self,
+        _request: Request<ReadIndexRequest>,
+    ) -> Result<Response<ReadIndexResponse>, Status> {
+        let index = self.storage.read_index().await.map_err(storage_error)?;
+        Ok(Response::new(ReadIndexResponse { index }))
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use kv_service_proto::{prost_to_serde_json, serde_json_to_prost, InvalidValue};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::storage_error;
+use crate::{
+    key_value_service::{
+        replication_message::Kind,
+        replication_service_server::ReplicationService as ReplicationServiceTrait,
+        ReplicateRequest, ReplicatedEntry, ReplicatedKey, ReplicatedWrite, ReplicationMessage,
+        SnapshotChunk,
+    },
+    storage::{Entry, Event, EventKind, KeyspaceSnapshot, Replication, StorageEngine},
+};
+
+const SNAPSHOT_CHUNK_SIZE: usize = 1000;
+const REPLICATION_CHANNEL_SIZE: usize = 128;
+
+#[derive(Debug)]
+pub struct ReplicationService<S: StorageEngine> {
+    storage: Arc<S>,
+}
+
+impl<S: StorageEngine> ReplicationService<S> {
+    pub fn new(storage: Arc<S>) -> Self {
+        Self { storage }
+    }
+}
+
+pub(crate) fn replicated_key(key: String, entry: Option<Entry>) -> ReplicatedKey {
+    ReplicatedKey {
+        key,
+        entry: entry.map(|entry| ReplicatedEntry {
+            value: Some(serde_json_to_prost(entry.value)),
+            expires_at_ms: entry.expires_at,
+            version: entry.version,
+        }),
+    }
+}
+
+/// Reverse of [`replicated_key`], returns `None` as the entry for deleted keys.
+pub(crate) fn replicated_change(
+    key: ReplicatedKey,
+) -> Result<(String, Option<Entry>), InvalidValue> {
+    let entry = match key.entry {
+        Some(entry) => Some(Entry {
+            value: entry
+                .value
+                .map(prost_to_serde_json)
+                .transpose()?
+                .unwrap_or_default(),
+            expires_at: entry.expires_at_ms,
+            version: entry.version,
+        }),
+        None => None,
+    };
+    Ok((key.key, entry))
+}
+
+fn snapshot_chunks(snapshot: KeyspaceSnapshot) -> Vec<SnapshotChunk> {
+    let mut entries = snapshot.entries.into_iter().peekable();
+    let mut chunks = Vec::new();
+    loop {
+        let chunk = entries
+            .by_ref()
+            .take(SNAPSHOT_CHUNK_SIZE)
+            .map(|(key, entry)| replicated_key(key, Some(entry)))
+            .collect();
+        let last = entries.peek().is_none();
+        chunks.push(SnapshotChunk {
+            revision: snapshot.revision,
+            entries: chunk,
+            last,
+        });
+        if last {
+            return chunks;
+        }
+    }
+}
+
+fn replicated_write(events: Vec<Event>) -> ReplicatedWrite {
+    let revision = events.first().map_or(0, |event| event.revision);
+    let changes = events
+        .into_iter()
+        .map(|event| match event.kind {
+            EventKind::Put(entry) => replicated_key(event.key, Some(entry)),
+            EventKind::Delete => replicated_key(event.key, None),
+        })
+        .collect();
+    ReplicatedWrite { revision, changes }
+}
+
+#[tonic::async_trait]
+impl<S: StorageEngine> ReplicationServiceTrait for ReplicationService<S> {
+    type ReplicateStream = ReceiverStream<Result<ReplicationMessage, Status>>;
+
+    async fn replicate(
+        &self,
+        request: Request<ReplicateRequest>,
+    ) -> Result<Response<Self::ReplicateStream>, Status> {
+        tracing::info!("Received replicate request: {:?}", request.get_ref());
+        let Replication {
+            snapshot,
+            mut watcher,
+        } = self
+            .storage
+            .replicate(request.into_inner().after_revision)
+            .await
+            .map_err(storage_error)?;
+
+        let (tx, rx) = mpsc::channel(REPLICATION_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            if let Some(snapshot) = snapshot {
+                tracing::info!(
+                    "Sending snapshot of {} keys at revision {}",
+                    snapshot.entries.len(),
+                    snapshot.revision
+                );
+                for chunk in snapshot_chunks(snapshot) {
+                    let message = ReplicationMessage {
+                        kind: Some(Kind::Snapshot(chunk)),
+                    };
+                    if tx.send(Ok(message)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            loop {
+                let events = tokio::select! {
+                    events = watcher.next_write() => events,
+                    _ = tx.closed() => break,
+                };
+                let message = match events {
+                    Ok(events) => ReplicationMessage {
+                        kind: Some(Kind::Write(replicated_write(events))),
+                    },
+                    Err(err) => {
+                        tracing::warn!("Ending replication: {:?}", err);
+                        let _ = tx.send(Err(Status::aborted(err.to_string()))).await;
+                        break;
+                    }
+                };
+                if tx.send(Ok(message)).await.is_err() {
+                    break;
+                }
+            }
+            tracing::info!("Replication stream closed");
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use crate::storage::InMemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_chunks() {
+        let chunks = snapshot_chunks(KeyspaceSnapshot {
+            revision: 3,
+            entries: Vec::new(),
+        });
+        assert_eq!(
+            chunks,
+            vec![SnapshotChunk {
+                revision: 3,
+                entries: Vec::new(),
+                last: true,
+            }]
+        );
+
+        let entries = (0..SNAPSHOT_CHUNK_SIZE + 1)
+            .map(|i| (i.to_string(), serde_json::json!(i.to_string()).into()))
+            .collect();
+        let chunks = snapshot_chunks(KeyspaceSnapshot {
+            revision: 3,
+            entries,
+        });
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].entries.len(), SNAPSHOT_CHUNK_SIZE);
+        assert!(!chunks[0].last);
+        assert_eq!(chunks[1].entries.len(), 1);
+        assert!(chunks[1].last);
+    }
+
+    #[test]
+    fn test_replicated_change() {
+        let entry = Entry {
+            value: serde_json::json!({"a": "b"}),
+            expires_at: Some(10),
+            version: 2,
+        };
+        assert_eq!(
+            replicated_change(replicated_key("key".to_string(), Some(entry.clone()))).unwrap(),
+            ("key".to_string(), Some(entry))
+        );
+        assert_eq!(
+            replicated_change(replicated_key("key".to_string(), None)).unwrap(),
+            ("key".to_string(), None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replicate() {
+        let storage = Arc::new(InMemoryStorage::default());
+        storage
+            .set("a".to_string(), serde_json::json!("a").into(), None)
+            .await
+            .unwrap();
+        let service = ReplicationService::new(storage.clone());
+        let mut stream = service
+            .replicate(Request::new(ReplicateRequest {
+                after_revision: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(Ok(ReplicationMessage {
+            kind: Some(Kind::Snapshot(chunk)),
+        })) = stream.next().await
+        else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(chunk.revision, 1);
+        assert_eq!(chunk.entries.len(), 1);
+        assert!(chunk.last);
+
+        storage.delete("a").await.unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            ReplicationMessage {
+                kind: Some(Kind::Write(ReplicatedWrite {
+                    revision: 2,
+                    changes: vec![replicated_key("a".to_string(), None)],
+                })),
+            }
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    ops::Bound,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub mod cluster;
+pub mod in_memory;
+pub mod persistent;
+pub mod replica;
+pub mod watch;
+
+pub use cluster::{ClusterError, ClusterStorage};
+pub use in_memory::InMemoryStorage;
+pub use persistent::{FsyncPolicy, PersistentStorage};
+pub use replica::{ReplicaError, ReplicaStorage};
+pub use watch::{Event, EventKind, RevisionCompacted, Watcher};
+
+const EXPIRY_REAPER_BATCH_SIZE: usize = 1000;
+
+/// Milliseconds since the Unix epoch, the unit used for expiry timestamps.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Revision of the write that stored this entry, assigned by the storage engine.
+    #[serde(default)]
+    pub version: u64,
+}
+
+impl Entry {
+    pub fn new(value: Value, expires_at: Option<u64>) -> Self {
+        Self {
+            value,
+            expires_at,
+            version: 0,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl From<Value> for Entry {
+    fn from(value: Value) -> Self {
+        Self::new(value, None)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VersionedValue {
+    pub value: Value,
+    pub version: u64,
+    /// Expiry time in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl From<Entry> for VersionedValue {
+    fn from(entry: Entry) -> Self {
+        Self {
+            value: entry.value,
+            version: entry.version,
+            expires_at: entry.expires_at,
+        }
+    }
+}
+
+/// Requirement on the current state of a key for a conditional write.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    Exists(bool),
+    Version(u64),
+    Value(Value),
+}
+
+impl Condition {
+    /// `current` is the live entry for the key, if any.
+    pub fn matches(&self, current: Option<&Entry>) -> bool {
+        match self {
+            Self::Exists(exists) => current.is_some() == *exists,
+            Self::Version(version) => current.is_some_and(|entry| entry.version == *version),
+            Self::Value(value) => current.is_some_and(|entry| entry.value == *value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Precondition {
+    pub key: String,
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SetResult {
+    Written {
+        previous: Option<VersionedValue>,
+        version: u64,
+    },
+    ConditionFailed {
+        current_version: Option<u64>,
+    },
+}
+
+/// Keys selected by a scan, in lexicographic order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyRange {
+    pub prefix: String,
+    /// Inclusive lower bound.
+    pub start: Option<String>,
+    /// Exclusive upper bound.
+    pub end: Option<String>,
+    /// Exclusive lower bound used to resume a previous scan.
+    pub after: Option<String>,
+}
+
+impl KeyRange {
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn lower_bound(&self) -> Bound<&str> {
+        let mut lower = self.prefix.as_str();
+        let mut inclusive = true;
+        if let Some(start) = self.start.as_deref() {
+            if start > lower {
+                lower = start;
+            }
+        }
+        if let Some(after) = self.after.as_deref() {
+            if after >= lower {
+                lower = after;
+                inclusive = false;
+            }
+        }
+        if inclusive {
+            Bound::Included(lower)
+        } else {
+            Bound::Excluded(lower)
+        }
+    }
+
+    pub fn upper_bound(&self) -> Bound<&str> {
+        match &self.end {
+            Some(end) => Bound::Excluded(end.as_str()),
+            None => Bound::Unbounded,
+        }
+    }
+
+    /// Returns false if no key can possibly fall in the range.
+    pub fn is_satisfiable(&self) -> bool {
+        match (self.lower_bound(), self.upper_bound()) {
+            (Bound::Included(lower), Bound::Excluded(upper)) => lower < upper,
+            (Bound::Excluded(lower), Bound::Excluded(upper)) => lower < upper,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BatchOperation {
+    Set { key: String, entry: Entry },
+    Delete { key: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BatchResult {
+    Set {
+        previous_value: Option<Value>,
+        version: u64,
+    },
+    Delete {
+        removed_value: Option<Value>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TransactionResult {
+    Committed {
+        version: u64,
+        results: Vec<BatchResult>,
+    },
+    /// Nothing was written because the precondition at this index didn't hold.
+    Failed { precondition: usize },
+}
+
+/// Every live entry of the keyspace after the write with revision `revision`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyspaceSnapshot {
+    pub revision: u64,
+    pub entries: Vec<(String, Entry)>,
+}
+
+/// Where a follower starts replicating from: a snapshot if it can't catch up from the
+/// retained writes, and a watcher for every write after that.
+#[derive(Debug)]
+pub struct Replication {
+    pub snapshot: Option<KeyspaceSnapshot>,
+    pub watcher: Watcher,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub sequence: u64,
+    pub keys: usize,
+}
+
+/// Backing store used by the gRPC `KeyValueService`.
+///
+/// Every write is assigned the next value of a revision counter shared by the whole keyspace,
+/// which becomes the version of the entries it stores. Expired entries must be treated as
+/// missing by every operation. `batch` and `transaction` must check their preconditions and
+/// apply all operations atomically with respect to other calls on the same engine.
+#[tonic::async_trait]
+pub trait StorageEngine: Send + Sync + 'static {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<VersionedValue>>;
+    /// Looks up several keys, returning one result per key in the same order.
+    async fn get_many(&self, keys: &[String]) -> anyhow::Result<Vec<Option<VersionedValue>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+    async fn set(
+        &self,
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+    ) -> anyhow::Result<SetResult>;
+    async fn delete(&self, key: &str) -> anyhow::Result<Option<Value>>;
+    /// Returns at most `limit` live entries in `range`, ordered by key.
+    async fn scan(&self, range: &KeyRange, limit: usize) -> anyhow::Result<Vec<(String, Value)>>;
+    async fn batch(&self, operations: Vec<BatchOperation>) -> anyhow::Result<Vec<BatchResult>>;
+    /// Applies `operations` as a single write if every precondition holds.
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+    ) -> anyhow::Result<TransactionResult>;
+
+    /// Subscribes to the put and delete events of later writes, or of all writes from
+    /// `from_revision` on. Fails with [`RevisionCompacted`] if those events are no longer
+    /// retained. Keys removed because they expired don't produce events.
+    async fn watch(&self, from_revision: Option<u64>) -> anyhow::Result<Watcher>;
+
+    /// Starts replicating to a follower that has applied every write up to `after_revision`,
+    /// or that has no data yet if it's `None`.
+    async fn replicate(&self, after_revision: Option<u64>) -> anyhow::Result<Replication>;
+
+    /// Removes at most `limit` entries that expired before `now` and returns how many were
+    /// removed.
+    async fn remove_expired(&self, now: u64, limit: usize) -> anyhow::Result<usize>;
+
+    /// Writes a point-in-time snapshot of the keyspace, or returns `None` if the engine has
+    /// nothing to snapshot to.
+    async fn snapshot(&self) -> anyhow::Result<Option<SnapshotInfo>> {
+        Ok(None)
+    }
+}
+
+pub fn spawn_periodic_snapshots<S: StorageEngine>(storage: &Arc<S>, period: Duration) {
+    let storage = Arc::downgrade(storage);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            let Some(storage) = storage.upgrade() else {
+                break;
+            };
+            match storage.snapshot().await {
+                Ok(Some(snapshot)) => tracing::info!(
+                    "Wrote snapshot {} with {} keys",
+                    snapshot.sequence,
+                    snapshot.keys
+                ),
+                Ok(None) => break,
+                Err(err) => tracing::error!("Failed to write snapshot: {:?}", err),
+            }
+        }
+    });
+}
+
+pub fn spawn_expiry_reaper<S: StorageEngine>(storage: &Arc<S>, period: Duration) {
+    let storage = Arc::downgrade(storage);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let Some(storage) = storage.upgrade() else {
+                break;
+            };
+            let now = now_millis();
+            // Reaping in small batches keeps each write lock acquisition short.
+            loop {
+                match storage.remove_expired(now, EXPIRY_REAPER_BATCH_SIZE).await {
+                    Ok(removed) => {
+                        if removed > 0 {
+                            tracing::debug!("Removed {} expired keys", removed);
+                        }
+                        if removed < EXPIRY_REAPER_BATCH_SIZE {
+                            break;
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to remove expired keys: {:?}", err);
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
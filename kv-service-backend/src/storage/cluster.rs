@@ -0,0 +1,380 @@
+use openraft::{
+    error::{CheckIsLeaderError, ClientWriteError, ForwardToLeader, RaftError},
+    metrics::WaitError,
+    BasicNode,
+};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tonic::transport::{Channel, ClientTlsConfig};
+
+use super::{
+    now_millis, BatchOperation, BatchResult, Condition, Entry, InMemoryStorage, KeyRange,
+    Precondition, Replication, SetResult, SnapshotInfo, StorageEngine, TransactionResult,
+    VersionedValue, Watcher,
+};
+use crate::{
+    grpc_endpoint,
+    key_value_service::{raft_service_client::RaftServiceClient, RaftMessage, ReadIndexRequest},
+    raft::{self, ClusterConfig, Command, CommandResult, NodeId, Raft, Request},
+};
+
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MEMBERSHIP_CHANGE_TIMEOUT: Duration = Duration::from_secs(30);
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClusterError {
+    /// The request has to be handled by the leader, which isn't known during an election or
+    /// without a majority.
+    NotLeader { leader: Option<String> },
+    /// The leader couldn't reach a majority to confirm it's still the leader.
+    NoQuorum,
+    /// The request didn't complete in time. A write may still be committed later.
+    Timeout,
+    /// Raft has been shut down on this node.
+    Stopped,
+    /// A membership change was rejected.
+    Membership(String),
+}
+
+impl fmt::Display for ClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotLeader {
+                leader: Some(leader),
+            } => write!(f, "this node isn't the leader, the leader is at {leader}"),
+            Self::NotLeader { leader: None } => write!(f, "the cluster has no leader"),
+            Self::NoQuorum => write!(f, "the leader can't reach a majority of the cluster"),
+            Self::Timeout => write!(f, "the cluster didn't respond in time"),
+            Self::Stopped => write!(f, "this node has been shut down"),
+            Self::Membership(message) => write!(f, "membership change rejected: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ClusterError {}
+
+fn not_leader(forward: ForwardToLeader<NodeId, BasicNode>) -> ClusterError {
+    ClusterError::NotLeader {
+        leader: forward.leader_node.map(|node| node.addr),
+    }
+}
+
+fn client_write_error(err: RaftError<NodeId, ClientWriteError<NodeId, BasicNode>>) -> ClusterError {
+    match err {
+        RaftError::APIError(ClientWriteError::ForwardToLeader(forward)) => not_leader(forward),
+        RaftError::APIError(ClientWriteError::ChangeMembershipError(err)) => {
+            ClusterError::Membership(err.to_string())
+        }
+        RaftError::Fatal(_) => ClusterError::Stopped,
+    }
+}
+
+fn check_is_leader_error(
+    err: RaftError<NodeId, CheckIsLeaderError<NodeId, BasicNode>>,
+) -> ClusterError {
+    match err {
+        RaftError::APIError(CheckIsLeaderError::ForwardToLeader(forward)) => not_leader(forward),
+        RaftError::APIError(CheckIsLeaderError::QuorumNotEnough(_)) => ClusterError::NoQuorum,
+        RaftError::Fatal(_) => ClusterError::Stopped,
+    }
+}
+
+fn wait_error(err: WaitError) -> ClusterError {
+    match err {
+        WaitError::Timeout(..) => ClusterError::Timeout,
+        WaitError::ShuttingDown => ClusterError::Stopped,
+    }
+}
+
+fn unexpected(result: CommandResult) -> anyhow::Error {
+    anyhow::anyhow!("unexpected command result {result:?}")
+}
+
+/// Keyspace replicated with Raft over a cluster of nodes.
+///
+/// Writes are acknowledged once they are committed on a majority, nodes that aren't the leader
+/// forward them to it. Reads are linearizable: a follower first asks the leader how far the
+/// committed log goes and waits until it has applied that much. Watches and replication to
+/// read-only followers are served from the local copy.
+pub struct ClusterStorage {
+    raft: Raft,
+    memory: Arc<InMemoryStorage>,
+    /// Time of the last applied command, see [`Request::now`].
+    applied_now: Arc<AtomicU64>,
+    peer_tls_config: Option<ClientTlsConfig>,
+    /// Channels to the leaders requests were forwarded to, by address.
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+impl ClusterStorage {
+    pub async fn start(config: ClusterConfig) -> anyhow::Result<Self> {
+        let memory = Arc::new(InMemoryStorage::default());
+        let applied_now = Arc::new(AtomicU64::new(0));
+        let raft = raft::start(&config, memory.clone(), applied_now.clone()).await?;
+        Ok(Self {
+            raft,
+            memory,
+            applied_now,
+            peer_tls_config: config.peer_tls_config,
+            channels: Mutex::default(),
+        })
+    }
+
+    pub fn raft(&self) -> &Raft {
+        &self.raft
+    }
+
+    /// Stops the Raft node, every later request fails with [`ClusterError::Stopped`].
+    pub async fn shutdown(&self) -> anyhow::Result<()> {
+        self.raft.shutdown().await?;
+        Ok(())
+    }
+
+    /// Adds a node that receives the log without voting, and waits until it has caught up.
+    pub async fn add_learner(&self, id: NodeId, address: String) -> anyhow::Result<()> {
+        let result = tokio::time::timeout(
+            MEMBERSHIP_CHANGE_TIMEOUT,
+            self.raft.add_learner(id, BasicNode::new(address), true),
+        )
+        .await
+        .map_err(|_| ClusterError::Timeout)?;
+        result.map_err(client_write_error)?;
+        Ok(())
+    }
+
+    /// Makes `voters` the voting members, removing every other node from the cluster.
+    pub async fn change_membership(&self, voters: BTreeSet<NodeId>) -> anyhow::Result<()> {
+        let result = tokio::time::timeout(
+            MEMBERSHIP_CHANGE_TIMEOUT,
+            self.raft.change_membership(voters, false),
+        )
+        .await
+        .map_err(|_| ClusterError::Timeout)?;
+        result.map_err(client_write_error)?;
+        Ok(())
+    }
+
+    /// Commits `command` if this node is the leader.
+    pub(crate) async fn propose(&self, command: Command) -> anyhow::Result<CommandResult> {
+        let request = Request {
+            command,
+            now: now_millis(),
+        };
+        let result = tokio::time::timeout(WRITE_TIMEOUT, self.raft.client_write(request))
+            .await
+            .map_err(|_| ClusterError::Timeout)?;
+        let response = result.map_err(client_write_error)?;
+        response
+            .data
+            .ok_or_else(|| anyhow::anyhow!("command {} had no result", response.log_id))
+    }
+
+    /// Returns the index of the log a read has to wait for, if this node is the leader.
+    pub(crate) async fn read_index(&self) -> anyhow::Result<Option<u64>> {
+        let result = tokio::time::timeout(READ_TIMEOUT, self.raft.get_read_log_id())
+            .await
+            .map_err(|_| ClusterError::Timeout)?;
+        let (read_log_id, _) = result.map_err(check_is_leader_error)?;
+        Ok(read_log_id.map(|log_id| log_id.index))
+    }
+
+    fn leader_client(&self, leader: &str) -> anyhow::Result<RaftServiceClient<Channel>> {
+        let mut channels = self.channels.lock().unwrap();
+        if let Some(channel) = channels.get(leader) {
+            return Ok(RaftServiceClient::new(channel.clone()));
+        }
+        let channel = grpc_endpoint(leader, self.peer_tls_config.clone())?.connect_lazy();
+        channels.insert(leader.to_string(), channel.clone());
+        Ok(RaftServiceClient::new(channel))
+    }
+
+    async fn write(&self, command: Command) -> anyhow::Result<CommandResult> {
+        let err = match self.propose(command.clone()).await {
+            Ok(result) => return Ok(result),
+            Err(err) => err,
+        };
+        let Some(ClusterError::NotLeader {
+            leader: Some(leader),
+        }) = err.downcast_ref::<ClusterError>()
+        else {
+            return Err(err);
+        };
+        let mut request = tonic::Request::new(RaftMessage {
+            data: serde_json::to_vec(&command)?,
+        });
+        request.set_timeout(WRITE_TIMEOUT);
+        let response = self.leader_client(leader)?.forward(request).await?;
+        Ok(serde_json::from_slice(&response.into_inner().data)?)
+    }
+
+    /// Waits until every write committed before the call has been applied locally.
+    async fn read_barrier(&self) -> anyhow::Result<()> {
+        let index = match self.read_index().await {
+            Ok(index) => index,
+            Err(err) => {
+                let Some(ClusterError::NotLeader {
+                    leader: Some(leader),
+                }) = err.downcast_ref::<ClusterError>()
+                else {
+                    return Err(err);
+                };
+                let mut request = tonic::Request::new(ReadIndexRequest {});
+                request.set_timeout(READ_TIMEOUT);
+                self.leader_client(leader)?
+                    .read_index(request)
+                    .await?
+                    .into_inner()
+                    .index
+            }
+        };
+        self.raft
+            .wait(Some(READ_TIMEOUT))
+            .applied_index_at_least(index, "linearizable read")
+            .await
+            .map_err(wait_error)?;
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl StorageEngine for ClusterStorage {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<VersionedValue>> {
+        self.read_barrier().await?;
+        self.memory.get(key).await
+    }
+
+    async fn get_many(&self, keys: &[String]) -> anyhow::Result<Vec<Option<VersionedValue>>> {
+        self.read_barrier().await?;
+        self.memory.get_many(keys).await
+    }
+
+    async fn set(
+        &self,
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+    ) -> anyhow::Result<SetResult> {
+        match self
+            .write(Command::Set {
+                key,
+                entry,
+                condition,
+            })
+            .await?
+        {
+            CommandResult::Set(result) => Ok(result),
+            result => Err(unexpected(result)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        match self
+            .write(Command::Delete {
+                key: key.to_string(),
+            })
+            .await?
+        {
+            CommandResult::Delete(removed) => Ok(removed),
+            result => Err(unexpected(result)),
+        }
+    }
+
+    async fn scan(&self, range: &KeyRange, limit: usize) -> anyhow::Result<Vec<(String, Value)>> {
+        self.read_barrier().await?;
+        self.memory.scan(range, limit).await
+    }
+
+    async fn batch(&self, operations: Vec<BatchOperation>) -> anyhow::Result<Vec<BatchResult>> {
+        match self.write(Command::Batch { operations }).await? {
+            CommandResult::Batch(results) => Ok(results),
+            result => Err(unexpected(result)),
+        }
+    }
+
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+    ) -> anyhow::Result<TransactionResult> {
+        match self
+            .write(Command::Transaction {
+                preconditions,
+                operations,
+            })
+            .await?
+        {
+            CommandResult::Transaction(result) => Ok(result),
+            result => Err(unexpected(result)),
+        }
+    }
+
+    async fn watch(&self, from_revision: Option<u64>) -> anyhow::Result<Watcher> {
+        self.memory.watch(from_revision).await
+    }
+
+    async fn replicate(&self, after_revision: Option<u64>) -> anyhow::Result<Replication> {
+        self.memory.replicate(after_revision).await
+    }
+
+    async fn remove_expired(&self, now: u64, limit: usize) -> anyhow::Result<usize> {
+        // Commands applied later never run at an earlier time than the last one, so removing
+        // what expired by then can't change their outcome.
+        let now = now.min(self.applied_now.load(Ordering::Acquire));
+        self.memory.remove_expired(now, limit).await
+    }
+
+    /// Snapshots every applied entry and purges them from the Raft log.
+    async fn snapshot(&self) -> anyhow::Result<Option<SnapshotInfo>> {
+        let applied = self.raft.metrics().borrow().last_applied;
+        self.raft
+            .trigger()
+            .snapshot()
+            .await
+            .map_err(|_| ClusterError::Stopped)?;
+        let metrics = self
+            .raft
+            .wait(Some(SNAPSHOT_TIMEOUT))
+            .metrics(
+                |metrics| {
+                    metrics.snapshot.map(|log_id| log_id.index)
+                        >= applied.map(|log_id| log_id.index)
+                },
+                "snapshot",
+            )
+            .await
+            .map_err(wait_error)?;
+        let Some(snapshot) = metrics.snapshot else {
+            return Ok(Some(SnapshotInfo {
+                sequence: 0,
+                keys: 0,
+            }));
+        };
+        self.raft
+            .trigger()
+            .purge_log(snapshot.index)
+            .await
+            .map_err(|_| ClusterError::Stopped)?;
+        self.raft
+            .wait(Some(SNAPSHOT_TIMEOUT))
+            .metrics(
+                |metrics| metrics.purged.map(|log_id| log_id.index) >= Some(snapshot.index),
+                "purge",
+            )
+            .await
+            .map_err(wait_error)?;
+        Ok(Some(SnapshotInfo {
+            sequence: snapshot.index,
+            keys: self.memory.len().await,
+        }))
+    }
+}
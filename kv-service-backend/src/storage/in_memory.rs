@@ -0,0 +1,1101 @@
+use serde_json::Value;
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, BTreeSet},
+    hash::BuildHasher,
+    sync::{Mutex, MutexGuard},
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::{
+    now_millis,
+    watch::{ChangeFeed, Event, EventKind, Watcher},
+    BatchOperation, BatchResult, Condition, Entry, KeyRange, KeyspaceSnapshot, Precondition,
+    Replication, SetResult, StorageEngine, TransactionResult, VersionedValue,
+};
+
+/// Number of separately locked stripes the keyspace is split into by default.
+pub const DEFAULT_STRIPES: usize = 16;
+
+type Stripe = BTreeMap<String, Entry>;
+
+/// Orders the writes to all stripes. A write takes the next revision, applies its changes and
+/// publishes its events while holding the sequencer, so watchers see writes in revision order.
+#[derive(Debug)]
+struct Sequencer {
+    revision: u64,
+    feed: ChangeFeed,
+}
+
+/// Keys are spread over stripes by hash, each behind its own lock, so that requests for keys
+/// in different stripes don't wait for each other. Only the short section of a write that
+/// assigns its revision is serialised across stripes. Multi-key operations lock all the
+/// stripes of their keys, in stripe order so that they can't deadlock, and stay atomic.
+#[derive(Debug)]
+pub struct InMemoryStorage {
+    stripes: Box<[RwLock<Stripe>]>,
+    hasher: RandomState,
+    sequencer: Mutex<Sequencer>,
+}
+
+/// The stripes of the keys of a write, locked in stripe order.
+struct WriteGuard<'a> {
+    storage: &'a InMemoryStorage,
+    stripes: BTreeMap<usize, RwLockWriteGuard<'a, Stripe>>,
+}
+
+impl<'a> WriteGuard<'a> {
+    fn stripe(&mut self, key: &str) -> &mut Stripe {
+        let index = self.storage.stripe_index(key);
+        self.stripes
+            .get_mut(&index)
+            .expect("the stripe of every key of a write is locked")
+    }
+
+    fn live_entry(&self, key: &str, now: u64) -> Option<&Entry> {
+        let index = self.storage.stripe_index(key);
+        self.stripes
+            .get(&index)
+            .expect("the stripe of every key of a write is locked")
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+    }
+
+    /// Returns the index of the first precondition that doesn't hold.
+    fn failed_precondition(&self, preconditions: &[Precondition], now: u64) -> Option<usize> {
+        preconditions.iter().position(|precondition| {
+            !precondition
+                .condition
+                .matches(self.live_entry(&precondition.key, now))
+        })
+    }
+
+    /// Starts a write under the next revision.
+    fn begin(&mut self, now: u64) -> Revision<'_, 'a> {
+        let mut sequencer = self.storage.sequencer();
+        sequencer.revision += 1;
+        Revision {
+            revision: sequencer.revision,
+            sequencer,
+            guard: self,
+            events: Vec::new(),
+            now,
+        }
+    }
+
+    /// Starts a write that was assigned `revision` by another storage.
+    fn begin_replicated(&mut self, revision: u64, now: u64) -> anyhow::Result<Revision<'_, 'a>> {
+        let mut sequencer = self.storage.sequencer();
+        anyhow::ensure!(
+            revision > sequencer.revision,
+            "revision {revision} was replicated out of order after revision {}",
+            sequencer.revision
+        );
+        sequencer.revision = revision;
+        Ok(Revision {
+            revision,
+            sequencer,
+            guard: self,
+            events: Vec::new(),
+            now,
+        })
+    }
+}
+
+/// A write in progress, whose events are published together by `publish`.
+struct Revision<'g, 'a> {
+    revision: u64,
+    sequencer: MutexGuard<'a, Sequencer>,
+    guard: &'g mut WriteGuard<'a>,
+    events: Vec<Event>,
+    now: u64,
+}
+
+impl Revision<'_, '_> {
+    /// Stores `entry` with the version of this revision and returns the live entry it
+    /// replaced.
+    fn insert(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        entry.version = self.revision;
+        self.events.push(Event {
+            key: key.clone(),
+            revision: self.revision,
+            kind: EventKind::Put(entry.clone()),
+        });
+        live_entry(self.guard.stripe(&key).insert(key, entry), self.now)
+    }
+
+    /// Removes `key` as part of this revision and returns its live entry.
+    fn remove(&mut self, key: String) -> Option<Entry> {
+        let removed = live_entry(self.guard.stripe(&key).remove(&key), self.now);
+        if removed.is_some() {
+            self.events.push(Event {
+                key,
+                revision: self.revision,
+                kind: EventKind::Delete,
+            });
+        }
+        removed
+    }
+
+    /// Applies all operations under this revision.
+    fn apply(&mut self, operations: Vec<BatchOperation>) -> Vec<BatchResult> {
+        let version = self.revision;
+        operations
+            .into_iter()
+            .map(|operation| match operation {
+                BatchOperation::Set { key, entry } => BatchResult::Set {
+                    previous_value: self.insert(key, entry).map(|entry| entry.value),
+                    version,
+                },
+                BatchOperation::Delete { key } => BatchResult::Delete {
+                    removed_value: self.remove(key).map(|entry| entry.value),
+                },
+            })
+            .collect()
+    }
+
+    /// Publishes the events of this write to watchers.
+    fn publish(mut self) {
+        let events = std::mem::take(&mut self.events);
+        self.sequencer.feed.publish(events);
+    }
+}
+
+fn operation_key(operation: &BatchOperation) -> &str {
+    match operation {
+        BatchOperation::Set { key, .. } | BatchOperation::Delete { key } => key,
+    }
+}
+
+impl Default for InMemoryStorage {
+    fn default() -> Self {
+        Self::new(BTreeMap::new())
+    }
+}
+
+impl InMemoryStorage {
+    pub fn new(data: BTreeMap<String, Entry>) -> Self {
+        let revision = data.values().map(|entry| entry.version).max().unwrap_or(0);
+        Self::with_revision(data, revision)
+    }
+
+    /// Creates a storage whose next write is assigned revision `revision + 1`.
+    pub(crate) fn with_revision(data: BTreeMap<String, Entry>, revision: u64) -> Self {
+        Self::build(data, revision, DEFAULT_STRIPES)
+    }
+
+    /// Creates an empty storage split into `stripes` stripes, at least one.
+    pub fn with_stripes(stripes: usize) -> Self {
+        Self::build(BTreeMap::new(), 0, stripes)
+    }
+
+    fn build(data: BTreeMap<String, Entry>, revision: u64, stripes: usize) -> Self {
+        let mut storage = Self {
+            stripes: (0..stripes.max(1)).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            sequencer: Mutex::new(Sequencer {
+                revision,
+                feed: ChangeFeed::new(revision),
+            }),
+        };
+        storage.fill(data);
+        storage
+    }
+
+    fn fill(&mut self, data: BTreeMap<String, Entry>) {
+        for (key, entry) in data {
+            let index = self.stripe_index(&key);
+            self.stripes[index].get_mut().insert(key, entry);
+        }
+    }
+
+    fn stripe_index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.stripes.len() as u64) as usize
+    }
+
+    fn sequencer(&self) -> MutexGuard<'_, Sequencer> {
+        self.sequencer.lock().expect("sequencer lock poisoned")
+    }
+
+    async fn lock<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> WriteGuard<'_> {
+        let indices: BTreeSet<usize> = keys.into_iter().map(|key| self.stripe_index(key)).collect();
+        let mut stripes = BTreeMap::new();
+        for index in indices {
+            stripes.insert(index, self.stripes[index].write().await);
+        }
+        WriteGuard {
+            storage: self,
+            stripes,
+        }
+    }
+
+    /// Read-locks every stripe, which gives a consistent view of the whole keyspace since
+    /// writes hold the locks of their stripes until they are published.
+    async fn read_all(&self) -> Vec<RwLockReadGuard<'_, Stripe>> {
+        let mut stripes = Vec::with_capacity(self.stripes.len());
+        for stripe in self.stripes.iter() {
+            stripes.push(stripe.read().await);
+        }
+        stripes
+    }
+
+    /// Replaces the whole keyspace with a snapshot taken at `revision`. Existing watchers are
+    /// disconnected since they would miss the changes in between.
+    pub(crate) async fn restore(&self, snapshot: KeyspaceSnapshot) {
+        let mut stripes = Vec::with_capacity(self.stripes.len());
+        for stripe in self.stripes.iter() {
+            let mut stripe = stripe.write().await;
+            stripe.clear();
+            stripes.push(stripe);
+        }
+        for (key, entry) in snapshot.entries {
+            stripes[self.stripe_index(&key)].insert(key, entry);
+        }
+        *self.sequencer() = Sequencer {
+            revision: snapshot.revision,
+            feed: ChangeFeed::new(snapshot.revision),
+        };
+    }
+
+    /// Applies a write that was assigned `revision` by another storage, where `None` entries
+    /// are deletes.
+    pub(crate) async fn apply_replicated(
+        &self,
+        revision: u64,
+        changes: Vec<(String, Option<Entry>)>,
+    ) -> anyhow::Result<()> {
+        let now = now_millis();
+        let mut guard = self.lock(changes.iter().map(|(key, _)| key.as_str())).await;
+        let mut write = guard.begin_replicated(revision, now)?;
+        for (key, entry) in changes {
+            match entry {
+                Some(entry) => {
+                    write.insert(key, entry);
+                }
+                None => {
+                    write.remove(key);
+                }
+            }
+        }
+        write.publish();
+        Ok(())
+    }
+
+    /// Returns every stored entry, including expired ones, and the revision of the last write.
+    pub(crate) async fn keyspace(&self) -> KeyspaceSnapshot {
+        let stripes = self.read_all().await;
+        let mut entries: Vec<(String, Entry)> = stripes
+            .iter()
+            .flat_map(|stripe| stripe.iter())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        KeyspaceSnapshot {
+            revision: self.sequencer().revision,
+            entries,
+        }
+    }
+
+    /// The `*_at` methods perform a write as if the current time was `now`, so that replicas
+    /// applying the same writes end up in the same state.
+    pub(crate) async fn set_at(
+        &self,
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+        now: u64,
+    ) -> SetResult {
+        let mut guard = self.lock([key.as_str()]).await;
+        if let Some(condition) = condition {
+            let current = guard.live_entry(&key, now);
+            if !condition.matches(current) {
+                return SetResult::ConditionFailed {
+                    current_version: current.map(|entry| entry.version),
+                };
+            }
+        }
+        let mut write = guard.begin(now);
+        let version = write.revision;
+        let previous = write.insert(key, entry).map(VersionedValue::from);
+        write.publish();
+        SetResult::Written { previous, version }
+    }
+
+    pub(crate) async fn delete_at(&self, key: &str, now: u64) -> Option<Value> {
+        let mut guard = self.lock([key]).await;
+        let mut write = guard.begin(now);
+        let removed = write.remove(key.to_string());
+        write.publish();
+        removed.map(|entry| entry.value)
+    }
+
+    pub(crate) async fn batch_at(
+        &self,
+        operations: Vec<BatchOperation>,
+        now: u64,
+    ) -> Vec<BatchResult> {
+        let mut guard = self.lock(operations.iter().map(operation_key)).await;
+        let mut write = guard.begin(now);
+        let results = write.apply(operations);
+        write.publish();
+        results
+    }
+
+    pub(crate) async fn transaction_at(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+        now: u64,
+    ) -> TransactionResult {
+        let keys = preconditions
+            .iter()
+            .map(|precondition| precondition.key.as_str())
+            .chain(operations.iter().map(operation_key));
+        let mut guard = self.lock(keys).await;
+        if let Some(precondition) = guard.failed_precondition(&preconditions, now) {
+            return TransactionResult::Failed { precondition };
+        }
+        let mut write = guard.begin(now);
+        let version = write.revision;
+        let results = write.apply(operations);
+        write.publish();
+        TransactionResult::Committed { version, results }
+    }
+
+    pub(crate) async fn entries(&self) -> BTreeMap<String, Entry> {
+        let stripes = self.read_all().await;
+        stripes
+            .iter()
+            .flat_map(|stripe| stripe.iter())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Number of stored entries, including expired ones that haven't been removed yet.
+    pub(crate) async fn len(&self) -> usize {
+        let stripes = self.read_all().await;
+        stripes.iter().map(|stripe| stripe.len()).sum()
+    }
+
+    pub(crate) async fn revision(&self) -> u64 {
+        self.sequencer().revision
+    }
+
+    pub(crate) async fn live_entry(&self, key: &str) -> Option<Entry> {
+        let stripe = self.stripes[self.stripe_index(key)].read().await;
+        live_entry(stripe.get(key).cloned(), now_millis())
+    }
+
+    pub(crate) async fn failed_precondition(
+        &self,
+        preconditions: &[Precondition],
+    ) -> Option<usize> {
+        let guard = self
+            .lock(
+                preconditions
+                    .iter()
+                    .map(|precondition| precondition.key.as_str()),
+            )
+            .await;
+        guard.failed_precondition(preconditions, now_millis())
+    }
+}
+
+fn live_entry(entry: Option<Entry>, now: u64) -> Option<Entry> {
+    entry.filter(|entry| !entry.is_expired(now))
+}
+
+#[tonic::async_trait]
+impl StorageEngine for InMemoryStorage {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<VersionedValue>> {
+        Ok(self.live_entry(key).await.map(VersionedValue::from))
+    }
+
+    async fn get_many(&self, keys: &[String]) -> anyhow::Result<Vec<Option<VersionedValue>>> {
+        let now = now_millis();
+        let indices: BTreeSet<usize> = keys.iter().map(|key| self.stripe_index(key)).collect();
+        let mut stripes = BTreeMap::new();
+        for index in indices {
+            stripes.insert(index, self.stripes[index].read().await);
+        }
+        let values = keys
+            .iter()
+            .map(|key| {
+                let stripe = &stripes[&self.stripe_index(key)];
+                live_entry(stripe.get(key).cloned(), now).map(VersionedValue::from)
+            })
+            .collect();
+        Ok(values)
+    }
+
+    async fn set(
+        &self,
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+    ) -> anyhow::Result<SetResult> {
+        Ok(self.set_at(key, entry, condition, now_millis()).await)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        Ok(self.delete_at(key, now_millis()).await)
+    }
+
+    async fn scan(&self, range: &KeyRange, limit: usize) -> anyhow::Result<Vec<(String, Value)>> {
+        if !range.is_satisfiable() {
+            return Ok(Vec::new());
+        }
+        let now = now_millis();
+        let stripes = self.read_all().await;
+        let mut ranges: Vec<_> = stripes
+            .iter()
+            .map(|stripe| {
+                stripe
+                    .range::<str, _>((range.lower_bound(), range.upper_bound()))
+                    .take_while(|(key, _)| key.starts_with(&range.prefix))
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .peekable()
+            })
+            .collect();
+        // Every stripe is in key order, so the next key is the smallest next key of any stripe.
+        let mut entries = Vec::new();
+        while entries.len() < limit {
+            let next = ranges
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, range)| range.peek().map(|(key, _)| (index, *key)))
+                .min_by(|(_, a), (_, b)| a.cmp(b))
+                .map(|(index, _)| index);
+            let Some((key, entry)) = next.and_then(|index| ranges[index].next()) else {
+                break;
+            };
+            entries.push((key.clone(), entry.value.clone()));
+        }
+        Ok(entries)
+    }
+
+    async fn batch(&self, operations: Vec<BatchOperation>) -> anyhow::Result<Vec<BatchResult>> {
+        Ok(self.batch_at(operations, now_millis()).await)
+    }
+
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+    ) -> anyhow::Result<TransactionResult> {
+        Ok(self
+            .transaction_at(preconditions, operations, now_millis())
+            .await)
+    }
+
+    async fn watch(&self, from_revision: Option<u64>) -> anyhow::Result<Watcher> {
+        Ok(self.sequencer().feed.subscribe(from_revision)?)
+    }
+
+    async fn replicate(&self, after_revision: Option<u64>) -> anyhow::Result<Replication> {
+        let stripes = self.read_all().await;
+        let sequencer = self.sequencer();
+        if let Some(after_revision) =
+            after_revision.filter(|revision| *revision <= sequencer.revision)
+        {
+            if let Ok(watcher) = sequencer.feed.subscribe(Some(after_revision + 1)) {
+                return Ok(Replication {
+                    snapshot: None,
+                    watcher,
+                });
+            }
+        }
+        let now = now_millis();
+        let mut entries: Vec<(String, Entry)> = stripes
+            .iter()
+            .flat_map(|stripe| stripe.iter())
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Replication {
+            snapshot: Some(KeyspaceSnapshot {
+                revision: sequencer.revision,
+                entries,
+            }),
+            watcher: sequencer.feed.subscribe(None)?,
+        })
+    }
+
+    async fn remove_expired(&self, now: u64, limit: usize) -> anyhow::Result<usize> {
+        let mut removed = 0;
+        for stripe in self.stripes.iter() {
+            if removed >= limit {
+                break;
+            }
+            let expired: Vec<String> = stripe
+                .read()
+                .await
+                .iter()
+                .filter(|(_, entry)| entry.is_expired(now))
+                .map(|(key, _)| key.clone())
+                .take(limit - removed)
+                .collect();
+            if expired.is_empty() {
+                continue;
+            }
+            let mut stripe = stripe.write().await;
+            for key in expired {
+                // The key may have been overwritten since we released the read lock.
+                if stripe.get(&key).is_some_and(|entry| entry.is_expired(now)) {
+                    stripe.remove(&key);
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versioned(value: Value, version: u64) -> Option<VersionedValue> {
+        Some(VersionedValue {
+            value,
+            version,
+            expires_at: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_set_and_get() {
+        let storage = InMemoryStorage::default();
+        let result = storage
+            .set("key".to_string(), serde_json::json!("value").into(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            SetResult::Written {
+                previous: None,
+                version: 1
+            }
+        );
+        assert_eq!(
+            storage.get("key").await.unwrap(),
+            versioned(serde_json::json!("value"), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_existing() {
+        let mut data = BTreeMap::new();
+        data.insert("key".to_string(), serde_json::json!("value").into());
+        let storage = InMemoryStorage::new(data);
+        let result = storage
+            .set("key".to_string(), serde_json::json!("value2").into(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            SetResult::Written {
+                previous: versioned(serde_json::json!("value"), 0),
+                version: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_versions_increase_across_keys() {
+        let storage = InMemoryStorage::default();
+        storage
+            .set("a".to_string(), serde_json::json!(1).into(), None)
+            .await
+            .unwrap();
+        storage.delete("missing").await.unwrap();
+        storage
+            .set("b".to_string(), serde_json::json!(2).into(), None)
+            .await
+            .unwrap();
+        assert_eq!(storage.get("a").await.unwrap().unwrap().version, 1);
+        assert_eq!(storage.get("b").await.unwrap().unwrap().version, 3);
+        assert_eq!(storage.revision().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_set() {
+        let storage = InMemoryStorage::default();
+        let set = |value: i32, condition| {
+            storage.set(
+                "key".to_string(),
+                serde_json::json!(value).into(),
+                Some(condition),
+            )
+        };
+        assert_eq!(
+            set(1, Condition::Version(1)).await.unwrap(),
+            SetResult::ConditionFailed {
+                current_version: None
+            }
+        );
+        assert_eq!(
+            set(1, Condition::Exists(false)).await.unwrap(),
+            SetResult::Written {
+                previous: None,
+                version: 1
+            }
+        );
+        assert_eq!(
+            set(2, Condition::Exists(false)).await.unwrap(),
+            SetResult::ConditionFailed {
+                current_version: Some(1)
+            }
+        );
+        assert_eq!(
+            set(2, Condition::Version(2)).await.unwrap(),
+            SetResult::ConditionFailed {
+                current_version: Some(1)
+            }
+        );
+        assert_eq!(
+            set(2, Condition::Version(1)).await.unwrap(),
+            SetResult::Written {
+                previous: versioned(serde_json::json!(1), 1),
+                version: 2
+            }
+        );
+        assert_eq!(
+            set(3, Condition::Exists(true)).await.unwrap(),
+            SetResult::Written {
+                previous: versioned(serde_json::json!(2), 2),
+                version: 3
+            }
+        );
+        // Failed conditions don't consume a revision.
+        assert_eq!(storage.revision().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_many() {
+        let mut data = BTreeMap::new();
+        data.insert("a".to_string(), serde_json::json!("a").into());
+        data.insert(
+            "expired".to_string(),
+            Entry::new(serde_json::json!(1), Some(now_millis() - 1)),
+        );
+        let storage = InMemoryStorage::new(data);
+        let keys = ["expired", "a", "missing", "a"].map(str::to_string);
+        assert_eq!(
+            storage.get_many(&keys).await.unwrap(),
+            vec![
+                None,
+                versioned(serde_json::json!("a"), 0),
+                None,
+                versioned(serde_json::json!("a"), 0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let mut data = BTreeMap::new();
+        data.insert("key".to_string(), serde_json::json!("value").into());
+        let storage = InMemoryStorage::new(data);
+        assert_eq!(
+            storage.delete("key").await.unwrap(),
+            Some(serde_json::json!("value"))
+        );
+        assert_eq!(storage.delete("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let mut data = BTreeMap::new();
+        data.insert("b/2".to_string(), serde_json::json!(2).into());
+        data.insert("a".to_string(), serde_json::json!(0).into());
+        data.insert("b/1".to_string(), serde_json::json!(1).into());
+        let storage = InMemoryStorage::new(data);
+        let entries = storage
+            .scan(&KeyRange::prefix("b/"), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("b/1".to_string(), serde_json::json!(1)),
+                ("b/2".to_string(), serde_json::json!(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_range() {
+        let mut data = BTreeMap::new();
+        for key in ["a", "b", "c", "d", "e"] {
+            data.insert(key.to_string(), serde_json::json!(key).into());
+        }
+        let storage = InMemoryStorage::new(data);
+        let keys = |entries: Vec<(String, Value)>| {
+            entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+
+        let range = KeyRange {
+            start: Some("b".to_string()),
+            end: Some("e".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            keys(storage.scan(&range, usize::MAX).await.unwrap()),
+            vec!["b", "c", "d"]
+        );
+        assert_eq!(keys(storage.scan(&range, 2).await.unwrap()), vec!["b", "c"]);
+
+        let range = KeyRange {
+            after: Some("c".to_string()),
+            ..range
+        };
+        assert_eq!(
+            keys(storage.scan(&range, usize::MAX).await.unwrap()),
+            vec!["d"]
+        );
+
+        let range = KeyRange {
+            start: Some("d".to_string()),
+            end: Some("b".to_string()),
+            ..Default::default()
+        };
+        assert!(storage.scan(&range, usize::MAX).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let mut data = BTreeMap::new();
+        data.insert("a".to_string(), serde_json::json!("a").into());
+        let storage = InMemoryStorage::new(data);
+        let results = storage
+            .batch(vec![
+                BatchOperation::Set {
+                    key: "a".to_string(),
+                    entry: serde_json::json!("b").into(),
+                },
+                BatchOperation::Delete {
+                    key: "c".to_string(),
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                BatchResult::Set {
+                    previous_value: Some(serde_json::json!("a")),
+                    version: 1
+                },
+                BatchResult::Delete {
+                    removed_value: None
+                },
+            ]
+        );
+        assert_eq!(
+            storage.get("a").await.unwrap(),
+            versioned(serde_json::json!("b"), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let mut data = BTreeMap::new();
+        data.insert("a".to_string(), serde_json::json!("a").into());
+        data.insert("b".to_string(), serde_json::json!("b").into());
+        let storage = InMemoryStorage::new(data);
+        let operations = vec![
+            BatchOperation::Set {
+                key: "a".to_string(),
+                entry: serde_json::json!("a2").into(),
+            },
+            BatchOperation::Delete {
+                key: "b".to_string(),
+            },
+        ];
+        let precondition = |key: &str, condition| Precondition {
+            key: key.to_string(),
+            condition,
+        };
+
+        let result = storage
+            .transaction(
+                vec![
+                    precondition("a", Condition::Value(serde_json::json!("a"))),
+                    precondition("c", Condition::Exists(true)),
+                ],
+                operations.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, TransactionResult::Failed { precondition: 1 });
+        assert_eq!(storage.revision().await, 0);
+        assert_eq!(
+            storage.get("b").await.unwrap(),
+            versioned(serde_json::json!("b"), 0)
+        );
+
+        let result = storage
+            .transaction(
+                vec![
+                    precondition("a", Condition::Value(serde_json::json!("a"))),
+                    precondition("b", Condition::Version(0)),
+                    precondition("c", Condition::Exists(false)),
+                ],
+                operations,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            TransactionResult::Committed {
+                version: 1,
+                results: vec![
+                    BatchResult::Set {
+                        previous_value: Some(serde_json::json!("a")),
+                        version: 1
+                    },
+                    BatchResult::Delete {
+                        removed_value: Some(serde_json::json!("b"))
+                    },
+                ]
+            }
+        );
+        assert_eq!(
+            storage.get("a").await.unwrap(),
+            versioned(serde_json::json!("a2"), 1)
+        );
+        assert_eq!(storage.get("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let storage = InMemoryStorage::default();
+        storage
+            .set("a".to_string(), serde_json::json!(1).into(), None)
+            .await
+            .unwrap();
+        let mut watcher = storage.watch(None).await.unwrap();
+        storage.delete("missing").await.unwrap();
+        storage
+            .batch(vec![
+                BatchOperation::Set {
+                    key: "b".to_string(),
+                    entry: serde_json::json!(2).into(),
+                },
+                BatchOperation::Delete {
+                    key: "a".to_string(),
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            watcher.next().await.unwrap(),
+            Event {
+                key: "b".to_string(),
+                revision: 3,
+                kind: EventKind::Put(Entry {
+                    version: 3,
+                    ..serde_json::json!(2).into()
+                }),
+            }
+        );
+        assert_eq!(
+            watcher.next().await.unwrap(),
+            Event {
+                key: "a".to_string(),
+                revision: 3,
+                kind: EventKind::Delete,
+            }
+        );
+
+        let mut watcher = storage.watch(Some(1)).await.unwrap();
+        assert_eq!(watcher.next().await.unwrap().revision, 1);
+        assert!(storage.watch(Some(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replicate() {
+        let storage = InMemoryStorage::default();
+        storage
+            .set("a".to_string(), serde_json::json!("a").into(), None)
+            .await
+            .unwrap();
+        storage
+            .set(
+                "expired".to_string(),
+                Entry::new(serde_json::json!(1), Some(now_millis() - 1)),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let mut replication = storage.replicate(None).await.unwrap();
+        let snapshot = replication.snapshot.unwrap();
+        assert_eq!(snapshot.revision, 2);
+        assert_eq!(
+            snapshot.entries,
+            vec![(
+                "a".to_string(),
+                Entry {
+                    version: 1,
+                    ..serde_json::json!("a").into()
+                }
+            )]
+        );
+        storage.delete("a").await.unwrap();
+        assert_eq!(replication.watcher.next().await.unwrap().revision, 3);
+
+        // A follower that is only missing retained writes doesn't need a snapshot.
+        let mut replication = storage.replicate(Some(1)).await.unwrap();
+        assert!(replication.snapshot.is_none());
+        assert_eq!(replication.watcher.next().await.unwrap().revision, 2);
+        // A follower that is ahead must have followed another leader.
+        let replication = storage.replicate(Some(4)).await.unwrap();
+        assert_eq!(replication.snapshot.unwrap().revision, 3);
+    }
+
+    #[tokio::test]
+    async fn test_apply_replicated() {
+        let storage = InMemoryStorage::default();
+        storage
+            .restore(KeyspaceSnapshot {
+                revision: 5,
+                entries: vec![(
+                    "a".to_string(),
+                    Entry {
+                        version: 3,
+                        ..serde_json::json!("a").into()
+                    },
+                )],
+            })
+            .await;
+        assert_eq!(
+            storage.get("a").await.unwrap(),
+            versioned(serde_json::json!("a"), 3)
+        );
+        let mut watcher = storage.watch(None).await.unwrap();
+
+        storage
+            .apply_replicated(
+                7,
+                vec![
+                    ("a".to_string(), None),
+                    ("b".to_string(), Some(serde_json::json!("b").into())),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(storage.get("a").await.unwrap(), None);
+        assert_eq!(
+            storage.get("b").await.unwrap(),
+            versioned(serde_json::json!("b"), 7)
+        );
+        assert_eq!(watcher.next_write().await.unwrap().len(), 2);
+        assert!(storage.apply_replicated(7, Vec::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_missing() {
+        let now = now_millis();
+        let mut data = BTreeMap::new();
+        data.insert(
+            "expired".to_string(),
+            Entry::new(serde_json::json!(1), Some(now - 1)),
+        );
+        data.insert(
+            "live".to_string(),
+            Entry::new(serde_json::json!(2), Some(now + 60_000)),
+        );
+        let storage = InMemoryStorage::new(data);
+        assert_eq!(storage.get("expired").await.unwrap(), None);
+        assert_eq!(
+            storage.get("live").await.unwrap(),
+            Some(VersionedValue {
+                value: serde_json::json!(2),
+                version: 0,
+                expires_at: Some(now + 60_000),
+            })
+        );
+        assert_eq!(
+            storage
+                .scan(&KeyRange::default(), usize::MAX)
+                .await
+                .unwrap(),
+            vec![("live".to_string(), serde_json::json!(2))]
+        );
+        let result = storage
+            .set(
+                "expired".to_string(),
+                serde_json::json!(3).into(),
+                Some(Condition::Exists(false)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            SetResult::Written {
+                previous: None,
+                version: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_expired() {
+        let now = now_millis();
+        let mut data = BTreeMap::new();
+        for i in 0..3 {
+            data.insert(
+                format!("expired{i}"),
+                Entry::new(serde_json::json!(i), Some(now - 1)),
+            );
+        }
+        data.insert("live".to_string(), serde_json::json!("live").into());
+        let storage = InMemoryStorage::new(data);
+        assert_eq!(storage.remove_expired(now, 2).await.unwrap(), 2);
+        assert_eq!(storage.remove_expired(now, 2).await.unwrap(), 1);
+        assert_eq!(storage.remove_expired(now, 2).await.unwrap(), 0);
+        assert_eq!(storage.entries().await.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writes_across_stripes() {
+        let storage = std::sync::Arc::new(InMemoryStorage::with_stripes(4));
+        let mut watcher = storage.watch(None).await.unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        let key = format!("{writer}/{i:02}");
+                        storage
+                            .set(key, serde_json::json!(i).into(), None)
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        assert_eq!(storage.revision().await, 400);
+        for revision in 1..=400 {
+            assert_eq!(watcher.next().await.unwrap().revision, revision);
+        }
+        let keys: Vec<String> = storage
+            .scan(&KeyRange::default(), usize::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 400);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        let page = storage.scan(&KeyRange::prefix("3/"), 10).await.unwrap();
+        assert_eq!(page.len(), 10);
+        assert_eq!(page[9], ("3/09".to_string(), serde_json::json!(9)));
+    }
+}
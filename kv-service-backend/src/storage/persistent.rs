@@ -0,0 +1,886 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::{
+    now_millis, BatchOperation, BatchResult, Condition, Entry, InMemoryStorage, KeyRange,
+    Precondition, Replication, SetResult, SnapshotInfo, StorageEngine, TransactionResult,
+    VersionedValue, Watcher,
+};
+
+const LOG_FILE_NAME: &str = "kv-service.log";
+const SNAPSHOT_FILE_PREFIX: &str = "snapshot-";
+const SNAPSHOT_FILE_EXTENSION: &str = ".json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    Always,
+    Every(Duration),
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => {
+                let Some(millis) = s.strip_suffix("ms") else {
+                    bail!("invalid fsync policy {s:?}, expected always, never or <N>ms");
+                };
+                let millis = millis
+                    .parse()
+                    .with_context(|| format!("invalid fsync interval {s:?}"))?;
+                if millis == 0 {
+                    bail!("fsync interval must be greater than 0");
+                }
+                Ok(Self::Every(Duration::from_millis(millis)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LogRecord {
+    Set {
+        key: String,
+        value: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+    Batch {
+        operations: Vec<LogRecord>,
+    },
+}
+
+impl From<BatchOperation> for LogRecord {
+    fn from(operation: BatchOperation) -> Self {
+        match operation {
+            BatchOperation::Set { key, entry } => Self::Set {
+                key,
+                value: entry.value,
+                expires_at: entry.expires_at,
+            },
+            BatchOperation::Delete { key } => Self::Delete { key },
+        }
+    }
+}
+
+impl LogRecord {
+    /// Applies the record written at revision `seq`.
+    fn apply(self, data: &mut BTreeMap<String, Entry>, seq: u64) {
+        match self {
+            Self::Set {
+                key,
+                value,
+                expires_at,
+            } => {
+                let mut entry = Entry::new(value, expires_at);
+                entry.version = seq;
+                data.insert(key, entry);
+            }
+            Self::Delete { key } => {
+                data.remove(&key);
+            }
+            Self::Batch { operations } => {
+                for operation in operations {
+                    operation.apply(data, seq);
+                }
+            }
+        }
+    }
+}
+
+/// The sequence number of a record is the revision the in-memory map assigns to the write.
+#[derive(Debug, Serialize, Deserialize)]
+struct LogEntry {
+    seq: u64,
+    record: LogRecord,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    sequence: u64,
+    data: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug)]
+struct AppendLog {
+    file: File,
+    fsync_policy: FsyncPolicy,
+    dirty: bool,
+    last_seq: u64,
+    /// Length of the file up to the end of the last record.
+    len: u64,
+    /// Set when a failed append couldn't be undone, the log then refuses further records.
+    failed: bool,
+}
+
+impl AppendLog {
+    async fn append(&mut self, record: LogRecord) -> anyhow::Result<()> {
+        if self.failed {
+            bail!("storage log is unusable after a write to it failed");
+        }
+        let entry = LogEntry {
+            seq: self.last_seq + 1,
+            record,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        if let Err(err) = self.write(&line).await {
+            self.discard_unfinished_write().await;
+            return Err(err);
+        }
+        self.len += line.len() as u64;
+        self.last_seq = entry.seq;
+        Ok(())
+    }
+
+    async fn write(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        self.file.write_all(line).await?;
+        self.file.flush().await?;
+        if self.fsync_policy == FsyncPolicy::Always {
+            self.file.sync_data().await?;
+        } else {
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Cuts off whatever a failed append wrote, so that the next record doesn't continue a
+    /// partial line, and so that a record the caller was told failed isn't replayed.
+    async fn discard_unfinished_write(&mut self) {
+        if let Err(err) = self.file.set_len(self.len).await {
+            tracing::error!(
+                "Failed to discard an unfinished record from the storage log: {:?}",
+                err
+            );
+            self.failed = true;
+        }
+    }
+
+    async fn sync(&mut self) -> anyhow::Result<()> {
+        if self.dirty {
+            self.file.sync_data().await?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    async fn truncate(&mut self) -> anyhow::Result<()> {
+        self.file.set_len(0).await?;
+        self.file.sync_all().await?;
+        self.dirty = false;
+        self.len = 0;
+        self.failed = false;
+        Ok(())
+    }
+}
+
+/// Storage engine that records every mutation in an append-only log before applying it
+/// to an in-memory map.
+///
+/// The log and snapshots of the keyspace live in a data directory. Opening the engine loads
+/// the newest snapshot and replays the part of the log written after it.
+#[derive(Debug)]
+pub struct PersistentStorage {
+    memory: InMemoryStorage,
+    log: Arc<Mutex<AppendLog>>,
+    dir: PathBuf,
+}
+
+impl PersistentStorage {
+    pub async fn open(dir: impl AsRef<Path>, fsync_policy: FsyncPolicy) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("couldn't create data directory {}", dir.display()))?;
+
+        let (snapshot_sequence, mut data) = match load_newest_snapshot(&dir).await? {
+            Some(snapshot) => (snapshot.sequence, snapshot.data),
+            None => (0, BTreeMap::new()),
+        };
+        let log_path = dir.join(LOG_FILE_NAME);
+        let last_seq = replay(&log_path, snapshot_sequence, &mut data).await?;
+        tracing::info!(
+            "Loaded {} keys from {} (snapshot {}, last record {})",
+            data.len(),
+            dir.display(),
+            snapshot_sequence,
+            last_seq
+        );
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .await
+            .with_context(|| format!("couldn't open storage log {}", log_path.display()))?;
+        let len = file.metadata().await?.len();
+        let log = Arc::new(Mutex::new(AppendLog {
+            file,
+            fsync_policy,
+            dirty: false,
+            last_seq,
+            len,
+            failed: false,
+        }));
+
+        if let FsyncPolicy::Every(interval) = fsync_policy {
+            tokio::spawn(sync_periodically(Arc::downgrade(&log), interval));
+        }
+
+        Ok(Self {
+            memory: InMemoryStorage::with_revision(data, last_seq),
+            log,
+            dir,
+        })
+    }
+}
+
+async fn sync_periodically(log: std::sync::Weak<Mutex<AppendLog>>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let Some(log) = log.upgrade() else {
+            break;
+        };
+        let result = log.lock().await.sync().await;
+        if let Err(err) = result {
+            tracing::error!("Failed to sync storage log: {:?}", err);
+        }
+    }
+}
+
+/// Applies the records of the log at `path` newer than `after_seq` and returns the sequence
+/// number of the last record.
+async fn replay(
+    path: &Path,
+    after_seq: u64,
+    data: &mut BTreeMap<String, Entry>,
+) -> anyhow::Result<u64> {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(after_seq),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("couldn't read storage log {}", path.display()))
+        }
+    };
+
+    let mut last_seq = after_seq;
+    let mut valid_len = 0;
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        let Some(entry) = line.strip_suffix(b"\n") else {
+            // A crash in the middle of an append leaves a partial last line behind.
+            tracing::warn!(
+                "Discarding incomplete record at the end of {}",
+                path.display()
+            );
+            break;
+        };
+        let entry: LogEntry = serde_json::from_slice(entry).with_context(|| {
+            format!(
+                "corrupted record at byte {} of {}",
+                valid_len,
+                path.display()
+            )
+        })?;
+        // Records already covered by the snapshot are left behind if we crash between
+        // writing a snapshot and truncating the log.
+        if entry.seq > last_seq {
+            entry.record.apply(data, entry.seq);
+            last_seq = entry.seq;
+        }
+        valid_len += line.len();
+    }
+
+    if valid_len < contents.len() {
+        let file = OpenOptions::new().write(true).open(path).await?;
+        file.set_len(valid_len as u64).await?;
+        file.sync_all().await?;
+    }
+
+    Ok(last_seq)
+}
+
+fn snapshot_sequence(file_name: &str) -> Option<u64> {
+    file_name
+        .strip_prefix(SNAPSHOT_FILE_PREFIX)?
+        .strip_suffix(SNAPSHOT_FILE_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+fn snapshot_path(dir: &Path, sequence: u64) -> PathBuf {
+    dir.join(format!(
+        "{SNAPSHOT_FILE_PREFIX}{sequence:020}{SNAPSHOT_FILE_EXTENSION}"
+    ))
+}
+
+async fn list_snapshots(dir: &Path) -> anyhow::Result<Vec<u64>> {
+    let mut sequences = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(sequence) = entry.file_name().to_str().and_then(snapshot_sequence) {
+            sequences.push(sequence);
+        }
+    }
+    sequences.sort_unstable();
+    Ok(sequences)
+}
+
+async fn load_newest_snapshot(dir: &Path) -> anyhow::Result<Option<Snapshot>> {
+    let Some(sequence) = list_snapshots(dir).await?.pop() else {
+        return Ok(None);
+    };
+    let path = snapshot_path(dir, sequence);
+    let contents = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("couldn't read snapshot {}", path.display()))?;
+    let snapshot = serde_json::from_slice(&contents)
+        .with_context(|| format!("corrupted snapshot {}", path.display()))?;
+    Ok(Some(snapshot))
+}
+
+async fn write_snapshot(dir: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let path = snapshot_path(dir, snapshot.sequence);
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(&serde_json::to_vec(snapshot)?).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
+
+async fn remove_snapshots_before(dir: &Path, sequence: u64) -> anyhow::Result<()> {
+    for old_sequence in list_snapshots(dir).await? {
+        if old_sequence < sequence {
+            tokio::fs::remove_file(snapshot_path(dir, old_sequence)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl StorageEngine for PersistentStorage {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<VersionedValue>> {
+        self.memory.get(key).await
+    }
+
+    async fn get_many(&self, keys: &[String]) -> anyhow::Result<Vec<Option<VersionedValue>>> {
+        self.memory.get_many(keys).await
+    }
+
+    async fn set(
+        &self,
+        key: String,
+        entry: Entry,
+        condition: Option<Condition>,
+    ) -> anyhow::Result<SetResult> {
+        // Every write takes the log lock, so the condition can't be invalidated before the
+        // record is applied.
+        let mut log = self.log.lock().await;
+        if let Some(condition) = condition {
+            let current = self.memory.live_entry(&key).await;
+            if !condition.matches(current.as_ref()) {
+                return Ok(SetResult::ConditionFailed {
+                    current_version: current.map(|entry| entry.version),
+                });
+            }
+        }
+        log.append(LogRecord::Set {
+            key: key.clone(),
+            value: entry.value.clone(),
+            expires_at: entry.expires_at,
+        })
+        .await?;
+        self.memory.set(key, entry, None).await
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        let mut log = self.log.lock().await;
+        log.append(LogRecord::Delete {
+            key: key.to_string(),
+        })
+        .await?;
+        self.memory.delete(key).await
+    }
+
+    async fn scan(&self, range: &KeyRange, limit: usize) -> anyhow::Result<Vec<(String, Value)>> {
+        self.memory.scan(range, limit).await
+    }
+
+    async fn batch(&self, operations: Vec<BatchOperation>) -> anyhow::Result<Vec<BatchResult>> {
+        let mut log = self.log.lock().await;
+        log.append(LogRecord::Batch {
+            operations: operations.iter().cloned().map(LogRecord::from).collect(),
+        })
+        .await?;
+        self.memory.batch(operations).await
+    }
+
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<BatchOperation>,
+    ) -> anyhow::Result<TransactionResult> {
+        let mut log = self.log.lock().await;
+        if let Some(precondition) = self.memory.failed_precondition(&preconditions).await {
+            return Ok(TransactionResult::Failed { precondition });
+        }
+        // A committed transaction replays exactly like a batch.
+        log.append(LogRecord::Batch {
+            operations: operations.iter().cloned().map(LogRecord::from).collect(),
+        })
+        .await?;
+        let results = self.memory.batch(operations).await?;
+        Ok(TransactionResult::Committed {
+            version: log.last_seq,
+            results,
+        })
+    }
+
+    async fn watch(&self, from_revision: Option<u64>) -> anyhow::Result<Watcher> {
+        self.memory.watch(from_revision).await
+    }
+
+    async fn replicate(&self, after_revision: Option<u64>) -> anyhow::Result<Replication> {
+        self.memory.replicate(after_revision).await
+    }
+
+    async fn remove_expired(&self, now: u64, limit: usize) -> anyhow::Result<usize> {
+        // Expiry times are absolute and part of the logged entry, so there is nothing to log.
+        self.memory.remove_expired(now, limit).await
+    }
+
+    async fn snapshot(&self) -> anyhow::Result<Option<SnapshotInfo>> {
+        // Holding the log lock keeps writers out until the log has been truncated.
+        let mut log = self.log.lock().await;
+        let now = now_millis();
+        debug_assert_eq!(self.memory.revision().await, log.last_seq);
+        let mut data = self.memory.entries().await;
+        data.retain(|_, entry| !entry.is_expired(now));
+        let snapshot = Snapshot {
+            sequence: log.last_seq,
+            data,
+        };
+        write_snapshot(&self.dir, &snapshot).await?;
+        log.truncate().await?;
+        remove_snapshots_before(&self.dir, snapshot.sequence).await?;
+        Ok(Some(SnapshotInfo {
+            sequence: snapshot.sequence,
+            keys: snapshot.data.len(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get_value(storage: &PersistentStorage, key: &str) -> Option<Value> {
+        storage.get(key).await.unwrap().map(|value| value.value)
+    }
+
+    #[test]
+    fn test_parse_fsync_policy() {
+        assert_eq!(
+            "always".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::Always
+        );
+        assert_eq!("never".parse::<FsyncPolicy>().unwrap(), FsyncPolicy::Never);
+        assert_eq!(
+            "100ms".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::Every(Duration::from_millis(100))
+        );
+        assert!("0ms".parse::<FsyncPolicy>().is_err());
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Always)
+                .await
+                .unwrap();
+            storage
+                .set("a".to_string(), serde_json::json!("a").into(), None)
+                .await
+                .unwrap();
+            storage
+                .set("b".to_string(), serde_json::json!("b").into(), None)
+                .await
+                .unwrap();
+            storage.delete("a").await.unwrap();
+            storage
+                .batch(vec![
+                    BatchOperation::Set {
+                        key: "c".to_string(),
+                        entry: serde_json::json!({"c": 1}).into(),
+                    },
+                    BatchOperation::Delete {
+                        key: "b".to_string(),
+                    },
+                ])
+                .await
+                .unwrap();
+        }
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Always)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, None);
+        assert_eq!(get_value(&storage, "b").await, None);
+        assert_eq!(
+            get_value(&storage, "c").await,
+            Some(serde_json::json!({"c": 1}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_discards_incomplete_record() {
+        let dir = tempfile::tempdir().unwrap();
+        tokio::fs::write(
+            dir.path().join(LOG_FILE_NAME),
+            concat!(
+                "{\"seq\":1,\"record\":{\"set\":{\"key\":\"a\",\"value\":1}}}\n",
+                "{\"seq\":2,\"record\":{\"set\":{\"key\":\"b\"",
+            ),
+        )
+        .await
+        .unwrap();
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, Some(serde_json::json!(1)));
+        assert_eq!(get_value(&storage, "b").await, None);
+
+        storage
+            .set("b".to_string(), serde_json::json!(2).into(), None)
+            .await
+            .unwrap();
+        drop(storage);
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "b").await, Some(serde_json::json!(2)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_append_leaves_no_torn_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        storage
+            .set("a".to_string(), serde_json::json!(1).into(), None)
+            .await
+            .unwrap();
+
+        // What a write that failed halfway, for example on a full disk, leaves behind.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(LOG_FILE_NAME))
+            .await
+            .unwrap();
+        file.write_all(b"{\"seq\":2,\"record\":{\"set\":{\"key\":\"b\"")
+            .await
+            .unwrap();
+        storage.log.lock().await.discard_unfinished_write().await;
+
+        storage
+            .set("c".to_string(), serde_json::json!(3).into(), None)
+            .await
+            .unwrap();
+        drop(storage);
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, Some(serde_json::json!(1)));
+        assert_eq!(get_value(&storage, "b").await, None);
+        assert_eq!(get_value(&storage, "c").await, Some(serde_json::json!(3)));
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_corrupted_record() {
+        let dir = tempfile::tempdir().unwrap();
+        tokio::fs::write(
+            dir.path().join(LOG_FILE_NAME),
+            "not json\n{\"seq\":1,\"record\":{\"delete\":{\"key\":\"a\"}}}\n",
+        )
+        .await
+        .unwrap();
+
+        assert!(PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_truncates_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        storage
+            .set("a".to_string(), serde_json::json!(1).into(), None)
+            .await
+            .unwrap();
+        storage
+            .set("b".to_string(), serde_json::json!(2).into(), None)
+            .await
+            .unwrap();
+        let first = storage.snapshot().await.unwrap().unwrap();
+        assert_eq!(
+            first,
+            SnapshotInfo {
+                sequence: 2,
+                keys: 2
+            }
+        );
+        let log_len = tokio::fs::metadata(dir.path().join(LOG_FILE_NAME))
+            .await
+            .unwrap()
+            .len();
+        assert_eq!(log_len, 0);
+
+        storage.delete("a").await.unwrap();
+        let second = storage.snapshot().await.unwrap().unwrap();
+        assert_eq!(
+            second,
+            SnapshotInfo {
+                sequence: 3,
+                keys: 1
+            }
+        );
+        assert_eq!(list_snapshots(dir.path()).await.unwrap(), vec![3]);
+
+        storage
+            .set("c".to_string(), serde_json::json!(3).into(), None)
+            .await
+            .unwrap();
+        drop(storage);
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, None);
+        assert_eq!(get_value(&storage, "b").await, Some(serde_json::json!(2)));
+        assert_eq!(get_value(&storage, "c").await, Some(serde_json::json!(3)));
+        storage
+            .set("d".to_string(), serde_json::json!(4).into(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.snapshot().await.unwrap().unwrap(),
+            SnapshotInfo {
+                sequence: 5,
+                keys: 3
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_skips_records_covered_by_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = BTreeMap::new();
+        data.insert("a".to_string(), serde_json::json!(2).into());
+        write_snapshot(dir.path(), &Snapshot { sequence: 2, data })
+            .await
+            .unwrap();
+        tokio::fs::write(
+            dir.path().join(LOG_FILE_NAME),
+            concat!(
+                "{\"seq\":1,\"record\":{\"set\":{\"key\":\"a\",\"value\":1}}}\n",
+                "{\"seq\":2,\"record\":{\"set\":{\"key\":\"a\",\"value\":2}}}\n",
+                "{\"seq\":3,\"record\":{\"set\":{\"key\":\"b\",\"value\":3}}}\n",
+            ),
+        )
+        .await
+        .unwrap();
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, Some(serde_json::json!(2)));
+        assert_eq!(get_value(&storage, "b").await, Some(serde_json::json!(3)));
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let expires_at = now_millis() + 60_000;
+        {
+            let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+                .await
+                .unwrap();
+            storage
+                .set(
+                    "a".to_string(),
+                    Entry::new(serde_json::json!(1), Some(expires_at)),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        let mut entry = Entry::new(serde_json::json!(1), Some(expires_at));
+        entry.version = 1;
+        assert_eq!(storage.memory.entries().await.get("a"), Some(&entry));
+    }
+
+    #[tokio::test]
+    async fn test_versions_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+                .await
+                .unwrap();
+            storage
+                .set("a".to_string(), serde_json::json!(1).into(), None)
+                .await
+                .unwrap();
+            storage
+                .set("b".to_string(), serde_json::json!(2).into(), None)
+                .await
+                .unwrap();
+            storage.snapshot().await.unwrap();
+            storage
+                .set("a".to_string(), serde_json::json!(3).into(), None)
+                .await
+                .unwrap();
+            storage.delete("b").await.unwrap();
+        }
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(storage.get("a").await.unwrap().unwrap().version, 3);
+        let result = storage
+            .set(
+                "a".to_string(),
+                serde_json::json!(4).into(),
+                Some(Condition::Version(3)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            SetResult::Written {
+                previous: Some(VersionedValue {
+                    value: serde_json::json!(3),
+                    version: 3,
+                    expires_at: None
+                }),
+                version: 5
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_condition_is_not_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        storage
+            .set("a".to_string(), serde_json::json!(1).into(), None)
+            .await
+            .unwrap();
+        let result = storage
+            .set(
+                "a".to_string(),
+                serde_json::json!(2).into(),
+                Some(Condition::Exists(false)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            SetResult::ConditionFailed {
+                current_version: Some(1)
+            }
+        );
+        assert_eq!(storage.log.lock().await.last_seq, 1);
+        assert_eq!(get_value(&storage, "a").await, Some(serde_json::json!(1)));
+    }
+
+    #[tokio::test]
+    async fn test_transaction_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+                .await
+                .unwrap();
+            storage
+                .set("a".to_string(), serde_json::json!(1).into(), None)
+                .await
+                .unwrap();
+            let operations = vec![
+                BatchOperation::Set {
+                    key: "b".to_string(),
+                    entry: serde_json::json!(2).into(),
+                },
+                BatchOperation::Delete {
+                    key: "a".to_string(),
+                },
+            ];
+            let result = storage
+                .transaction(
+                    vec![Precondition {
+                        key: "a".to_string(),
+                        condition: Condition::Version(2),
+                    }],
+                    operations.clone(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(result, TransactionResult::Failed { precondition: 0 });
+            let result = storage
+                .transaction(
+                    vec![Precondition {
+                        key: "a".to_string(),
+                        condition: Condition::Version(1),
+                    }],
+                    operations,
+                )
+                .await
+                .unwrap();
+            assert!(matches!(
+                result,
+                TransactionResult::Committed { version: 2, .. }
+            ));
+        }
+
+        let storage = PersistentStorage::open(dir.path(), FsyncPolicy::Never)
+            .await
+            .unwrap();
+        assert_eq!(get_value(&storage, "a").await, None);
+        assert_eq!(storage.get("b").await.unwrap().unwrap().version, 2);
+    }
+}
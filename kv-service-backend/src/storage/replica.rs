@@ -0,0 +1,203 @@
+use serde_json::Value;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    BatchOperation, BatchResult, Condition, Entry, InMemoryStorage, KeyRange, KeyspaceSnapshot,
+    Precondition, Replication, SetResult, StorageEngine, TransactionResult, VersionedValue,
+    Watcher,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicaError {
+    /// Writes have to be sent to the leader.
+    ReadOnly { leader: String },
+    /// The replica hasn't received a snapshot from the leader yet.
+    NotSynced { leader: String },
+}
+
+impl fmt::Display for ReplicaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly { leader } => write!(
+                f,
+                "this backend is a read-only follower, send writes to the leader at {leader}"
+            ),
+            Self::NotSynced { leader } => {
+                write!(
+                    f,
+                    "this follower hasn't synced with the leader at {leader} yet"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplicaError {}
+
+/// Read-only copy of a leader's keyspace, kept up to date by [`crate::follower`].
+///
+/// Entries keep the versions the leader assigned, so versions and watch revisions are the same
+/// on every replica. Expired keys are removed locally like on the leader.
+#[derive(Debug)]
+pub struct ReplicaStorage {
+    memory: InMemoryStorage,
+    leader: String,
+    synced: AtomicBool,
+}
+
+impl ReplicaStorage {
+    pub fn new(leader: impl Into<String>) -> Self {
+        Self {
+            memory: InMemoryStorage::default(),
+            leader: leader.into(),
+            synced: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true once a snapshot of the leader's keyspace has been loaded.
+    pub fn is_synced(&self) -> bool {
+        self.synced.load(Ordering::Acquire)
+    }
+
+    pub(crate) async fn revision(&self) -> u64 {
+        self.memory.revision().await
+    }
+
+    pub(crate) async fn restore(&self, snapshot: KeyspaceSnapshot) {
+        self.memory.restore(snapshot).await;
+        self.synced.store(true, Ordering::Release);
+    }
+
+    pub(crate) async fn apply(
+        &self,
+        revision: u64,
+        changes: Vec<(String, Option<Entry>)>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(self.is_synced(), "received a write before the snapshot");
+        self.memory.apply_replicated(revision, changes).await
+    }
+
+    fn check_synced(&self) -> anyhow::Result<()> {
+        if !self.is_synced() {
+            return Err(ReplicaError::NotSynced {
+                leader: self.leader.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn read_only(&self) -> anyhow::Error {
+        ReplicaError::ReadOnly {
+            leader: self.leader.clone(),
+        }
+        .into()
+    }
+}
+
+#[tonic::async_trait]
+impl StorageEngine for ReplicaStorage {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<VersionedValue>> {
+        self.check_synced()?;
+        self.memory.get(key).await
+    }
+
+    async fn get_many(&self, keys: &[String]) -> anyhow::Result<Vec<Option<VersionedValue>>> {
+        self.check_synced()?;
+        self.memory.get_many(keys).await
+    }
+
+    async fn set(
+        &self,
+        _key: String,
+        _entry: Entry,
+        _condition: Option<Condition>,
+    ) -> anyhow::Result<SetResult> {
+        Err(self.read_only())
+    }
+
+    async fn delete(&self, _key: &str) -> anyhow::Result<Option<Value>> {
+        Err(self.read_only())
+    }
+
+    async fn scan(&self, range: &KeyRange, limit: usize) -> anyhow::Result<Vec<(String, Value)>> {
+        self.check_synced()?;
+        self.memory.scan(range, limit).await
+    }
+
+    async fn batch(&self, _operations: Vec<BatchOperation>) -> anyhow::Result<Vec<BatchResult>> {
+        Err(self.read_only())
+    }
+
+    async fn transaction(
+        &self,
+        _preconditions: Vec<Precondition>,
+        _operations: Vec<BatchOperation>,
+    ) -> anyhow::Result<TransactionResult> {
+        Err(self.read_only())
+    }
+
+    async fn watch(&self, from_revision: Option<u64>) -> anyhow::Result<Watcher> {
+        self.check_synced()?;
+        self.memory.watch(from_revision).await
+    }
+
+    async fn replicate(&self, after_revision: Option<u64>) -> anyhow::Result<Replication> {
+        self.check_synced()?;
+        self.memory.replicate(after_revision).await
+    }
+
+    async fn remove_expired(&self, now: u64, limit: usize) -> anyhow::Result<usize> {
+        self.memory.remove_expired(now, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replica() {
+        let replica = ReplicaStorage::new("leader:8081");
+        let err = replica.get("a").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReplicaError>(),
+            Some(&ReplicaError::NotSynced {
+                leader: "leader:8081".to_string()
+            })
+        );
+        assert!(replica.apply(1, Vec::new()).await.is_err());
+
+        replica.restore(KeyspaceSnapshot::default()).await;
+        replica
+            .apply(
+                1,
+                vec![("a".to_string(), Some(serde_json::json!("a").into()))],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            replica.get("a").await.unwrap(),
+            Some(VersionedValue {
+                value: serde_json::json!("a"),
+                version: 1,
+                expires_at: None
+            })
+        );
+
+        let err = replica
+            .set("a".to_string(), serde_json::json!("b").into(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReplicaError>(),
+            Some(&ReplicaError::ReadOnly {
+                leader: "leader:8081".to_string()
+            })
+        );
+        assert!(replica.delete("a").await.is_err());
+    }
+}
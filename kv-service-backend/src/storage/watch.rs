@@ -0,0 +1,247 @@
+use std::{collections::VecDeque, fmt, sync::Arc};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::Entry;
+
+const WATCH_CHANNEL_CAPACITY: usize = 1024;
+const WATCH_HISTORY_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Put(Entry),
+    Delete,
+}
+
+/// A change to a single key, made by the write at `revision`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub key: String,
+    pub revision: u64,
+    pub kind: EventKind,
+}
+
+/// Returned when a watch asks for events that are no longer retained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RevisionCompacted {
+    pub requested: u64,
+    pub oldest: u64,
+}
+
+impl fmt::Display for RevisionCompacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "revision {} has been compacted, the oldest available revision is {}",
+            self.requested, self.oldest
+        )
+    }
+}
+
+impl std::error::Error for RevisionCompacted {}
+
+/// Publishes the events of every write and keeps the most recent ones so that watchers can
+/// resume from an earlier revision.
+///
+/// Callers must serialize `publish` with `subscribe`, otherwise a watcher can miss or
+/// duplicate events.
+#[derive(Debug)]
+pub(crate) struct ChangeFeed {
+    /// Sends all events of a write at once, so a watcher never sees half of a write.
+    sender: broadcast::Sender<Arc<[Event]>>,
+    history: VecDeque<Event>,
+    /// Every event with a revision at or above this one is in `history`.
+    oldest_revision: u64,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl ChangeFeed {
+    /// Creates a feed for a keyspace whose last write had revision `revision`.
+    pub(crate) fn new(revision: u64) -> Self {
+        let (sender, _) = broadcast::channel(WATCH_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            history: VecDeque::new(),
+            oldest_revision: revision + 1,
+        }
+    }
+
+    /// Publishes the events of a single write, which all have the same revision.
+    pub(crate) fn publish(&mut self, events: Vec<Event>) {
+        if events.is_empty() {
+            return;
+        }
+        self.history.extend(events.iter().cloned());
+        // Nobody listening isn't an error, the events are still kept in the history.
+        let _ = self.sender.send(events.into());
+        while self.history.len() > WATCH_HISTORY_SIZE {
+            // Drop whole revisions so a replay never starts in the middle of a batch.
+            let dropped = self.history.pop_front().map_or(0, |event| event.revision);
+            while self
+                .history
+                .front()
+                .is_some_and(|event| event.revision == dropped)
+            {
+                self.history.pop_front();
+            }
+            self.oldest_revision = dropped + 1;
+        }
+    }
+
+    /// Subscribes to events with a revision at or above `from_revision`, or to future
+    /// events only if it's `None`.
+    pub(crate) fn subscribe(
+        &self,
+        from_revision: Option<u64>,
+    ) -> Result<Watcher, RevisionCompacted> {
+        let receiver = self.sender.subscribe();
+        let Some(from_revision) = from_revision else {
+            return Ok(Watcher {
+                backlog: VecDeque::new(),
+                receiver,
+                from_revision: 0,
+            });
+        };
+        if from_revision < self.oldest_revision {
+            return Err(RevisionCompacted {
+                requested: from_revision,
+                oldest: self.oldest_revision,
+            });
+        }
+        let backlog = self
+            .history
+            .iter()
+            .filter(|event| event.revision >= from_revision)
+            .cloned()
+            .collect();
+        Ok(Watcher {
+            backlog,
+            receiver,
+            from_revision,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Watcher {
+    /// Received events that haven't been returned yet, always ending with a whole write.
+    backlog: VecDeque<Event>,
+    receiver: broadcast::Receiver<Arc<[Event]>>,
+    from_revision: u64,
+}
+
+impl Watcher {
+    /// Waits for the next event. Fails if the watcher fell too far behind the writers or the
+    /// storage was dropped.
+    pub async fn next(&mut self) -> anyhow::Result<Event> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                return Ok(event);
+            }
+            match self.receiver.recv().await {
+                Ok(events) => self.backlog.extend(
+                    events
+                        .iter()
+                        .filter(|event| event.revision >= self.from_revision)
+                        .cloned(),
+                ),
+                Err(RecvError::Lagged(skipped)) => {
+                    anyhow::bail!("watcher fell behind and missed {skipped} writes")
+                }
+                Err(RecvError::Closed) => anyhow::bail!("storage was closed"),
+            }
+        }
+    }
+
+    /// Waits for the next write and returns all of its events.
+    pub async fn next_write(&mut self) -> anyhow::Result<Vec<Event>> {
+        let event = self.next().await?;
+        let revision = event.revision;
+        let mut events = vec![event];
+        while self
+            .backlog
+            .front()
+            .is_some_and(|event| event.revision == revision)
+        {
+            events.extend(self.backlog.pop_front());
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str, revision: u64) -> Event {
+        let mut entry = Entry::from(serde_json::json!(revision));
+        entry.version = revision;
+        Event {
+            key: key.to_string(),
+            revision,
+            kind: EventKind::Put(entry),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_follow() {
+        let mut feed = ChangeFeed::new(0);
+        feed.publish(vec![put("a", 1)]);
+        feed.publish(vec![put("b", 2)]);
+        let mut watcher = feed.subscribe(Some(2)).unwrap();
+        let mut future_watcher = feed.subscribe(None).unwrap();
+        feed.publish(vec![put("c", 3)]);
+        assert_eq!(watcher.next().await.unwrap(), put("b", 2));
+        assert_eq!(watcher.next().await.unwrap(), put("c", 3));
+        assert_eq!(future_watcher.next().await.unwrap(), put("c", 3));
+    }
+
+    #[tokio::test]
+    async fn test_future_start_revision() {
+        let mut feed = ChangeFeed::new(0);
+        let mut watcher = feed.subscribe(Some(3)).unwrap();
+        feed.publish(vec![put("a", 1)]);
+        feed.publish(vec![put("a", 2)]);
+        feed.publish(vec![put("a", 3)]);
+        assert_eq!(watcher.next().await.unwrap(), put("a", 3));
+    }
+
+    #[tokio::test]
+    async fn test_next_write() {
+        let mut feed = ChangeFeed::new(0);
+        feed.publish(vec![put("a", 1), put("b", 1)]);
+        let mut watcher = feed.subscribe(Some(1)).unwrap();
+        feed.publish(vec![put("c", 2), put("d", 2)]);
+        feed.publish(vec![put("e", 3)]);
+        assert_eq!(
+            watcher.next_write().await.unwrap(),
+            vec![put("a", 1), put("b", 1)]
+        );
+        assert_eq!(
+            watcher.next_write().await.unwrap(),
+            vec![put("c", 2), put("d", 2)]
+        );
+        assert_eq!(watcher.next_write().await.unwrap(), vec![put("e", 3)]);
+    }
+
+    #[test]
+    fn test_compacted() {
+        let mut feed = ChangeFeed::new(5);
+        assert_eq!(
+            feed.subscribe(Some(5)).err(),
+            Some(RevisionCompacted {
+                requested: 5,
+                oldest: 6
+            })
+        );
+        for revision in 6..(WATCH_HISTORY_SIZE as u64 + 7) {
+            feed.publish(vec![put("a", revision)]);
+        }
+        assert!(feed.subscribe(Some(6)).is_err());
+        assert!(feed.subscribe(Some(7)).is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use kv_service_config::{spawn_tls_reloader, PemFile};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{
+    rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig},
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+
+/// Time a client has to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept, errors such as running out of file descriptors persist for a while.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Files of the identity of the server and of the CA its clients must be signed by, in PEM.
+#[derive(Debug, Clone)]
+pub struct ServerTlsFiles {
+    pub certificate_chain: PathBuf,
+    pub private_key: PathBuf,
+    pub client_ca: PathBuf,
+}
+
+impl ServerTlsFiles {
+    fn load(&self) -> anyhow::Result<ServerConfig> {
+        let certificate_chain =
+            PemFile::certificates("TLS_CERT_FILE", self.certificate_chain.clone())?;
+        let private_key = PemFile::private_key("TLS_KEY_FILE", self.private_key.clone())?;
+        let client_ca = PemFile::certificates("TLS_CLIENT_CA_FILE", self.client_ca.clone())?;
+
+        let mut roots = RootCertStore::empty();
+        for certificate in rustls_pemfile::certs(&mut client_ca.pem.as_bytes()) {
+            roots.add(certificate?)?;
+        }
+        let verifier = WebPkiClientVerifier::builder(roots.into()).build()?;
+        let certificates = rustls_pemfile::certs(&mut certificate_chain.pem.as_bytes())
+            .collect::<Result<_, _>>()?;
+        let key = rustls_pemfile::private_key(&mut private_key.pem.as_bytes())?
+            .context("TLS_KEY_FILE: no private key")?;
+        let mut config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certificates, key)?;
+        config.alpn_protocols.push(b"h2".to_vec());
+        Ok(config)
+    }
+}
+
+/// TLS config of the gRPC server, which follows its files while the server runs. Every new
+/// connection is accepted with the latest config.
+#[derive(Clone)]
+pub struct ReloadableServerTls {
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl ReloadableServerTls {
+    /// Loads the files and reloads them when they change, checking them every `interval`, or on
+    /// SIGHUP.
+    pub fn watch(files: ServerTlsFiles, interval: Duration) -> anyhow::Result<Self> {
+        let tls = Self {
+            config: Arc::new(RwLock::new(Arc::new(files.load()?))),
+        };
+        let paths = vec![
+            files.certificate_chain.clone(),
+            files.private_key.clone(),
+            files.client_ca.clone(),
+        ];
+        let reloaded = tls.clone();
+        spawn_tls_reloader(paths, interval, move || {
+            let config = files.load()?;
+            *reloaded.config.write().unwrap() = Arc::new(config);
+            Ok(())
+        });
+        Ok(tls)
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.read().unwrap().clone())
+    }
+}
+
+/// Accepts TLS connections on the listener for `Router::serve_with_incoming`. Handshakes run
+/// concurrently, and connections whose handshake fails are dropped.
+pub fn tls_incoming(
+    listener: TcpListener,
+    tls: ReloadableServerTls,
+) -> impl Stream<Item = std::io::Result<TlsStream<TcpStream>>> {
+    let (sender, receiver) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(async move {
+        while !sender.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    tracing::warn!("Failed to accept connection: {}", err);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            let acceptor = tls.acceptor();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    }
+                    Ok(Err(err)) => tracing::debug!("TLS handshake with {} failed: {}", peer, err),
+                    Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(receiver)
+}
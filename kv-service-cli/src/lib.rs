@@ -0,0 +1,434 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use futures_util::StreamExt;
+use kv_service_client::{
+    Client, Condition, PutOptions, ScanOptions, TlsConfig, WatchEvent, WatchOptions,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub use output::Output;
+use output::{write_json, Table};
+
+mod output;
+
+/// Keys fetched per request when scanning or exporting.
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Protocol {
+    /// REST API of a frontend.
+    Http,
+    /// `KeyValueService` of a backend.
+    Grpc,
+}
+
+/// Command-line client of kv-service.
+#[derive(Debug, Parser)]
+#[command(name = "kvctl", version)]
+pub struct Cli {
+    #[arg(long, value_enum, default_value_t = Protocol::Http, global = true)]
+    pub protocol: Protocol,
+    /// Address of the service [default: 127.0.0.1:8080 over HTTP, 127.0.0.1:8081 over gRPC]
+    #[arg(long, global = true)]
+    pub address: Option<String>,
+    /// Connect over TLS, trusting `root.crt` of the TLS directory.
+    #[arg(long, global = true)]
+    pub tls: bool,
+    /// Connect over TLS and authenticate with `client.crt` and `client.key` of the TLS directory.
+    #[arg(long, global = true)]
+    pub mtls: bool,
+    #[arg(long, default_value = "tls", global = true)]
+    pub tls_dir: PathBuf,
+    /// Name the server certificate is issued to, if not the host of the address.
+    #[arg(long, env = "CA_DOMAIN_NAME", global = true)]
+    pub domain_name: Option<String>,
+    /// Time limit of a request in seconds.
+    #[arg(long, default_value_t = 10, global = true)]
+    pub timeout: u64,
+    /// How often a request is retried after a transient error.
+    #[arg(long, default_value_t = 3, global = true)]
+    pub retries: u32,
+    #[arg(short, long, value_enum, default_value_t = Output::Json, global = true)]
+    pub output: Output,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the value of a key.
+    Get { key: String },
+    /// Write a JSON value to a key.
+    Put {
+        key: String,
+        /// JSON value, or `-` to read it from stdin.
+        value: String,
+        /// Lifetime of the value in seconds.
+        #[arg(long)]
+        ttl: Option<u64>,
+        /// Only write if the key is at this version.
+        #[arg(long, conflicts_with_all = ["if_absent", "if_exists"])]
+        if_version: Option<u64>,
+        /// Only write if the key doesn't exist.
+        #[arg(long, conflicts_with = "if_exists")]
+        if_absent: bool,
+        /// Only write if the key exists.
+        #[arg(long)]
+        if_exists: bool,
+    },
+    /// Delete a key.
+    Delete { key: String },
+    /// List keys in key order.
+    Scan {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Inclusive lower bound.
+        #[arg(long)]
+        start: Option<String>,
+        /// Exclusive upper bound.
+        #[arg(long)]
+        end: Option<String>,
+        /// Stop after this many keys.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Print the changes of a key until interrupted.
+    Watch {
+        key: String,
+        /// Watch every key that starts with the key.
+        #[arg(long)]
+        prefix: bool,
+        /// Replay the retained changes from this revision on.
+        #[arg(long)]
+        start_revision: Option<u64>,
+    },
+    /// Write keys and values as JSON lines, which `import` reads back.
+    Export {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// File to write to instead of stdout.
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+    /// Write the keys and values of JSON lines written by `export`.
+    Import {
+        /// File to read, or `-` for stdin.
+        #[arg(default_value = "-")]
+        file: PathBuf,
+    },
+}
+
+/// Line of `export` and `import`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    key: String,
+    value: Value,
+}
+
+impl Cli {
+    fn address(&self) -> String {
+        let address = self.address.clone().unwrap_or_else(|| {
+            match self.protocol {
+                Protocol::Http => "127.0.0.1:8080",
+                Protocol::Grpc => "127.0.0.1:8081",
+            }
+            .to_string()
+        });
+        if address.contains("://") {
+            address
+        } else if self.tls || self.mtls {
+            format!("https://{address}")
+        } else {
+            format!("http://{address}")
+        }
+    }
+
+    fn tls_config(&self) -> anyhow::Result<Option<TlsConfig>> {
+        if !self.tls && !self.mtls {
+            return Ok(None);
+        }
+        let read = |name: &str| {
+            let path = self.tls_dir.join(name);
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))
+        };
+        let mut config = TlsConfig::new().ca_certificate(read("root.crt")?);
+        if self.mtls {
+            config = config.identity(read("client.crt")?, read("client.key")?);
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name);
+        }
+        Ok(Some(config))
+    }
+
+    pub fn client(&self) -> anyhow::Result<Client> {
+        let builder = match self.protocol {
+            Protocol::Http => Client::http(self.address()),
+            Protocol::Grpc => Client::grpc(self.address()),
+        };
+        let mut builder = builder
+            .timeout(Duration::from_secs(self.timeout))
+            .retries(self.retries);
+        if let Some(tls) = self.tls_config()? {
+            builder = builder.tls(tls);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Runs the command of `cli` and writes its output to `out`.
+pub async fn run(cli: Cli, out: &mut impl Write) -> anyhow::Result<()> {
+    let client = cli.client()?;
+    let output = cli.output;
+    match cli.command {
+        Command::Get { key } => {
+            let value = client
+                .get_versioned::<Value>(&key)
+                .await?
+                .with_context(|| format!("key {key} not found"))?;
+            match output {
+                Output::Json => write_json(
+                    out,
+                    &json!({"key": key, "value": value.value, "version": value.version}),
+                )?,
+                Output::Table => {
+                    let mut table = Table::new(&["KEY", "VERSION", "VALUE"]);
+                    table.row(vec![
+                        key,
+                        value.version.to_string(),
+                        value.value.to_string(),
+                    ]);
+                    table.write(out)?;
+                }
+            }
+        }
+        Command::Put {
+            key,
+            value,
+            ttl,
+            if_version,
+            if_absent,
+            if_exists,
+        } => {
+            let value = if value == "-" {
+                let mut value = String::new();
+                io::stdin().read_to_string(&mut value)?;
+                value
+            } else {
+                value
+            };
+            let value: Value = serde_json::from_str(&value)
+                .context("value must be JSON, strings need to be quoted")?;
+            let condition = match (if_version, if_absent, if_exists) {
+                (Some(version), _, _) => Some(Condition::Version(version)),
+                (None, true, _) => Some(Condition::Exists(false)),
+                (None, false, true) => Some(Condition::Exists(true)),
+                (None, false, false) => None,
+            };
+            let options = PutOptions {
+                ttl: ttl.map(Duration::from_secs),
+                condition,
+            };
+            let version = client.put_with(&key, &value, options).await?;
+            match output {
+                Output::Json => write_json(out, &json!({"key": key, "version": version}))?,
+                Output::Table => {
+                    let mut table = Table::new(&["KEY", "VERSION"]);
+                    table.row(vec![key, version.to_string()]);
+                    table.write(out)?;
+                }
+            }
+        }
+        Command::Delete { key } => {
+            let deleted = client.delete(&key).await?;
+            match output {
+                Output::Json => write_json(out, &json!({"key": key, "deleted": deleted}))?,
+                Output::Table => {
+                    let mut table = Table::new(&["KEY", "DELETED"]);
+                    table.row(vec![key, deleted.to_string()]);
+                    table.write(out)?;
+                }
+            }
+        }
+        Command::Scan {
+            prefix,
+            start,
+            end,
+            limit,
+        } => {
+            let options = ScanOptions {
+                prefix,
+                start,
+                end,
+                ..Default::default()
+            };
+            let mut table = Table::new(&["KEY", "VALUE"]);
+            scan(&client, options, limit, |entry| {
+                match output {
+                    Output::Json => write_json(out, &serde_json::to_value(entry)?)?,
+                    Output::Table => table.row(vec![entry.key, entry.value.to_string()]),
+                }
+                Ok(())
+            })
+            .await?;
+            if output == Output::Table {
+                table.write(out)?;
+            }
+        }
+        Command::Watch {
+            key,
+            prefix,
+            start_revision,
+        } => {
+            let options = WatchOptions {
+                prefix,
+                start_revision,
+            };
+            let mut events = client.watch::<Value>(&key, options).await?;
+            if output == Output::Table {
+                writeln!(out, "{:>8}  {:<6}  KEY  VALUE", "REVISION", "TYPE")?;
+            }
+            while let Some(event) = events.next().await {
+                let event = event?;
+                let (kind, value) = match &event {
+                    WatchEvent::Put { value, .. } => ("put", Some(value)),
+                    WatchEvent::Delete { .. } => ("delete", None),
+                };
+                match output {
+                    Output::Json => {
+                        let mut line = json!({
+                            "type": kind,
+                            "key": event.key(),
+                            "revision": event.revision(),
+                        });
+                        if let Some(value) = value {
+                            line["value"] = value.clone();
+                        }
+                        write_json(out, &line)?;
+                    }
+                    Output::Table => {
+                        let value = value.map(Value::to_string).unwrap_or_default();
+                        let line = format!(
+                            "{:>8}  {:<6}  {}  {}",
+                            event.revision(),
+                            kind,
+                            event.key(),
+                            value
+                        );
+                        writeln!(out, "{}", line.trim_end())?;
+                    }
+                }
+                // Events arrive one at a time, so they are shown as they come.
+                out.flush()?;
+            }
+        }
+        Command::Export { prefix, file } => {
+            let options = ScanOptions {
+                prefix,
+                ..Default::default()
+            };
+            let mut file = file
+                .map(|path| {
+                    File::create(&path)
+                        .with_context(|| format!("failed to create {}", path.display()))
+                })
+                .transpose()?
+                .map(io::BufWriter::new);
+            let out: &mut dyn Write = match &mut file {
+                Some(file) => file,
+                None => out,
+            };
+            scan(&client, options, None, |entry| {
+                writeln!(out, "{}", serde_json::to_string(&entry)?)?;
+                Ok(())
+            })
+            .await?;
+            out.flush()?;
+        }
+        Command::Import { file } => {
+            let input: Box<dyn BufRead> = if file.as_os_str() == "-" {
+                Box::new(io::stdin().lock())
+            } else {
+                let reader = File::open(&file)
+                    .with_context(|| format!("failed to open {}", file.display()))?;
+                Box::new(BufReader::new(reader))
+            };
+            let mut imported = 0;
+            for (i, line) in input.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: Entry = serde_json::from_str(&line)
+                    .with_context(|| format!("invalid entry on line {}", i + 1))?;
+                client.put(&entry.key, &entry.value).await?;
+                imported += 1;
+            }
+            match output {
+                Output::Json => write_json(out, &json!({"imported": imported}))?,
+                Output::Table => {
+                    let mut table = Table::new(&["IMPORTED"]);
+                    table.row(vec![imported.to_string()]);
+                    table.write(out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` with every key that matches `options`, up to `limit` keys.
+async fn scan(
+    client: &Client,
+    mut options: ScanOptions,
+    limit: Option<usize>,
+    mut f: impl FnMut(Entry) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    while remaining > 0 {
+        options.limit = Some(remaining.min(PAGE_SIZE) as u32);
+        let page = client.scan::<Value>(options.clone()).await?;
+        remaining -= page.items.len();
+        for item in page.items {
+            f(Entry {
+                key: item.key,
+                value: item.value,
+            })?;
+        }
+        match page.next_cursor {
+            Some(cursor) => options.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_arguments() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["kvctl", "--protocol", "grpc", "get", "a"]);
+        assert_eq!(cli.address(), "http://127.0.0.1:8081");
+        let cli = Cli::parse_from(["kvctl", "get", "a", "--tls", "--address", "localhost:3000"]);
+        assert_eq!(cli.address(), "https://localhost:3000");
+
+        assert!(
+            Cli::try_parse_from(["kvctl", "put", "a", "1", "--if-absent", "--if-exists"]).is_err()
+        );
+    }
+}
@@ -0,0 +1,9 @@
+use clap::Parser;
+use kv_service_cli::Cli;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    let cli = Cli::parse();
+    kv_service_cli::run(cli, &mut std::io::stdout().lock()).await
+}
@@ -0,0 +1,87 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// One JSON object per line.
+    Json,
+    /// Aligned columns with a header.
+    Table,
+}
+
+/// Columns of values, written with every column as wide as its widest cell.
+pub struct Table {
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            headers: headers.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut widths: Vec<_> = self.headers.iter().map(|header| header.len()).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let headers = self.headers.iter().map(|header| header.to_string());
+        write_row(out, &widths, &headers.collect::<Vec<_>>())?;
+        for row in &self.rows {
+            write_row(out, &widths, row)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_row(out: &mut impl Write, widths: &[usize], cells: &[String]) -> io::Result<()> {
+    let mut line = String::new();
+    for (i, (cell, width)) in cells.iter().zip(widths).enumerate() {
+        if i + 1 == cells.len() {
+            line.push_str(cell);
+        } else {
+            line.push_str(&format!("{cell:<width$}  "));
+        }
+    }
+    writeln!(out, "{}", line.trim_end())
+}
+
+/// Writes a JSON line.
+pub fn write_json(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    writeln!(out, "{value}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut table = Table::new(&["KEY", "VERSION", "VALUE"]);
+        table.row(vec![
+            "user/1".to_string(),
+            "12".to_string(),
+            r#"{"name":"Ada"}"#.to_string(),
+        ]);
+        table.row(vec!["a".to_string(), "3".to_string(), "null".to_string()]);
+        let mut out = Vec::new();
+        table.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "KEY     VERSION  VALUE\n\
+             user/1  12       {\"name\":\"Ada\"}\n\
+             a       3        null\n"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+
+use kv_service_proto::InvalidValue;
+use tonic::Code;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The service failed the request. `code` is the machine-readable code of the failure, the
+    /// same over both transports, such as `not_found` or `unavailable`.
+    Service { code: String, message: String },
+    /// A conditional write found the key in a different state.
+    ConditionFailed,
+    /// An attempt didn't complete within the timeout of the client.
+    Timeout,
+    /// The service couldn't be reached or the connection broke.
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+    /// A value couldn't be converted from or to JSON.
+    Serialization(serde_json::Error),
+    /// The service sent a value with no JSON representation.
+    InvalidValue(InvalidValue),
+    /// The client was configured with an invalid address or TLS certificate.
+    Config(String),
+}
+
+impl Error {
+    /// Whether the request may succeed if it's sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Service { code, .. } => matches!(
+                code.as_str(),
+                "unavailable" | "deadline_exceeded" | "resource_exhausted"
+            ),
+            Self::Timeout | Self::Transport(_) => true,
+            Self::ConditionFailed
+            | Self::Serialization(_)
+            | Self::InvalidValue(_)
+            | Self::Config(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Service { code, message } => write!(f, "{code}: {message}"),
+            Self::ConditionFailed => write!(f, "condition failed"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Transport(err) => write!(f, "transport error: {err}"),
+            Self::Serialization(err) => write!(f, "invalid value: {err}"),
+            Self::InvalidValue(err) => write!(f, "invalid value: {err}"),
+            Self::Config(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serialization(err)
+    }
+}
+
+impl From<InvalidValue> for Error {
+    fn from(err: InvalidValue) -> Self {
+        Self::InvalidValue(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            Self::Service {
+                code: "internal".to_string(),
+                message: err.to_string(),
+            }
+        } else {
+            Self::Transport(Box::new(err))
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Self::Transport(Box::new(err))
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        let code = match status.code() {
+            Code::FailedPrecondition => return Self::ConditionFailed,
+            Code::Ok => "ok",
+            Code::Cancelled => "cancelled",
+            Code::Unknown => "unknown",
+            Code::InvalidArgument => "invalid_argument",
+            Code::DeadlineExceeded => "deadline_exceeded",
+            Code::NotFound => "not_found",
+            Code::AlreadyExists => "already_exists",
+            Code::PermissionDenied => "permission_denied",
+            Code::ResourceExhausted => "resource_exhausted",
+            Code::Aborted => "aborted",
+            Code::OutOfRange => "out_of_range",
+            Code::Unimplemented => "unimplemented",
+            Code::Internal => "internal",
+            Code::Unavailable => "unavailable",
+            Code::DataLoss => "data_loss",
+            Code::Unauthenticated => "unauthenticated",
+        };
+        Self::Service {
+            code: code.to_string(),
+            message: status.message().to_string(),
+        }
+    }
+}
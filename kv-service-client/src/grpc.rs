@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use kv_service_proto::{
+    key_value_service::{
+        condition::Check, key_value_service_client::KeyValueServiceClient, watch_event::EventType,
+        KeyRequest, KeyValueRequest, ScanRequest, WatchRequest,
+    },
+    prost_to_serde_json, serde_json_to_prost,
+};
+use serde_json::Value;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use crate::{
+    Condition, Error, KeyValue, PutOptions, Result, ScanOptions, ScanPage, TlsConfig, Transport,
+    Versioned, WatchEvent, WatchOptions, WatchStream, DEFAULT_SCAN_LIMIT,
+};
+
+pub(crate) struct GrpcTransport {
+    client: KeyValueServiceClient<Channel>,
+}
+
+impl GrpcTransport {
+    pub(crate) fn new(address: &str, timeout: Duration, tls: Option<TlsConfig>) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(address.to_string())
+            .map_err(|err| Error::Config(format!("invalid address {address}: {err}")))?
+            .connect_timeout(timeout);
+        if let Some(tls) = tls {
+            let mut config = ClientTlsConfig::new();
+            if let Some(ca_certificate) = tls.ca_certificate {
+                config = config.ca_certificate(Certificate::from_pem(ca_certificate));
+            }
+            if let Some((certificate, key)) = tls.identity {
+                config = config.identity(Identity::from_pem(certificate, key));
+            }
+            if let Some(domain_name) = tls.domain_name {
+                config = config.domain_name(domain_name);
+            }
+            endpoint = endpoint
+                .tls_config(config)
+                .map_err(|err| Error::Config(err.to_string()))?;
+        }
+        Ok(Self {
+            client: KeyValueServiceClient::new(endpoint.connect_lazy()),
+        })
+    }
+}
+
+impl From<Condition> for kv_service_proto::key_value_service::Condition {
+    fn from(condition: Condition) -> Self {
+        let check = match condition {
+            Condition::Exists(exists) => Check::Exists(exists),
+            Condition::Version(version) => Check::Version(version),
+        };
+        Self { check: Some(check) }
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for GrpcTransport {
+    async fn get(&self, key: &str) -> Result<Option<Versioned<Value>>> {
+        let response = self
+            .client
+            .clone()
+            .get(KeyRequest {
+                key: key.to_string(),
+            })
+            .await?
+            .into_inner();
+        let Some(value) = response.value else {
+            return Ok(None);
+        };
+        Ok(Some(Versioned {
+            value: prost_to_serde_json(value)?,
+            version: response.version,
+        }))
+    }
+
+    async fn put(&self, key: &str, value: Value, options: &PutOptions) -> Result<u64> {
+        let request = KeyValueRequest {
+            key: key.to_string(),
+            value: Some(serde_json_to_prost(value)),
+            ttl_ms: options.ttl.map(|ttl| ttl.as_millis() as u64),
+            expires_at_ms: None,
+            condition: options.condition.map(Into::into),
+        };
+        Ok(self.client.clone().set(request).await?.into_inner().version)
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let request = KeyRequest {
+            key: key.to_string(),
+        };
+        Ok(self
+            .client
+            .clone()
+            .delete(request)
+            .await?
+            .into_inner()
+            .deleted)
+    }
+
+    async fn scan(&self, options: &ScanOptions) -> Result<ScanPage<Value>> {
+        let limit = options.limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+        // One extra entry tells whether there is another page.
+        let request = ScanRequest {
+            prefix: options.prefix.clone(),
+            start: options.start.clone(),
+            end: options.end.clone(),
+            limit: limit.saturating_add(1),
+            cursor: options.cursor.clone().unwrap_or_default(),
+        };
+        let mut stream = self.client.clone().scan(request).await?.into_inner();
+        let mut responses = Vec::new();
+        while let Some(response) = stream.message().await? {
+            responses.push(response);
+        }
+        let next_cursor = if responses.len() > limit as usize {
+            responses.truncate(limit as usize);
+            responses.last().map(|response| response.cursor.clone())
+        } else {
+            None
+        };
+        let items = responses
+            .into_iter()
+            .map(|response| {
+                Ok(KeyValue {
+                    key: response.key,
+                    value: response
+                        .value
+                        .map(prost_to_serde_json)
+                        .transpose()?
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ScanPage { items, next_cursor })
+    }
+
+    async fn watch(&self, key: &str, options: &WatchOptions) -> Result<WatchStream<Value>> {
+        let request = WatchRequest {
+            key: key.to_string(),
+            prefix: options.prefix,
+            start_revision: options.start_revision,
+        };
+        let stream = self.client.clone().watch(request).await?.into_inner();
+        Ok(Box::pin(stream.map(|event| {
+            let event = event?;
+            Ok(match event.r#type() {
+                EventType::Put => WatchEvent::Put {
+                    value: prost_to_serde_json(event.value.unwrap_or_default())?,
+                    key: event.key,
+                    revision: event.revision,
+                },
+                EventType::Delete => WatchEvent::Delete {
+                    key: event.key,
+                    revision: event.revision,
+                },
+            })
+        })))
+    }
+}
@@ -0,0 +1,433 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures_util::StreamExt;
+use reqwest::{
+    header::{self, HeaderValue},
+    Certificate, Identity, Response, StatusCode, Url,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    Condition, Error, KeyValue, PutOptions, Result, ScanOptions, ScanPage, TlsConfig, Transport,
+    Versioned, WatchEvent, WatchOptions, WatchStream,
+};
+
+pub(crate) struct HttpTransport {
+    client: reqwest::Client,
+    /// URL of the `/api` route.
+    base: Url,
+}
+
+impl HttpTransport {
+    pub(crate) fn new(address: &str, timeout: Duration, tls: Option<TlsConfig>) -> Result<Self> {
+        let invalid_address = |err: &dyn std::fmt::Display| {
+            Error::Config(format!("invalid address {address}: {err}"))
+        };
+        let mut base = Url::parse(address).map_err(|err| invalid_address(&err))?;
+        // Watches stay open, so only connecting is limited here and requests are limited by the
+        // client.
+        let mut builder = reqwest::Client::builder().connect_timeout(timeout);
+        if let Some(tls) = tls {
+            if let Some(ca_certificate) = tls.ca_certificate {
+                let certificate = Certificate::from_pem(ca_certificate.as_bytes())
+                    .map_err(|err| Error::Config(err.to_string()))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+            if let Some((certificate, key)) = tls.identity {
+                let identity = Identity::from_pkcs8_pem(certificate.as_bytes(), key.as_bytes())
+                    .map_err(|err| Error::Config(err.to_string()))?;
+                builder = builder.identity(identity);
+            }
+            if let Some(domain_name) = tls.domain_name {
+                // Requests go to the domain name, which resolves to the address, so that the
+                // certificate is verified against it.
+                let addr = base
+                    .socket_addrs(|| None)
+                    .map_err(|err| invalid_address(&err))?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| invalid_address(&"host not found"))?;
+                builder = builder.resolve(&domain_name, addr);
+                base.set_host(Some(&domain_name))
+                    .map_err(|err| invalid_address(&err))?;
+            }
+        }
+        base.path_segments_mut()
+            .map_err(|()| invalid_address(&"not a base URL"))?
+            .pop_if_empty()
+            .push("api");
+        let client = builder
+            .build()
+            .map_err(|err| Error::Config(err.to_string()))?;
+        Ok(Self { client, base })
+    }
+
+    /// URL of the key followed by `segments`, the key is a single segment even if it has
+    /// slashes.
+    fn key_url(&self, key: &str, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("base URL")
+            .push(key)
+            .extend(segments);
+        url
+    }
+}
+
+fn version(response: &Response) -> Result<u64> {
+    response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix('"')?.strip_suffix('"'))
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| Error::Service {
+            code: "internal".to_string(),
+            message: "response without a version in its ETag".to_string(),
+        })
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    code: String,
+    message: String,
+}
+
+async fn response_error(response: Response) -> Error {
+    let status = response.status();
+    match response.bytes().await {
+        Ok(body) => status_error(status, &body),
+        Err(err) => err.into(),
+    }
+}
+
+/// Reads the error of a failed response, which is in the body unless the frontend rejected the
+/// request itself.
+fn status_error(status: StatusCode, body: &[u8]) -> Error {
+    if status == StatusCode::PRECONDITION_FAILED {
+        return Error::ConditionFailed;
+    }
+    if let Ok(ErrorBody { error }) = serde_json::from_slice(body) {
+        return Error::Service {
+            code: error.code,
+            message: error.message,
+        };
+    }
+    let code = match status {
+        StatusCode::BAD_REQUEST => "invalid_argument",
+        StatusCode::UNAUTHORIZED => "unauthenticated",
+        StatusCode::FORBIDDEN => "permission_denied",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::GONE => "out_of_range",
+        StatusCode::CONFLICT => "aborted",
+        StatusCode::TOO_MANY_REQUESTS => "resource_exhausted",
+        StatusCode::NOT_IMPLEMENTED => "unimplemented",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        StatusCode::GATEWAY_TIMEOUT => "deadline_exceeded",
+        _ => "internal",
+    };
+    Error::Service {
+        code: code.to_string(),
+        message: status
+            .canonical_reason()
+            .unwrap_or("request failed")
+            .to_lowercase(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ScanResponse {
+    items: Vec<ScanItem>,
+    #[serde(default)]
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ScanItem {
+    key: String,
+    value: Value,
+}
+
+#[tonic::async_trait]
+impl Transport for HttpTransport {
+    async fn get(&self, key: &str) -> Result<Option<Versioned<Value>>> {
+        let response = self.client.get(self.key_url(key, &[])).send().await?;
+        match response.status() {
+            StatusCode::OK => {
+                let version = version(&response)?;
+                Ok(Some(Versioned {
+                    value: response.json().await?,
+                    version,
+                }))
+            }
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(response_error(response).await),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Value, options: &PutOptions) -> Result<u64> {
+        let mut request = self.client.put(self.key_url(key, &[])).json(&value);
+        if let Some(ttl) = options.ttl {
+            // The REST API takes whole seconds, so the value doesn't expire too early.
+            let seconds = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+            request = request.query(&[("ttl", seconds)]);
+        }
+        request = match options.condition {
+            Some(Condition::Exists(true)) => {
+                request.header(header::IF_MATCH, HeaderValue::from_static("*"))
+            }
+            Some(Condition::Exists(false)) => {
+                request.header(header::IF_NONE_MATCH, HeaderValue::from_static("*"))
+            }
+            Some(Condition::Version(version)) => {
+                request.header(header::IF_MATCH, format!("\"{version}\""))
+            }
+            None => request,
+        };
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::CREATED | StatusCode::NO_CONTENT => version(&response),
+            _ => Err(response_error(response).await),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool> {
+        let response = self.client.delete(self.key_url(key, &[])).send().await?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(response_error(response).await),
+        }
+    }
+
+    async fn scan(&self, options: &ScanOptions) -> Result<ScanPage<Value>> {
+        let mut query = vec![("prefix", options.prefix.clone())];
+        query.extend(options.start.clone().map(|start| ("start", start)));
+        query.extend(options.end.clone().map(|end| ("end", end)));
+        query.extend(options.cursor.clone().map(|cursor| ("cursor", cursor)));
+        query.extend(options.limit.map(|limit| ("limit", limit.to_string())));
+        let response = self
+            .client
+            .get(self.base.clone())
+            .query(&query)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(response_error(response).await);
+        }
+        let page: ScanResponse = response.json().await?;
+        Ok(ScanPage {
+            items: page
+                .items
+                .into_iter()
+                .map(|item| KeyValue {
+                    key: item.key,
+                    value: item.value,
+                })
+                .collect(),
+            next_cursor: page.next_cursor,
+        })
+    }
+
+    async fn watch(&self, key: &str, options: &WatchOptions) -> Result<WatchStream<Value>> {
+        let mut request = if options.prefix {
+            self.client
+                .get(self.base.clone())
+                .query(&[("prefix", key), ("watch", "true")])
+        } else {
+            self.client.get(self.key_url(key, &["watch"]))
+        };
+        if let Some(start_revision) = options.start_revision {
+            request = request.query(&[("start_revision", start_revision)]);
+        }
+        let response = request
+            .header(
+                header::ACCEPT,
+                HeaderValue::from_static("text/event-stream"),
+            )
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(response_error(response).await);
+        }
+        let state = (
+            response.bytes_stream().boxed(),
+            SseParser::default(),
+            VecDeque::new(),
+        );
+        let events =
+            futures_util::stream::unfold(state, |(mut body, mut parser, mut events)| async move {
+                loop {
+                    if let Some(event) = events.pop_front() {
+                        return Some((watch_event(event), (body, parser, events)));
+                    }
+                    match body.next().await? {
+                        Ok(chunk) => events.extend(parser.push(&chunk)),
+                        Err(err) => return Some((Err(err.into()), (body, parser, events))),
+                    }
+                }
+            });
+        Ok(Box::pin(events))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct SseEvent {
+    event: String,
+    data: String,
+}
+
+/// Splits a `text/event-stream` body into events, whatever the chunks it arrives in.
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    event: String,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(SseEvent {
+                        event: std::mem::take(&mut self.event),
+                        data: std::mem::take(&mut self.data).join("\n"),
+                    });
+                }
+                self.event.clear();
+                continue;
+            }
+            // Lines that start with a colon are comments, such as keep-alives.
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => self.event = value.to_string(),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+#[derive(Deserialize)]
+struct EventData {
+    #[serde(rename = "type")]
+    kind: String,
+    key: String,
+    #[serde(default)]
+    value: Value,
+    revision: u64,
+}
+
+fn watch_event(event: SseEvent) -> Result<WatchEvent<Value>> {
+    if event.event == "error" {
+        // The frontend sends the error as `<code>: <message>`.
+        return Err(match event.data.split_once(": ") {
+            Some((code, message))
+                if code
+                    .bytes()
+                    .all(|byte| byte.is_ascii_lowercase() || byte == b'_') =>
+            {
+                Error::Service {
+                    code: code.to_string(),
+                    message: message.to_string(),
+                }
+            }
+            _ => Error::Service {
+                code: "internal".to_string(),
+                message: event.data,
+            },
+        });
+    }
+    let data: EventData = serde_json::from_str(&event.data)?;
+    match data.kind.as_str() {
+        "put" => Ok(WatchEvent::Put {
+            key: data.key,
+            value: data.value,
+            revision: data.revision,
+        }),
+        "delete" => Ok(WatchEvent::Delete {
+            key: data.key,
+            revision: data.revision,
+        }),
+        kind => Err(Error::Service {
+            code: "internal".to_string(),
+            message: format!("unknown watch event {kind}"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_sse_parser() {
+        let body = ": keep-alive\n\nevent: put\nid: 7\ndata: {\"type\":\"put\",\"key\":\"a\",\
+                    \"value\":1.50,\"revision\":7}\n\nevent:delete\r\ndata:{\"type\":\"delete\",\
+                    \"key\":\"a\",\"revision\":8}\r\n\r\nevent: error\ndata: unavailable: no \
+                    leader\n\n";
+        // Events are split however the chunks are cut.
+        for chunk_size in [1, 7, body.len()] {
+            let mut parser = SseParser::default();
+            let events: Vec<_> = body
+                .as_bytes()
+                .chunks(chunk_size)
+                .flat_map(|chunk| parser.push(chunk))
+                .map(watch_event)
+                .collect();
+            assert_eq!(events.len(), 3);
+            assert_eq!(
+                events[0].as_ref().unwrap(),
+                &WatchEvent::Put {
+                    key: "a".to_string(),
+                    value: serde_json::from_str("1.50").unwrap(),
+                    revision: 7,
+                }
+            );
+            assert_eq!(
+                events[1].as_ref().unwrap(),
+                &WatchEvent::Delete {
+                    key: "a".to_string(),
+                    revision: 8,
+                }
+            );
+            assert!(matches!(
+                &events[2],
+                Err(Error::Service { code, message }) if code == "unavailable" && message == "no leader"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_status_error() {
+        assert!(matches!(
+            status_error(StatusCode::PRECONDITION_FAILED, b""),
+            Error::ConditionFailed
+        ));
+
+        let body = json!({"error": {"code": "unavailable", "message": "no leader"}}).to_string();
+        let err = status_error(StatusCode::SERVICE_UNAVAILABLE, body.as_bytes());
+        assert!(err.is_transient());
+        assert_eq!(err.to_string(), "unavailable: no leader");
+
+        let err = status_error(StatusCode::BAD_REQUEST, b"null");
+        assert!(!err.is_transient());
+        assert_eq!(err.to_string(), "invalid_argument: bad request");
+    }
+}
@@ -0,0 +1,391 @@
+//! Async client of kv-service, which talks either gRPC to a backend or HTTP to a frontend.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), kv_service_client::Error> {
+//! use std::time::Duration;
+//!
+//! use kv_service_client::Client;
+//!
+//! let client = Client::grpc("http://127.0.0.1:8081")
+//!     .timeout(Duration::from_secs(2))
+//!     .retries(3)
+//!     .build()?;
+//! client.put("counter", &41).await?;
+//! let counter: Option<u64> = client.get("counter").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Exact numbers
+//!
+//! Values pass through `serde_json::Value`, which holds only 64-bit integers and doubles by
+//! default, so larger integers and decimals with more digits than a double are rounded. The
+//! `arbitrary_precision` feature keeps them exact by enabling serde_json's feature of the same
+//! name. Cargo enables a feature for the whole build, so with it every crate that parses JSON
+//! into a `serde_json::Number` gets a number that keeps its text: `1.0` and `1.00` are no longer
+//! equal, and numbers serialized to formats other than JSON become a struct holding the text.
+
+use std::{path::Path, pin::Pin, sync::Arc, time::Duration};
+
+use futures_util::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+pub use error::Error;
+
+mod error;
+mod grpc;
+mod http;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+/// Page size of scans that don't set a limit, the default of the REST API.
+const DEFAULT_SCAN_LIMIT: u32 = 100;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub type WatchStream<T> = Pin<Box<dyn Stream<Item = Result<WatchEvent<T>>> + Send>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned<T> {
+    pub value: T,
+    /// Increases with every write of the key.
+    pub version: u64,
+}
+
+/// Requirement a key has to meet for a conditional write, otherwise the write fails with
+/// [`Error::ConditionFailed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// The key must exist if `true` or must not exist if `false`.
+    Exists(bool),
+    /// The key must be at this version.
+    Version(u64),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    /// Lifetime of the value, rounded up to whole seconds over HTTP.
+    pub ttl: Option<Duration>,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    pub prefix: String,
+    /// Inclusive lower bound.
+    pub start: Option<String>,
+    /// Exclusive upper bound.
+    pub end: Option<String>,
+    /// Page size, 100 by default.
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyValue<T> {
+    pub key: String,
+    pub value: T,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanPage<T> {
+    pub items: Vec<KeyValue<T>>,
+    /// Cursor of the next page, `None` on the last page. Cursors only work with the transport
+    /// that returned them.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WatchOptions {
+    /// Watch every key that starts with the key instead of the key itself.
+    pub prefix: bool,
+    /// Replay the retained changes from this revision on instead of only sending new ones.
+    pub start_revision: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent<T> {
+    Put {
+        key: String,
+        value: T,
+        revision: u64,
+    },
+    Delete {
+        key: String,
+        revision: u64,
+    },
+}
+
+impl<T> WatchEvent<T> {
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Put { key, .. } | Self::Delete { key, .. } => key,
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        match self {
+            Self::Put { revision, .. } | Self::Delete { revision, .. } => *revision,
+        }
+    }
+}
+
+/// Certificates of a TLS connection, in PEM.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    ca_certificate: Option<String>,
+    identity: Option<(String, String)>,
+    domain_name: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the `tls` directory of the repository: `root.crt` is trusted and `client.crt`
+    /// with `client.key` is the client certificate, if they exist.
+    pub fn from_dir(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut config = Self::new().ca_certificate(std::fs::read_to_string(dir.join("root.crt"))?);
+        if dir.join("client.crt").exists() {
+            config = config.identity(
+                std::fs::read_to_string(dir.join("client.crt"))?,
+                std::fs::read_to_string(dir.join("client.key"))?,
+            );
+        }
+        Ok(config)
+    }
+
+    /// Trusts the certificates signed by this CA.
+    pub fn ca_certificate(mut self, pem: impl Into<String>) -> Self {
+        self.ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// Authenticates the client with a certificate, for servers that require mutual TLS.
+    pub fn identity(mut self, certificate: impl Into<String>, key: impl Into<String>) -> Self {
+        self.identity = Some((certificate.into(), key.into()));
+        self
+    }
+
+    /// Verifies the server certificate against this name instead of the host of the address.
+    pub fn domain_name(mut self, domain_name: impl Into<String>) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+}
+
+/// Operations on JSON values, implemented by both transports.
+#[tonic::async_trait]
+trait Transport: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Versioned<Value>>>;
+    async fn put(&self, key: &str, value: Value, options: &PutOptions) -> Result<u64>;
+    async fn delete(&self, key: &str) -> Result<bool>;
+    async fn scan(&self, options: &ScanOptions) -> Result<ScanPage<Value>>;
+    async fn watch(&self, key: &str, options: &WatchOptions) -> Result<WatchStream<Value>>;
+}
+
+enum Protocol {
+    Grpc,
+    Http,
+}
+
+pub struct ClientBuilder {
+    protocol: Protocol,
+    address: String,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    tls: Option<TlsConfig>,
+}
+
+impl ClientBuilder {
+    /// Time limit of every attempt of a request, 10 seconds by default. Watches are only limited
+    /// until the stream is open.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How often a request that failed with a transient error is sent again, 3 times by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, which doubles with every further retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Connects over TLS, the address must then use `https`.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Creates the client, which connects on its first request.
+    pub fn build(self) -> Result<Client> {
+        let transport: Arc<dyn Transport> = match self.protocol {
+            Protocol::Grpc => Arc::new(grpc::GrpcTransport::new(
+                &self.address,
+                self.timeout,
+                self.tls,
+            )?),
+            Protocol::Http => Arc::new(http::HttpTransport::new(
+                &self.address,
+                self.timeout,
+                self.tls,
+            )?),
+        };
+        Ok(Client {
+            transport,
+            timeout: self.timeout,
+            retries: self.retries,
+            backoff: self.backoff,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    transport: Arc<dyn Transport>,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Client {
+    /// Talks to the `KeyValueService` of a backend, for example at `http://127.0.0.1:8081`.
+    pub fn grpc(address: impl Into<String>) -> ClientBuilder {
+        Self::builder(Protocol::Grpc, address.into())
+    }
+
+    /// Talks to the REST API of a frontend, for example at `http://127.0.0.1:8080`.
+    pub fn http(address: impl Into<String>) -> ClientBuilder {
+        Self::builder(Protocol::Http, address.into())
+    }
+
+    fn builder(protocol: Protocol, address: String) -> ClientBuilder {
+        ClientBuilder {
+            protocol,
+            address,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            tls: None,
+        }
+    }
+
+    /// Runs `request` with the timeout and retries of the client.
+    async fn send<T, F, Fut>(&self, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let result = match tokio::time::timeout(self.timeout, request()).await {
+                Ok(result) => result,
+                Err(_) => Err(Error::Timeout),
+            };
+            match result {
+                Err(err) if err.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        Ok(self.get_versioned(key).await?.map(|value| value.value))
+    }
+
+    pub async fn get_versioned<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<Option<Versioned<T>>> {
+        let Some(value) = self.send(|| self.transport.get(key)).await? else {
+            return Ok(None);
+        };
+        Ok(Some(Versioned {
+            value: serde_json::from_value(value.value)?,
+            version: value.version,
+        }))
+    }
+
+    /// Writes the value and returns its new version.
+    pub async fn put<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<u64> {
+        self.put_with(key, value, PutOptions::default()).await
+    }
+
+    /// Writes the value with an expiry or a condition. A conditional write whose first attempt
+    /// was applied but not answered fails with [`Error::ConditionFailed`] when it's retried.
+    pub async fn put_with<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        options: PutOptions,
+    ) -> Result<u64> {
+        let value = serde_json::to_value(value)?;
+        self.send(|| self.transport.put(key, value.clone(), &options))
+            .await
+    }
+
+    /// Deletes the key and returns whether it existed.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        self.send(|| self.transport.delete(key)).await
+    }
+
+    /// Returns a page of keys in key order.
+    pub async fn scan<T: DeserializeOwned>(&self, options: ScanOptions) -> Result<ScanPage<T>> {
+        let page = self.send(|| self.transport.scan(&options)).await?;
+        let items = page
+            .items
+            .into_iter()
+            .map(|item| {
+                Ok(KeyValue {
+                    key: item.key,
+                    value: serde_json::from_value(item.value)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(ScanPage {
+            items,
+            next_cursor: page.next_cursor,
+        })
+    }
+
+    /// Streams the changes of a key, or of every key with the prefix. The stream ends with an
+    /// error if the connection breaks, resume from the revision after the last event.
+    pub async fn watch<T: DeserializeOwned + 'static>(
+        &self,
+        key: &str,
+        options: WatchOptions,
+    ) -> Result<WatchStream<T>> {
+        let stream = self.send(|| self.transport.watch(key, &options)).await?;
+        Ok(Box::pin(stream.map(|event| {
+            Ok(match event? {
+                WatchEvent::Put {
+                    key,
+                    value,
+                    revision,
+                } => WatchEvent::Put {
+                    key,
+                    value: serde_json::from_value(value)?,
+                    revision,
+                },
+                WatchEvent::Delete { key, revision } => WatchEvent::Delete { key, revision },
+            })
+        })))
+    }
+}
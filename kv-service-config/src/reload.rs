@@ -0,0 +1,117 @@
+use std::{future, path::PathBuf, time::Duration};
+
+use tokio::{task::JoinHandle, time::Interval};
+
+/// Calls `reload` when the contents of one of the certificate or key files change, which are
+/// checked every `interval`, or when the process receives SIGHUP. A zero `interval` only reloads
+/// on SIGHUP. A reload that fails, for example because only the certificate was replaced so far,
+/// is logged and retried on the next change.
+pub fn spawn_tls_reloader<F>(
+    paths: Vec<PathBuf>,
+    interval: Duration,
+    mut reload: F,
+) -> JoinHandle<()>
+where
+    F: FnMut() -> anyhow::Result<()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut contents = read_all(&paths);
+        let mut ticker = (!interval.is_zero())
+            .then(|| tokio::time::interval_at(tokio::time::Instant::now() + interval, interval));
+        let mut hangup = hangup_signal();
+        loop {
+            let signaled = tokio::select! {
+                () = tick(&mut ticker) => false,
+                () = recv_hangup(&mut hangup) => true,
+            };
+            let current = read_all(&paths);
+            if !signaled && current == contents {
+                continue;
+            }
+            contents = current;
+            match reload() {
+                Ok(()) => tracing::info!("Reloaded TLS certificates"),
+                Err(err) => tracing::error!(
+                    "Failed to reload TLS certificates, keeping the previous ones: {:#}",
+                    err
+                ),
+            }
+        }
+    })
+}
+
+fn read_all(paths: &[PathBuf]) -> Vec<Option<Vec<u8>>> {
+    paths.iter().map(|path| std::fs::read(path).ok()).collect()
+}
+
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup_signal() -> Option<Hangup> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    signal(SignalKind::hangup())
+        .inspect_err(|err| tracing::warn!("Failed to listen for SIGHUP: {}", err))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Option<Hangup> {
+    None
+}
+
+async fn recv_hangup(hangup: &mut Option<Hangup>) {
+    #[cfg(unix)]
+    if let Some(hangup) = hangup {
+        if hangup.recv().await.is_some() {
+            return;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = hangup;
+    future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.crt");
+        std::fs::write(&path, "first").unwrap();
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = reloads.clone();
+        let reloader =
+            spawn_tls_reloader(vec![path.clone()], Duration::from_millis(10), move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 0);
+        std::fs::write(&path, "second").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+        reloader.abort();
+    }
+}
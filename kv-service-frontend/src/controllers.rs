@@ -1,15 +1,22 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{delete, get, put},
+    routing::{delete, get, post, put},
     Router,
 };
-use tower_http::trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+};
 use tracing::Level;
 
 use crate::services::key_value_service::KeyValueService;
 
 pub mod key_value_controller;
+pub mod shard_controller;
+pub mod websocket_controller;
+
+pub use shard_controller::ShardState;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -18,14 +25,35 @@ pub struct AppState {
 
 pub fn create_router(state: AppState) -> Router {
     Router::new()
+        .route("/api", get(key_value_controller::list_values))
+        .route("/api/_txn", post(key_value_controller::run_transaction))
+        .route("/api/_batch", post(key_value_controller::run_batch))
+        .route("/api/_ws", get(websocket_controller::websocket))
         .route("/api/:key", get(key_value_controller::get_value))
         .route("/api/:key", put(key_value_controller::put_value))
         .route("/api/:key", delete(key_value_controller::delete_value))
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
-                .on_request(DefaultOnRequest::new().level(Level::INFO))
-                .on_response(DefaultOnResponse::new().level(Level::INFO)),
-        )
+        .route("/api/:key/watch", get(key_value_controller::watch_value))
         .with_state(state)
+        .layer(trace_layer())
+}
+
+/// Creates the router of the shard management API. It moves keys between backends, so it is
+/// served on its own listener instead of next to the public API.
+pub fn create_shard_admin_router(shard_state: ShardState) -> Router {
+    Router::new()
+        .route("/api/_shards", get(shard_controller::list_shards))
+        .route("/api/_shards", post(shard_controller::add_shard))
+        .route(
+            "/api/_shards/:address",
+            delete(shard_controller::remove_shard),
+        )
+        .with_state(shard_state)
+        .layer(trace_layer())
+}
+
+fn trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>> {
+    TraceLayer::new_for_http()
+        .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+        .on_request(DefaultOnRequest::new().level(Level::INFO))
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
 }
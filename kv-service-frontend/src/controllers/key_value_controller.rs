@@ -1,46 +1,181 @@
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio_stream::{Stream, StreamExt};
 
-use crate::error::ServiceError;
+use crate::{
+    error::ServiceError,
+    services::key_value_service::{
+        Condition, Expiry, Operation, Precondition, PutItem, PutOptions, PutOutcome, ScanOptions,
+        ScanPage, TransactionOutcome, VersionedValue, WatchEventKind, WatchOptions, WatchStream,
+    },
+};
+
+const DEFAULT_SCAN_LIMIT: u32 = 100;
+const MAX_SCAN_LIMIT: u32 = 1000;
+const MAX_BATCH_SIZE: usize = 1000;
 
 use super::AppState;
 
+fn etag(version: u64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = HeaderValue::try_from(format!("\"{version}\"")).expect("valid header value");
+    headers.insert(header::ETAG, value);
+    headers
+}
+
+fn parse_etag(value: &HeaderValue) -> Option<u64> {
+    value
+        .to_str()
+        .ok()?
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .parse()
+        .ok()
+}
+
+fn is_wildcard(value: &HeaderValue) -> bool {
+    value.as_bytes().trim_ascii() == b"*"
+}
+
+/// Turns the `If-Match` and `If-None-Match` headers of a put into a condition on the
+/// current version of the key.
+fn put_condition(headers: &HeaderMap) -> Result<Option<Condition>, &'static str> {
+    match (
+        headers.get(header::IF_MATCH),
+        headers.get(header::IF_NONE_MATCH),
+    ) {
+        (Some(_), Some(_)) => Err("If-Match and If-None-Match cannot both be set"),
+        (Some(value), None) if is_wildcard(value) => Ok(Some(Condition::Exists(true))),
+        (Some(value), None) => parse_etag(value)
+            .map(|version| Some(Condition::Version(version)))
+            .ok_or("If-Match must be * or a single strong entity tag"),
+        (None, Some(value)) if is_wildcard(value) => Ok(Some(Condition::Exists(false))),
+        (None, Some(_)) => Err("If-None-Match must be *"),
+        (None, None) => Ok(None),
+    }
+}
+
 pub async fn get_value(
     State(state): State<AppState>,
     Path(key): Path<String>,
-) -> Result<(StatusCode, Json<Option<Value>>), ServiceError> {
+) -> Result<(StatusCode, HeaderMap, Json<Option<Value>>), ServiceError> {
     tracing::debug!("Getting value for key: {}", key);
     let value = state.key_value_service.get_value(&key).await?;
     let response = if let Some(value) = value {
         tracing::debug!("Got value: {:?} for key: {}", value, key);
-        (StatusCode::OK, Json(Some(value)))
+        (StatusCode::OK, etag(value.version), Json(Some(value.value)))
     } else {
         tracing::debug!("Value for key not found: {}", key);
-        (StatusCode::NOT_FOUND, Json(None))
+        (StatusCode::NOT_FOUND, HeaderMap::new(), Json(None))
     };
     Ok(response)
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct PutValueParams {
+    /// Lifetime of the value in seconds.
+    ttl: Option<u64>,
+    /// Expiry time in seconds since the Unix epoch.
+    expires_at: Option<u64>,
+}
+
+/// Keys whose path under `/api` is taken by another endpoint, a value stored under one of them
+/// couldn't be read back over REST.
+const RESERVED_KEYS: [&str; 3] = ["_txn", "_batch", "_ws"];
+
+/// Rejects writes to the `RESERVED_KEYS`.
+pub(super) fn writable_key(key: &str) -> Result<(), &'static str> {
+    if RESERVED_KEYS.contains(&key) {
+        return Err("_txn, _batch and _ws are reserved keys");
+    }
+    Ok(())
+}
+
+/// Validates the `ttl` and `expires_at` parameters, both in seconds.
+pub(super) fn expiry(
+    ttl: Option<u64>,
+    expires_at: Option<u64>,
+) -> Result<Option<Expiry>, &'static str> {
+    match (ttl, expires_at) {
+        (Some(_), Some(_)) => Err("ttl and expires_at cannot both be set"),
+        (Some(0), None) => Err("ttl must be greater than 0"),
+        (Some(ttl), None) => Ok(Some(Expiry::After(Duration::from_secs(ttl)))),
+        (None, Some(expires_at)) => {
+            let expires_at = UNIX_EPOCH + Duration::from_secs(expires_at);
+            if expires_at <= SystemTime::now() {
+                return Err("expires_at must be in the future");
+            }
+            Ok(Some(Expiry::At(expires_at)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
 pub async fn put_value(
     State(state): State<AppState>,
     Path(key): Path<String>,
+    Query(params): Query<PutValueParams>,
+    headers: HeaderMap,
     body: Json<Value>,
-) -> Result<StatusCode, ServiceError> {
+) -> Result<(StatusCode, HeaderMap), ServiceError> {
     if body.0.is_null() {
-        return Ok(StatusCode::BAD_REQUEST);
+        return Err(ServiceError::InvalidArgument(
+            "value cannot be null".to_string(),
+        ));
     }
+    let expiry = match expiry(params.ttl, params.expires_at) {
+        Ok(expiry) => expiry,
+        Err(err) => {
+            tracing::debug!("Invalid expiry for key {}: {}", key, err);
+            return Err(ServiceError::InvalidArgument(err.to_string()));
+        }
+    };
+    let condition = match put_condition(&headers) {
+        Ok(condition) => condition,
+        Err(err) => {
+            tracing::debug!("Invalid precondition for key {}: {}", key, err);
+            return Err(ServiceError::InvalidArgument(err.to_string()));
+        }
+    };
     tracing::debug!("Putting value {} for key {}", body.0, key);
-    let updated = state.key_value_service.put_value(&key, body.0).await?;
-    let response = if updated {
-        tracing::debug!("Updated value for key: {}", key);
-        StatusCode::NO_CONTENT
-    } else {
-        tracing::debug!("Created value for key: {}", key);
-        StatusCode::CREATED
+    let outcome = state
+        .key_value_service
+        .put_value(&key, body.0, PutOptions { expiry, condition })
+        .await?;
+    let response = match outcome {
+        PutOutcome::Written {
+            updated: true,
+            version,
+        } => {
+            tracing::debug!("Updated value for key: {}", key);
+            (StatusCode::NO_CONTENT, etag(version))
+        }
+        PutOutcome::Written {
+            updated: false,
+            version,
+        } => {
+            tracing::debug!("Created value for key: {}", key);
+            (StatusCode::CREATED, etag(version))
+        }
+        PutOutcome::ConditionFailed => {
+            tracing::debug!("Precondition failed for key: {}", key);
+            (StatusCode::PRECONDITION_FAILED, HeaderMap::new())
+        }
     };
     Ok(response)
 }
@@ -61,13 +196,529 @@ pub async fn delete_value(
     Ok(response)
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ScanParams {
+    #[serde(default)]
+    prefix: String,
+    start: Option<String>,
+    end: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+    /// Stream changes to keys with the prefix instead of listing them.
+    #[serde(default)]
+    watch: bool,
+    start_revision: Option<u64>,
+}
+
+pub async fn list_values(
+    state: State<AppState>,
+    Query(params): Query<ScanParams>,
+    headers: HeaderMap,
+) -> Result<Response, ServiceError> {
+    if params.watch {
+        let options = WatchOptions {
+            key: params.prefix,
+            prefix: true,
+            start_revision: params.start_revision,
+        };
+        watch(state, options, &headers).await
+    } else {
+        Ok(scan_values(state, Query(params)).await?.into_response())
+    }
+}
+
+pub async fn scan_values(
+    State(state): State<AppState>,
+    Query(params): Query<ScanParams>,
+) -> Result<Json<ScanPage>, ServiceError> {
+    let limit = params.limit.unwrap_or(DEFAULT_SCAN_LIMIT);
+    if limit == 0 || limit > MAX_SCAN_LIMIT {
+        tracing::debug!("Invalid scan limit: {}", limit);
+        return Err(ServiceError::InvalidArgument(format!(
+            "limit must be between 1 and {MAX_SCAN_LIMIT}"
+        )));
+    }
+    tracing::debug!("Scanning keys with prefix: {}", params.prefix);
+    let page = state
+        .key_value_service
+        .scan(ScanOptions {
+            prefix: params.prefix,
+            start: params.start,
+            end: params.end,
+            cursor: params.cursor,
+            limit,
+        })
+        .await?;
+    tracing::debug!("Scanned {} keys", page.items.len());
+    Ok(Json(page))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct WatchParams {
+    start_revision: Option<u64>,
+}
+
+pub async fn watch_value(
+    state: State<AppState>,
+    Path(key): Path<String>,
+    Query(params): Query<WatchParams>,
+    headers: HeaderMap,
+) -> Result<Response, ServiceError> {
+    let options = WatchOptions {
+        key,
+        prefix: false,
+        start_revision: params.start_revision,
+    };
+    watch(state, options, &headers).await
+}
+
+/// A reconnecting `EventSource` sends the id of the last event it received, which is the
+/// revision of that event, so the watch resumes right after it.
+fn last_event_id(headers: &HeaderMap) -> Result<Option<u64>, &'static str> {
+    let Some(value) = headers.get("last-event-id") else {
+        return Ok(None);
+    };
+    let revision = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or("Last-Event-ID must be a revision")?;
+    Ok(Some(revision))
+}
+
+async fn watch(
+    State(state): State<AppState>,
+    mut options: WatchOptions,
+    headers: &HeaderMap,
+) -> Result<Response, ServiceError> {
+    match last_event_id(headers) {
+        Ok(Some(revision)) => options.start_revision = Some(revision.saturating_add(1)),
+        Ok(None) => {}
+        Err(err) => {
+            tracing::debug!("Invalid watch request: {}", err);
+            return Err(ServiceError::InvalidArgument(err.to_string()));
+        }
+    }
+    tracing::debug!("Watching {:?}", options);
+    let stream = state.key_value_service.watch(options).await?;
+    Ok(Sse::new(sse_events(stream))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+fn sse_events(stream: WatchStream) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    stream.map(|event| {
+        let event = match event {
+            Ok(event) => {
+                let name = match event.kind {
+                    WatchEventKind::Put => "put",
+                    WatchEventKind::Delete => "delete",
+                };
+                sse::Event::default()
+                    .id(event.revision.to_string())
+                    .event(name)
+                    .json_data(&event)
+                    .unwrap_or_else(|err| {
+                        sse::Event::default().event("error").data(err.to_string())
+                    })
+            }
+            Err(err) => {
+                tracing::debug!("Watch failed: {}", err);
+                sse::Event::default().event("error").data(err.to_string())
+            }
+        };
+        Ok(event)
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionPrecondition {
+    key: String,
+    exists: Option<bool>,
+    version: Option<u64>,
+    value: Option<Value>,
+}
+
+impl TryFrom<TransactionPrecondition> for Precondition {
+    type Error = &'static str;
+
+    fn try_from(precondition: TransactionPrecondition) -> Result<Self, Self::Error> {
+        let condition = match (
+            precondition.exists,
+            precondition.version,
+            precondition.value,
+        ) {
+            (Some(exists), None, None) => Condition::Exists(exists),
+            (None, Some(version), None) => Condition::Version(version),
+            (None, None, Some(value)) => Condition::Value(value),
+            _ => return Err("precondition must have exactly one of exists, version or value"),
+        };
+        Ok(Precondition {
+            key: precondition.key,
+            condition,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionOperation {
+    Set {
+        key: String,
+        value: Value,
+        ttl: Option<u64>,
+        expires_at: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+impl TryFrom<TransactionOperation> for Operation {
+    type Error = &'static str;
+
+    fn try_from(operation: TransactionOperation) -> Result<Self, Self::Error> {
+        match operation {
+            TransactionOperation::Set {
+                key,
+                value,
+                ttl,
+                expires_at,
+            } => {
+                if value.is_null() {
+                    return Err("value cannot be null");
+                }
+                writable_key(&key)?;
+                Ok(Operation::Set {
+                    key,
+                    value,
+                    expiry: expiry(ttl, expires_at)?,
+                })
+            }
+            TransactionOperation::Delete { key } => Ok(Operation::Delete { key }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionBody {
+    #[serde(default)]
+    preconditions: Vec<TransactionPrecondition>,
+    operations: Vec<TransactionOperation>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct OperationResult {
+    existed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct TransactionResult {
+    committed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
+    results: Vec<OperationResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed_precondition: Option<usize>,
+}
+
+pub async fn run_transaction(
+    State(state): State<AppState>,
+    Json(body): Json<TransactionBody>,
+) -> Result<(StatusCode, Json<TransactionResult>), ServiceError> {
+    if body.operations.is_empty() {
+        tracing::debug!("Transaction without operations");
+        return Err(ServiceError::InvalidArgument(
+            "a transaction needs at least one operation".to_string(),
+        ));
+    }
+    let parsed = body
+        .preconditions
+        .into_iter()
+        .map(Precondition::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|preconditions| {
+            let operations = body
+                .operations
+                .into_iter()
+                .map(Operation::try_from)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((preconditions, operations))
+        });
+    let (preconditions, operations) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            tracing::debug!("Invalid transaction: {}", err);
+            return Err(ServiceError::InvalidArgument(err.to_string()));
+        }
+    };
+    tracing::debug!(
+        "Running transaction with {} preconditions and {} operations",
+        preconditions.len(),
+        operations.len()
+    );
+    let outcome = state
+        .key_value_service
+        .transaction(preconditions, operations)
+        .await?;
+    let response = match outcome {
+        TransactionOutcome::Committed { version, existed } => {
+            tracing::debug!("Committed transaction at version {}", version);
+            let result = TransactionResult {
+                committed: true,
+                version: Some(version),
+                results: existed
+                    .into_iter()
+                    .map(|existed| OperationResult { existed })
+                    .collect(),
+                failed_precondition: None,
+            };
+            (StatusCode::OK, Json(result))
+        }
+        TransactionOutcome::Failed { precondition } => {
+            tracing::debug!("Transaction precondition {} failed", precondition);
+            let result = TransactionResult {
+                committed: false,
+                version: None,
+                results: Vec::new(),
+                failed_precondition: Some(precondition),
+            };
+            (StatusCode::CONFLICT, Json(result))
+        }
+    };
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchRequestItem {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: Value,
+        ttl: Option<u64>,
+        expires_at: Option<u64>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+enum BatchItem {
+    Get(String),
+    Set(PutItem),
+    Delete(String),
+}
+
+impl TryFrom<BatchRequestItem> for BatchItem {
+    type Error = &'static str;
+
+    fn try_from(item: BatchRequestItem) -> Result<Self, Self::Error> {
+        match item {
+            BatchRequestItem::Get { key } => Ok(BatchItem::Get(key)),
+            BatchRequestItem::Set {
+                key,
+                value,
+                ttl,
+                expires_at,
+            } => {
+                if value.is_null() {
+                    return Err("value cannot be null");
+                }
+                writable_key(&key)?;
+                Ok(BatchItem::Set(PutItem {
+                    key,
+                    value,
+                    expiry: expiry(ttl, expires_at)?,
+                }))
+            }
+            BatchRequestItem::Delete { key } => Ok(BatchItem::Delete(key)),
+        }
+    }
+}
+
+/// Result of one item of a batch, with the status code the single-key endpoint would return.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BatchItemResult {
+    pub(super) status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) error: Option<BatchItemError>,
+}
+
+/// Why an item failed, in the format of the error body of the single-key endpoints.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BatchItemError {
+    code: &'static str,
+    message: String,
+}
+
+impl BatchItemResult {
+    pub(super) fn status(status: StatusCode) -> Self {
+        Self {
+            status: status.as_u16(),
+            value: None,
+            version: None,
+            error: None,
+        }
+    }
+
+    fn failed(err: &ServiceError) -> Self {
+        Self {
+            error: Some(BatchItemError {
+                code: err.error_code(),
+                message: err.message(),
+            }),
+            ..Self::status(err.status_code())
+        }
+    }
+}
+
+impl From<Option<VersionedValue>> for BatchItemResult {
+    fn from(value: Option<VersionedValue>) -> Self {
+        match value {
+            Some(value) => Self {
+                value: Some(value.value),
+                version: Some(value.version),
+                ..Self::status(StatusCode::OK)
+            },
+            None => Self::status(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+impl From<PutOutcome> for BatchItemResult {
+    fn from(outcome: PutOutcome) -> Self {
+        match outcome {
+            PutOutcome::Written { updated, version } => Self {
+                version: Some(version),
+                ..Self::status(if updated {
+                    StatusCode::NO_CONTENT
+                } else {
+                    StatusCode::CREATED
+                })
+            },
+            PutOutcome::ConditionFailed => Self::status(StatusCode::PRECONDITION_FAILED),
+        }
+    }
+}
+
+/// Runs a list of gets, sets and deletes in order. Consecutive items of the same kind are
+/// sent to the backend as a single batch, so each run of sets or deletes is atomic.
+///
+/// The runs before a failed one stay applied, so instead of failing the request the items of
+/// the failed run report its error and the items after it `424 Failed Dependency`.
+pub async fn run_batch(
+    State(state): State<AppState>,
+    Json(items): Json<Vec<BatchRequestItem>>,
+) -> Result<Json<Vec<BatchItemResult>>, ServiceError> {
+    if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+        tracing::debug!("Invalid batch size: {}", items.len());
+        return Err(ServiceError::InvalidArgument(format!(
+            "a batch must have between 1 and {MAX_BATCH_SIZE} items"
+        )));
+    }
+    let items = match items
+        .into_iter()
+        .map(BatchItem::try_from)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(items) => items,
+        Err(err) => {
+            tracing::debug!("Invalid batch: {}", err);
+            return Err(ServiceError::InvalidArgument(err.to_string()));
+        }
+    };
+    tracing::debug!("Running batch of {} items", items.len());
+
+    let service = &state.key_value_service;
+    let mut results = Vec::with_capacity(items.len());
+    let mut items = items.into_iter().peekable();
+    while let Some(item) = items.next() {
+        let (len, run) = match item {
+            BatchItem::Get(key) => {
+                let mut keys = vec![key];
+                while let Some(BatchItem::Get(key)) =
+                    items.next_if(|item| matches!(item, BatchItem::Get(_)))
+                {
+                    keys.push(key);
+                }
+                let len = keys.len();
+                let run = service.get_values(keys).await.map(|values| {
+                    values
+                        .into_iter()
+                        .map(BatchItemResult::from)
+                        .collect::<Vec<_>>()
+                });
+                (len, run)
+            }
+            BatchItem::Set(item) => {
+                let mut put_items = vec![item];
+                while let Some(BatchItem::Set(item)) =
+                    items.next_if(|item| matches!(item, BatchItem::Set(_)))
+                {
+                    put_items.push(item);
+                }
+                let len = put_items.len();
+                let run = service.put_values(put_items).await.map(|outcomes| {
+                    outcomes
+                        .into_iter()
+                        .map(BatchItemResult::from)
+                        .collect::<Vec<_>>()
+                });
+                (len, run)
+            }
+            BatchItem::Delete(key) => {
+                let mut keys = vec![key];
+                while let Some(BatchItem::Delete(key)) =
+                    items.next_if(|item| matches!(item, BatchItem::Delete(_)))
+                {
+                    keys.push(key);
+                }
+                let len = keys.len();
+                let run = service.delete_values(keys).await.map(|deleted| {
+                    deleted
+                        .into_iter()
+                        .map(|deleted| {
+                            BatchItemResult::status(if deleted {
+                                StatusCode::OK
+                            } else {
+                                StatusCode::NOT_FOUND
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                });
+                (len, run)
+            }
+        };
+        match run {
+            Ok(run) => results.extend(run),
+            Err(err) => {
+                tracing::warn!("Batch failed at item {}: {}", results.len(), err);
+                results.extend(std::iter::repeat_n(BatchItemResult::failed(&err), len));
+                results.extend(
+                    items
+                        .by_ref()
+                        .map(|_| BatchItemResult::status(StatusCode::FAILED_DEPENDENCY)),
+                );
+            }
+        }
+    }
+    Ok(Json(results))
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
     use mockall::predicate::eq;
 
-    use crate::services::key_value_service::MockKeyValueService;
+    use crate::services::key_value_service::{KeyValue, MockKeyValueService, VersionedValue};
 
     use super::*;
 
@@ -81,14 +732,20 @@ mod tests {
         key_value_service
             .expect_get_value()
             .with(eq(key.clone()))
-            .returning(move |_| Ok(Some(cloned_value.clone())));
+            .returning(move |_| {
+                Ok(Some(VersionedValue {
+                    value: cloned_value.clone(),
+                    version: 7,
+                }))
+            });
 
         let state = AppState {
             key_value_service: Arc::new(key_value_service),
         };
 
-        let (status, response) = get_value(State(state), Path(key)).await.unwrap();
+        let (status, headers, response) = get_value(State(state), Path(key)).await.unwrap();
         assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ETAG], "\"7\"");
         assert_eq!(response.0, Some(value));
     }
 
@@ -100,17 +757,33 @@ mod tests {
         let mut key_value_service = MockKeyValueService::new();
         key_value_service
             .expect_put_value()
-            .with(eq(key.clone()), eq(value.clone()))
-            .returning(move |_, _| Ok(true));
+            .with(
+                eq(key.clone()),
+                eq(value.clone()),
+                eq(PutOptions::default()),
+            )
+            .returning(move |_, _, _| {
+                Ok(PutOutcome::Written {
+                    updated: true,
+                    version: 2,
+                })
+            });
 
         let state = AppState {
             key_value_service: Arc::new(key_value_service),
         };
 
-        let status = put_value(State(state), Path(key), Json(value))
-            .await
-            .unwrap();
+        let (status, headers) = put_value(
+            State(state),
+            Path(key),
+            Query(Default::default()),
+            HeaderMap::new(),
+            Json(value),
+        )
+        .await
+        .unwrap();
         assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(headers[header::ETAG], "\"2\"");
     }
 
     #[tokio::test]
@@ -145,7 +818,7 @@ mod tests {
             key_value_service: Arc::new(key_value_service),
         };
 
-        let (status, response) = get_value(State(state), Path(key)).await.unwrap();
+        let (status, _, response) = get_value(State(state), Path(key)).await.unwrap();
         assert_eq!(status, StatusCode::NOT_FOUND);
         assert_eq!(response.0, None);
     }
@@ -176,17 +849,33 @@ mod tests {
         let mut key_value_service = MockKeyValueService::new();
         key_value_service
             .expect_put_value()
-            .with(eq(key.clone()), eq(value.clone()))
-            .returning(move |_, _| Ok(false));
+            .with(
+                eq(key.clone()),
+                eq(value.clone()),
+                eq(PutOptions::default()),
+            )
+            .returning(move |_, _, _| {
+                Ok(PutOutcome::Written {
+                    updated: false,
+                    version: 1,
+                })
+            });
 
         let state = AppState {
             key_value_service: Arc::new(key_value_service),
         };
 
-        let status = put_value(State(state), Path(key), Json(value))
-            .await
-            .unwrap();
+        let (status, headers) = put_value(
+            State(state),
+            Path(key),
+            Query(Default::default()),
+            HeaderMap::new(),
+            Json(value),
+        )
+        .await
+        .unwrap();
         assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(headers[header::ETAG], "\"1\"");
     }
 
     #[tokio::test]
@@ -198,9 +887,587 @@ mod tests {
             key_value_service: Arc::new(MockKeyValueService::new()),
         };
 
-        let status = put_value(State(state), Path(key), Json(value))
+        let err = put_value(
+            State(state),
+            Path(key),
+            Query(Default::default()),
+            HeaderMap::new(),
+            Json(value),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_put_value_with_ttl() {
+        let key = "key".to_string();
+        let value = Value::String("value".to_string());
+
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_put_value()
+            .with(
+                eq(key.clone()),
+                eq(value.clone()),
+                eq(PutOptions {
+                    expiry: Some(Expiry::After(Duration::from_secs(60))),
+                    condition: None,
+                }),
+            )
+            .returning(move |_, _, _| {
+                Ok(PutOutcome::Written {
+                    updated: false,
+                    version: 1,
+                })
+            });
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let params = PutValueParams {
+            ttl: Some(60),
+            expires_at: None,
+        };
+        let (status, _) = put_value(
+            State(state),
+            Path(key),
+            Query(params),
+            HeaderMap::new(),
+            Json(value),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_put_value_invalid_expiry() {
+        let key = "key".to_string();
+        let value = Value::String("value".to_string());
+
+        let state = AppState {
+            key_value_service: Arc::new(MockKeyValueService::new()),
+        };
+
+        for params in [
+            PutValueParams {
+                ttl: Some(0),
+                expires_at: None,
+            },
+            PutValueParams {
+                ttl: None,
+                expires_at: Some(1),
+            },
+            PutValueParams {
+                ttl: Some(60),
+                expires_at: Some(u32::MAX as u64),
+            },
+        ] {
+            let err = put_value(
+                State(state.clone()),
+                Path(key.clone()),
+                Query(params),
+                HeaderMap::new(),
+                Json(value.clone()),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_put_condition() {
+        let headers = |pairs: &[(header::HeaderName, &'static str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+                .collect::<HeaderMap>()
+        };
+        assert_eq!(put_condition(&headers(&[])), Ok(None));
+        assert_eq!(
+            put_condition(&headers(&[(header::IF_MATCH, "\"3\"")])),
+            Ok(Some(Condition::Version(3)))
+        );
+        assert_eq!(
+            put_condition(&headers(&[(header::IF_MATCH, "*")])),
+            Ok(Some(Condition::Exists(true)))
+        );
+        assert_eq!(
+            put_condition(&headers(&[(header::IF_NONE_MATCH, "*")])),
+            Ok(Some(Condition::Exists(false)))
+        );
+        for invalid in [
+            headers(&[(header::IF_MATCH, "3")]),
+            headers(&[(header::IF_MATCH, "W/\"3\"")]),
+            headers(&[(header::IF_MATCH, "\"1\", \"2\"")]),
+            headers(&[(header::IF_NONE_MATCH, "\"3\"")]),
+            headers(&[(header::IF_MATCH, "*"), (header::IF_NONE_MATCH, "*")]),
+        ] {
+            assert!(put_condition(&invalid).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_value_precondition_failed() {
+        let key = "key".to_string();
+        let value = Value::String("value".to_string());
+
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_put_value()
+            .with(
+                eq(key.clone()),
+                eq(value.clone()),
+                eq(PutOptions {
+                    expiry: None,
+                    condition: Some(Condition::Version(3)),
+                }),
+            )
+            .returning(move |_, _, _| Ok(PutOutcome::ConditionFailed));
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MATCH, HeaderValue::from_static("\"3\""));
+        let (status, _) = put_value(
+            State(state),
+            Path(key),
+            Query(Default::default()),
+            headers,
+            Json(value),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_scan_values() {
+        let page = ScanPage {
+            items: vec![KeyValue {
+                key: "a/1".to_string(),
+                value: Value::String("value".to_string()),
+            }],
+            next_cursor: Some("cursor".to_string()),
+        };
+
+        let mut key_value_service = MockKeyValueService::new();
+        let cloned_page = page.clone();
+        key_value_service
+            .expect_scan()
+            .with(eq(ScanOptions {
+                prefix: "a/".to_string(),
+                limit: DEFAULT_SCAN_LIMIT,
+                ..Default::default()
+            }))
+            .returning(move |_| Ok(cloned_page.clone()));
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let params = ScanParams {
+            prefix: "a/".to_string(),
+            ..Default::default()
+        };
+        let response = scan_values(State(state), Query(params)).await.unwrap();
+        assert_eq!(response.0, page);
+    }
+
+    #[tokio::test]
+    async fn test_scan_values_invalid_limit() {
+        let state = AppState {
+            key_value_service: Arc::new(MockKeyValueService::new()),
+        };
+
+        for limit in [0, MAX_SCAN_LIMIT + 1] {
+            let params = ScanParams {
+                limit: Some(limit),
+                ..Default::default()
+            };
+            let err = scan_values(State(state.clone()), Query(params))
+                .await
+                .unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    fn transaction_body(body: Value) -> TransactionBody {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_transaction() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_transaction()
+            .with(
+                eq(vec![
+                    Precondition {
+                        key: "a".to_string(),
+                        condition: Condition::Version(1),
+                    },
+                    Precondition {
+                        key: "b".to_string(),
+                        condition: Condition::Value(serde_json::json!({"b": 1})),
+                    },
+                ]),
+                eq(vec![
+                    Operation::Set {
+                        key: "a".to_string(),
+                        value: serde_json::json!("a"),
+                        expiry: Some(Expiry::After(Duration::from_secs(60))),
+                    },
+                    Operation::Delete {
+                        key: "b".to_string(),
+                    },
+                ]),
+            )
+            .returning(|_, _| {
+                Ok(TransactionOutcome::Committed {
+                    version: 2,
+                    existed: vec![true, true],
+                })
+            });
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let body = transaction_body(serde_json::json!({
+            "preconditions": [
+                {"key": "a", "version": 1},
+                {"key": "b", "value": {"b": 1}},
+            ],
+            "operations": [
+                {"set": {"key": "a", "value": "a", "ttl": 60}},
+                {"delete": {"key": "b"}},
+            ],
+        }));
+        let (status, response) = run_transaction(State(state), Json(body)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::to_value(response.0).unwrap(),
+            serde_json::json!({
+                "committed": true,
+                "version": 2,
+                "results": [{"existed": true}, {"existed": true}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_transaction_conflict() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_transaction()
+            .returning(|_, _| Ok(TransactionOutcome::Failed { precondition: 0 }));
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let body = transaction_body(serde_json::json!({
+            "preconditions": [{"key": "a", "exists": false}],
+            "operations": [{"set": {"key": "a", "value": 1}}],
+        }));
+        let (status, response) = run_transaction(State(state), Json(body)).await.unwrap();
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            serde_json::to_value(response.0).unwrap(),
+            serde_json::json!({
+                "committed": false,
+                "results": [],
+                "failed_precondition": 0,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_transaction_invalid() {
+        let state = AppState {
+            key_value_service: Arc::new(MockKeyValueService::new()),
+        };
+
+        for body in [
+            serde_json::json!({"operations": []}),
+            serde_json::json!({"operations": [{"set": {"key": "a", "value": null}}]}),
+            serde_json::json!({"operations": [{"set": {"key": "a", "value": 1, "ttl": 0}}]}),
+            serde_json::json!({"operations": [{"set": {"key": "_batch", "value": 1}}]}),
+            serde_json::json!({
+                "preconditions": [{"key": "a"}],
+                "operations": [{"delete": {"key": "a"}}],
+            }),
+            serde_json::json!({
+                "preconditions": [{"key": "a", "exists": true, "version": 1}],
+                "operations": [{"delete": {"key": "a"}}],
+            }),
+        ] {
+            let err = run_transaction(State(state.clone()), Json(transaction_body(body)))
+                .await
+                .unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_batch() {
+        let mut key_value_service = MockKeyValueService::new();
+        let mut sequence = mockall::Sequence::new();
+        key_value_service
+            .expect_put_values()
+            .with(eq(vec![
+                PutItem {
+                    key: "a".to_string(),
+                    value: serde_json::json!(1),
+                    expiry: None,
+                },
+                PutItem {
+                    key: "b".to_string(),
+                    value: serde_json::json!(2),
+                    expiry: Some(Expiry::After(Duration::from_secs(60))),
+                },
+            ]))
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| {
+                Ok(vec![
+                    PutOutcome::Written {
+                        updated: true,
+                        version: 3,
+                    },
+                    PutOutcome::Written {
+                        updated: false,
+                        version: 3,
+                    },
+                ])
+            });
+        key_value_service
+            .expect_get_values()
+            .with(eq(vec!["a".to_string(), "c".to_string()]))
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| {
+                Ok(vec![
+                    Some(VersionedValue {
+                        value: serde_json::json!(1),
+                        version: 3,
+                    }),
+                    None,
+                ])
+            });
+        key_value_service
+            .expect_delete_values()
+            .with(eq(vec!["b".to_string()]))
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_| Ok(vec![true]));
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let items = serde_json::from_value(serde_json::json!([
+            {"set": {"key": "a", "value": 1}},
+            {"set": {"key": "b", "value": 2, "ttl": 60}},
+            {"get": {"key": "a"}},
+            {"get": {"key": "c"}},
+            {"delete": {"key": "b"}},
+        ]))
+        .unwrap();
+        let response = run_batch(State(state), Json(items)).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(response.0).unwrap(),
+            serde_json::json!([
+                {"status": 204, "version": 3},
+                {"status": 201, "version": 3},
+                {"status": 200, "value": 1, "version": 3},
+                {"status": 404},
+                {"status": 200},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_partial_failure() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_put_values()
+            .times(1)
+            .returning(|_| {
+                Ok(vec![PutOutcome::Written {
+                    updated: false,
+                    version: 1,
+                }])
+            });
+        key_value_service
+            .expect_delete_values()
+            .times(1)
+            .returning(|_| Err(tonic::Status::unavailable("backend is down").into()));
+        key_value_service.expect_get_values().never();
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let items = serde_json::from_value(serde_json::json!([
+            {"set": {"key": "a", "value": 1}},
+            {"delete": {"key": "b"}},
+            {"delete": {"key": "c"}},
+            {"get": {"key": "a"}},
+        ]))
+        .unwrap();
+        let response = run_batch(State(state), Json(items)).await.unwrap();
+        let error = serde_json::json!({"code": "unavailable", "message": "backend is down"});
+        assert_eq!(
+            serde_json::to_value(response.0).unwrap(),
+            serde_json::json!([
+                {"status": 201, "version": 1},
+                {"status": 503, "error": error},
+                {"status": 503, "error": error},
+                {"status": 424},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_batch_invalid() {
+        let state = AppState {
+            key_value_service: Arc::new(MockKeyValueService::new()),
+        };
+
+        let too_many = vec![serde_json::json!({"get": {"key": "a"}}); MAX_BATCH_SIZE + 1];
+        for items in [
+            serde_json::json!([]),
+            serde_json::json!(too_many),
+            serde_json::json!([{"set": {"key": "a", "value": null}}]),
+            serde_json::json!([{"set": {"key": "a", "value": 1, "ttl": 0}}]),
+            serde_json::json!([{"set": {"key": "_txn", "value": 1}}]),
+        ] {
+            let items = serde_json::from_value(items).unwrap();
+            let err = run_batch(State(state.clone()), Json(items))
+                .await
+                .unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_value() {
+        use axum::body::to_bytes;
+
+        use crate::services::key_value_service::WatchEvent;
+
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_watch()
+            .with(eq(WatchOptions {
+                key: "key".to_string(),
+                prefix: false,
+                start_revision: Some(5),
+            }))
+            .returning(|_| {
+                let events = vec![
+                    Ok(WatchEvent {
+                        kind: WatchEventKind::Put,
+                        key: "key".to_string(),
+                        value: Some(serde_json::json!("value")),
+                        revision: 5,
+                    }),
+                    Ok(WatchEvent {
+                        kind: WatchEventKind::Delete,
+                        key: "key".to_string(),
+                        value: None,
+                        revision: 6,
+                    }),
+                ];
+                Ok(Box::pin(tokio_stream::iter(events)) as WatchStream)
+            });
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("last-event-id", HeaderValue::from_static("4"));
+        let response = watch_value(
+            State(state),
+            Path("key".to_string()),
+            Query(Default::default()),
+            headers,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            concat!(
+                "id: 5\nevent: put\n",
+                "data: {\"type\":\"put\",\"key\":\"key\",\"value\":\"value\",\"revision\":5}\n\n",
+                "id: 6\nevent: delete\n",
+                "data: {\"type\":\"delete\",\"key\":\"key\",\"revision\":6}\n\n",
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_values_watch() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_watch()
+            .with(eq(WatchOptions {
+                key: "a/".to_string(),
+                prefix: true,
+                start_revision: Some(2),
+            }))
+            .returning(|_| Ok(Box::pin(tokio_stream::empty()) as WatchStream));
+
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+
+        let params = ScanParams {
+            prefix: "a/".to_string(),
+            watch: true,
+            start_revision: Some(2),
+            ..Default::default()
+        };
+        let response = list_values(State(state), Query(params), HeaderMap::new())
             .await
             .unwrap();
-        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch_invalid_last_event_id() {
+        let state = AppState {
+            key_value_service: Arc::new(MockKeyValueService::new()),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("last-event-id", HeaderValue::from_static("abc"));
+        let err = watch_value(
+            State(state),
+            Path("key".to_string()),
+            Query(Default::default()),
+            headers,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ServiceError,
+    services::{shard_service::ShardService, sharding::RebalanceReport},
+};
+
+#[derive(Clone)]
+pub struct ShardState {
+    pub shard_service: Arc<dyn ShardService>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ShardList {
+    shards: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddShardRequest {
+    address: String,
+}
+
+pub async fn list_shards(State(state): State<ShardState>) -> Json<ShardList> {
+    Json(ShardList {
+        shards: state.shard_service.shards().await,
+    })
+}
+
+pub async fn add_shard(
+    State(state): State<ShardState>,
+    Json(body): Json<AddShardRequest>,
+) -> Result<Json<RebalanceReport>, ServiceError> {
+    let address = body.address.trim();
+    if address.is_empty() || address.contains('/') {
+        tracing::debug!("Invalid shard address: {:?}", body.address);
+        return Err(ServiceError::InvalidArgument(
+            "address must be a host and port".to_string(),
+        ));
+    }
+    tracing::info!("Adding shard {}", address);
+    let report = state.shard_service.add_shard(address).await?;
+    tracing::info!("Added shard {}, moved {} keys", address, report.moved);
+    Ok(Json(report))
+}
+
+pub async fn remove_shard(
+    State(state): State<ShardState>,
+    Path(address): Path<String>,
+) -> Result<Json<RebalanceReport>, ServiceError> {
+    tracing::info!("Removing shard {}", address);
+    let report = state.shard_service.remove_shard(&address).await?;
+    tracing::info!("Removed shard {}, moved {} keys", address, report.moved);
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use mockall::predicate::eq;
+
+    use crate::services::shard_service::MockShardService;
+
+    use super::*;
+
+    fn report() -> RebalanceReport {
+        RebalanceReport {
+            shards: vec!["127.0.0.1:8081".to_string(), "127.0.0.1:8082".to_string()],
+            moved: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_shard() {
+        let mut shard_service = MockShardService::new();
+        shard_service
+            .expect_add_shard()
+            .with(eq("127.0.0.1:8082"))
+            .times(1)
+            .returning(|_| Ok(report()));
+        let state = ShardState {
+            shard_service: Arc::new(shard_service),
+        };
+
+        let body = AddShardRequest {
+            address: " 127.0.0.1:8082 ".to_string(),
+        };
+        let response = add_shard(State(state.clone()), Json(body)).await.unwrap();
+        assert_eq!(response.0, report());
+
+        let body = AddShardRequest {
+            address: "".to_string(),
+        };
+        let err = add_shard(State(state), Json(body)).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_remove_shard() {
+        let mut shard_service = MockShardService::new();
+        shard_service
+            .expect_remove_shard()
+            .with(eq("127.0.0.1:8083"))
+            .times(1)
+            .returning(|_| Ok(report()));
+        let state = ShardState {
+            shard_service: Arc::new(shard_service),
+        };
+
+        let response = remove_shard(State(state), Path("127.0.0.1:8083".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(response.0, report());
+    }
+}
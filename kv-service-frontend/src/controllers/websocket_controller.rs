@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::mpsc, task::AbortHandle};
+use tokio_stream::StreamExt;
+
+use crate::{
+    error::ServiceError,
+    services::key_value_service::{PutOptions, WatchEvent, WatchEventKind, WatchOptions},
+};
+
+use super::{
+    key_value_controller::{expiry, writable_key, BatchItemResult},
+    AppState,
+};
+
+const EVENT_CHANNEL_SIZE: usize = 128;
+
+/// A command sent by the client. `id` is chosen by the client and is echoed in the reply;
+/// for `subscribe` it also identifies the subscription.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientMessage {
+    Get {
+        id: u64,
+        key: String,
+    },
+    Put {
+        id: u64,
+        key: String,
+        value: Value,
+        ttl: Option<u64>,
+        expires_at: Option<u64>,
+    },
+    Delete {
+        id: u64,
+        key: String,
+    },
+    Subscribe {
+        id: u64,
+        key: String,
+        #[serde(default)]
+        prefix: bool,
+        start_revision: Option<u64>,
+    },
+    Unsubscribe {
+        id: u64,
+        subscription: u64,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerMessage {
+    Result(Reply),
+    Event(SubscriptionEvent),
+    Error(ErrorReply),
+}
+
+/// Reply to a command, with the status code the matching REST endpoint would return.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Reply {
+    id: u64,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SubscriptionEvent {
+    subscription: u64,
+    #[serde(rename = "type")]
+    kind: WatchEventKind,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<Value>,
+    revision: u64,
+}
+
+/// Sent when a message can't be parsed, a command fails or a subscription ends with an
+/// error. `id` is missing if the message couldn't be parsed.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ErrorReply {
+    id: Option<u64>,
+    message: String,
+}
+
+impl ServerMessage {
+    fn result(id: u64, result: BatchItemResult) -> Self {
+        Self::Result(Reply {
+            id,
+            status: result.status,
+            value: result.value,
+            version: result.version,
+        })
+    }
+
+    fn status(id: u64, status: StatusCode) -> Self {
+        Self::result(id, BatchItemResult::status(status))
+    }
+
+    fn event(subscription: u64, event: WatchEvent) -> Self {
+        Self::Event(SubscriptionEvent {
+            subscription,
+            kind: event.kind,
+            key: event.key,
+            value: event.value,
+            revision: event.revision,
+        })
+    }
+
+    fn error(id: Option<u64>, message: impl ToString) -> Self {
+        Self::Error(ErrorReply {
+            id,
+            message: message.to_string(),
+        })
+    }
+}
+
+pub async fn websocket(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(|socket| handle_socket(socket, state))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState) {
+    tracing::debug!("WebSocket connected");
+    let (events_tx, mut events_rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+    let (finished_tx, mut finished_rx) = mpsc::unbounded_channel();
+    let mut session = Session::new(state, events_tx, finished_tx);
+    loop {
+        let message = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => session.handle_text(&text).await,
+                Some(Ok(Message::Binary(_))) => {
+                    ServerMessage::error(None, "binary messages are not supported")
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // Pings are answered by axum.
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Err(err)) => {
+                    tracing::debug!("WebSocket failed: {}", err);
+                    break;
+                }
+            },
+            Some(message) = events_rx.recv() => message,
+            Some(finished) = finished_rx.recv() => {
+                session.remove_finished(finished);
+                continue;
+            }
+        };
+        let text = serde_json::to_string(&message).expect("server messages are serializable");
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+    tracing::debug!("WebSocket disconnected");
+}
+
+/// A subscription that ended on its own, identified by its id and by the number the session
+/// gave it, since the client may already have reused the id.
+type FinishedSubscription = (u64, u64);
+
+/// State of one connection. Dropping it ends all of its subscriptions.
+struct Session {
+    state: AppState,
+    events: mpsc::Sender<ServerMessage>,
+    finished: mpsc::UnboundedSender<FinishedSubscription>,
+    subscriptions: HashMap<u64, (u64, AbortHandle)>,
+    subscription_count: u64,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for (_, subscription) in self.subscriptions.values() {
+            subscription.abort();
+        }
+    }
+}
+
+impl Session {
+    fn new(
+        state: AppState,
+        events: mpsc::Sender<ServerMessage>,
+        finished: mpsc::UnboundedSender<FinishedSubscription>,
+    ) -> Self {
+        Self {
+            state,
+            events,
+            finished,
+            subscriptions: HashMap::new(),
+            subscription_count: 0,
+        }
+    }
+
+    /// Forgets a subscription whose stream ended or failed, so that its id can be used again.
+    fn remove_finished(&mut self, (id, number): FinishedSubscription) {
+        if matches!(self.subscriptions.get(&id), Some((current, _)) if *current == number) {
+            tracing::debug!("Subscription {} ended", id);
+            self.subscriptions.remove(&id);
+        }
+    }
+
+    async fn handle_text(&mut self, text: &str) -> ServerMessage {
+        match serde_json::from_str(text) {
+            Ok(message) => self.handle(message).await,
+            Err(err) => {
+                tracing::debug!("Invalid WebSocket message: {}", err);
+                ServerMessage::error(None, err)
+            }
+        }
+    }
+
+    async fn handle(&mut self, message: ClientMessage) -> ServerMessage {
+        let id = match message {
+            ClientMessage::Get { id, .. }
+            | ClientMessage::Put { id, .. }
+            | ClientMessage::Delete { id, .. }
+            | ClientMessage::Subscribe { id, .. }
+            | ClientMessage::Unsubscribe { id, .. } => id,
+        };
+        match self.run(message).await {
+            Ok(reply) => reply,
+            Err(err) => ServerMessage::error(Some(id), err),
+        }
+    }
+
+    async fn run(&mut self, message: ClientMessage) -> Result<ServerMessage, ServiceError> {
+        let service = &self.state.key_value_service;
+        let reply = match message {
+            ClientMessage::Get { id, key } => {
+                tracing::debug!("Getting value for key: {}", key);
+                ServerMessage::result(id, service.get_value(&key).await?.into())
+            }
+            ClientMessage::Put {
+                id,
+                key,
+                value,
+                ttl,
+                expires_at,
+            } => {
+                if value.is_null() {
+                    return Ok(ServerMessage::error(Some(id), "value cannot be null"));
+                }
+                if let Err(err) = writable_key(&key) {
+                    return Ok(ServerMessage::error(Some(id), err));
+                }
+                let expiry = match expiry(ttl, expires_at) {
+                    Ok(expiry) => expiry,
+                    Err(err) => return Ok(ServerMessage::error(Some(id), err)),
+                };
+                tracing::debug!("Putting value {} for key {}", value, key);
+                let options = PutOptions {
+                    expiry,
+                    condition: None,
+                };
+                ServerMessage::result(id, service.put_value(&key, value, options).await?.into())
+            }
+            ClientMessage::Delete { id, key } => {
+                tracing::debug!("Deleting value for key: {}", key);
+                let status = if service.delete_value(&key).await? {
+                    StatusCode::OK
+                } else {
+                    StatusCode::NOT_FOUND
+                };
+                ServerMessage::status(id, status)
+            }
+            ClientMessage::Subscribe {
+                id,
+                key,
+                prefix,
+                start_revision,
+            } => {
+                if self.subscriptions.contains_key(&id) {
+                    return Ok(ServerMessage::status(id, StatusCode::CONFLICT));
+                }
+                let options = WatchOptions {
+                    key,
+                    prefix,
+                    start_revision,
+                };
+                tracing::debug!("Subscribing {} to {:?}", id, options);
+                let mut stream = service.watch(options).await?;
+                let events = self.events.clone();
+                let finished = self.finished.clone();
+                self.subscription_count += 1;
+                let number = self.subscription_count;
+                let task = tokio::spawn(async move {
+                    while let Some(event) = stream.next().await {
+                        let (message, failed) = match event {
+                            Ok(event) => (ServerMessage::event(id, event), false),
+                            Err(err) => (ServerMessage::error(Some(id), err), true),
+                        };
+                        if events.send(message).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                    let _ = finished.send((id, number));
+                });
+                self.subscriptions.insert(id, (number, task.abort_handle()));
+                ServerMessage::status(id, StatusCode::OK)
+            }
+            ClientMessage::Unsubscribe { id, subscription } => {
+                tracing::debug!("Unsubscribing {}", subscription);
+                let status = match self.subscriptions.remove(&subscription) {
+                    Some((_, task)) => {
+                        task.abort();
+                        StatusCode::OK
+                    }
+                    None => StatusCode::NOT_FOUND,
+                };
+                ServerMessage::status(id, status)
+            }
+        };
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use mockall::predicate::eq;
+    use serde_json::json;
+
+    use crate::services::key_value_service::{
+        MockKeyValueService, PutOutcome, VersionedValue, WatchStream,
+    };
+
+    use super::*;
+
+    fn session(
+        key_value_service: MockKeyValueService,
+    ) -> (
+        Session,
+        mpsc::Receiver<ServerMessage>,
+        mpsc::UnboundedReceiver<FinishedSubscription>,
+    ) {
+        let state = AppState {
+            key_value_service: Arc::new(key_value_service),
+        };
+        let (events_tx, events_rx) = mpsc::channel(EVENT_CHANNEL_SIZE);
+        let (finished_tx, finished_rx) = mpsc::unbounded_channel();
+        (
+            Session::new(state, events_tx, finished_tx),
+            events_rx,
+            finished_rx,
+        )
+    }
+
+    #[test]
+    fn test_message_format() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"put": {"id": 1, "key": "a", "value": "b", "ttl": 60}}"#)
+                .unwrap();
+        assert_eq!(
+            message,
+            ClientMessage::Put {
+                id: 1,
+                key: "a".to_string(),
+                value: json!("b"),
+                ttl: Some(60),
+                expires_at: None,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(ServerMessage::event(
+                2,
+                WatchEvent {
+                    kind: WatchEventKind::Delete,
+                    key: "a".to_string(),
+                    value: None,
+                    revision: 3,
+                }
+            ))
+            .unwrap(),
+            json!({"event": {"subscription": 2, "type": "delete", "key": "a", "revision": 3}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_put_value()
+            .with(
+                eq("a"),
+                eq(json!("b")),
+                eq(PutOptions {
+                    expiry: None,
+                    condition: None,
+                }),
+            )
+            .returning(|_, _, _| {
+                Ok(PutOutcome::Written {
+                    updated: false,
+                    version: 1,
+                })
+            });
+        key_value_service
+            .expect_get_value()
+            .with(eq("a"))
+            .returning(|_| {
+                Ok(Some(VersionedValue {
+                    value: json!("b"),
+                    version: 1,
+                }))
+            });
+        key_value_service
+            .expect_delete_value()
+            .with(eq("a"))
+            .returning(|_| Ok(false));
+
+        let (mut session, _, _) = session(key_value_service);
+        assert_eq!(
+            serde_json::to_value(
+                session
+                    .handle_text(r#"{"put": {"id": 1, "key": "a", "value": "b"}}"#)
+                    .await
+            )
+            .unwrap(),
+            json!({"result": {"id": 1, "status": 201, "version": 1}})
+        );
+        assert_eq!(
+            serde_json::to_value(
+                session
+                    .handle_text(r#"{"get": {"id": 2, "key": "a"}}"#)
+                    .await
+            )
+            .unwrap(),
+            json!({"result": {"id": 2, "status": 200, "value": "b", "version": 1}})
+        );
+        assert_eq!(
+            serde_json::to_value(
+                session
+                    .handle_text(r#"{"delete": {"id": 3, "key": "a"}}"#)
+                    .await
+            )
+            .unwrap(),
+            json!({"result": {"id": 3, "status": 404}})
+        );
+        assert!(matches!(
+            session.handle_text(r#"{"get": {"key": "a"}}"#).await,
+            ServerMessage::Error(ErrorReply { id: None, .. })
+        ));
+        assert!(matches!(
+            session
+                .handle_text(r#"{"put": {"id": 4, "key": "a", "value": "b", "ttl": 0}}"#)
+                .await,
+            ServerMessage::Error(ErrorReply { id: Some(4), .. })
+        ));
+        assert!(matches!(
+            session
+                .handle_text(r#"{"put": {"id": 5, "key": "_ws", "value": "b"}}"#)
+                .await,
+            ServerMessage::Error(ErrorReply { id: Some(5), .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let (watch_tx, watch_rx) = mpsc::channel(1);
+        let mut watch_rx = Some(watch_rx);
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_watch()
+            .with(eq(WatchOptions {
+                key: "a/".to_string(),
+                prefix: true,
+                start_revision: None,
+            }))
+            .times(1)
+            .returning(move |_| {
+                let events = tokio_stream::wrappers::ReceiverStream::new(watch_rx.take().unwrap());
+                Ok(Box::pin(events) as WatchStream)
+            });
+
+        let (mut session, mut events, _) = session(key_value_service);
+        let subscribe = r#"{"subscribe": {"id": 1, "key": "a/", "prefix": true}}"#;
+        assert_eq!(
+            session.handle_text(subscribe).await,
+            ServerMessage::status(1, StatusCode::OK)
+        );
+        assert_eq!(
+            session.handle_text(subscribe).await,
+            ServerMessage::status(1, StatusCode::CONFLICT)
+        );
+
+        let event = WatchEvent {
+            kind: WatchEventKind::Put,
+            key: "a/1".to_string(),
+            value: Some(json!("b")),
+            revision: 5,
+        };
+        watch_tx.send(Ok(event.clone())).await.unwrap();
+        assert_eq!(events.recv().await, Some(ServerMessage::event(1, event)));
+
+        assert_eq!(
+            session
+                .handle_text(r#"{"unsubscribe": {"id": 2, "subscription": 1}}"#)
+                .await,
+            ServerMessage::status(2, StatusCode::OK)
+        );
+        assert_eq!(
+            session
+                .handle_text(r#"{"unsubscribe": {"id": 3, "subscription": 1}}"#)
+                .await,
+            ServerMessage::status(3, StatusCode::NOT_FOUND)
+        );
+        // The aborted subscription drops the stream.
+        watch_tx.closed().await;
+    }
+
+    #[tokio::test]
+    async fn test_subscription_ends() {
+        let mut key_value_service = MockKeyValueService::new();
+        key_value_service
+            .expect_watch()
+            .times(2)
+            .returning(|_| Ok(Box::pin(tokio_stream::empty()) as WatchStream));
+
+        let (mut session, _, mut finished) = session(key_value_service);
+        let subscribe = r#"{"subscribe": {"id": 1, "key": "a"}}"#;
+        assert_eq!(
+            session.handle_text(subscribe).await,
+            ServerMessage::status(1, StatusCode::OK)
+        );
+        let first = finished.recv().await.unwrap();
+        session.remove_finished(first);
+        assert_eq!(
+            session
+                .handle_text(r#"{"unsubscribe": {"id": 2, "subscription": 1}}"#)
+                .await,
+            ServerMessage::status(2, StatusCode::NOT_FOUND)
+        );
+
+        // The id can be used again, and a late notice about the first subscription doesn't
+        // remove the second one.
+        assert_eq!(
+            session.handle_text(subscribe).await,
+            ServerMessage::status(1, StatusCode::OK)
+        );
+        session.remove_finished(first);
+        assert!(session.subscriptions.contains_key(&1));
+        let second = finished.recv().await.unwrap();
+        session.remove_finished(second);
+        assert!(session.subscriptions.is_empty());
+    }
+}
@@ -4,27 +4,181 @@ use axum::{
     Json,
 };
 use serde_json::json;
+use std::fmt;
+use tonic::Code;
 
 #[derive(Debug)]
-pub struct ServiceError(anyhow::Error);
+pub enum ServiceError {
+    /// The backend failed the request with this status.
+    Backend(Box<tonic::Status>),
+    /// The frontend rejected the request before sending it to the backend.
+    InvalidArgument(String),
+    /// The request failed in the frontend itself.
+    Internal(anyhow::Error),
+}
+
+impl ServiceError {
+    fn code(&self) -> Code {
+        match self {
+            Self::Backend(status) => status.code(),
+            Self::InvalidArgument(_) => Code::InvalidArgument,
+            Self::Internal(_) => Code::Internal,
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self.code() {
+            Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            // A watch asked for revisions that were already compacted away.
+            Code::OutOfRange => StatusCode::GONE,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+            Code::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+            Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+            Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            Code::Ok | Code::Cancelled | Code::Unknown | Code::Internal | Code::DataLoss => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Machine-readable name of the error, which stays the same across releases.
+    pub fn error_code(&self) -> &'static str {
+        match self.code() {
+            Code::Ok => "ok",
+            Code::Cancelled => "cancelled",
+            Code::Unknown => "unknown",
+            Code::InvalidArgument => "invalid_argument",
+            Code::DeadlineExceeded => "deadline_exceeded",
+            Code::NotFound => "not_found",
+            Code::AlreadyExists => "already_exists",
+            Code::PermissionDenied => "permission_denied",
+            Code::ResourceExhausted => "resource_exhausted",
+            Code::FailedPrecondition => "failed_precondition",
+            Code::Aborted => "aborted",
+            Code::OutOfRange => "out_of_range",
+            Code::Unimplemented => "unimplemented",
+            Code::Internal => "internal",
+            Code::Unavailable => "unavailable",
+            Code::DataLoss => "data_loss",
+            Code::Unauthenticated => "unauthenticated",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::Backend(status) => status.message().to_string(),
+            Self::InvalidArgument(message) => message.clone(),
+            Self::Internal(err) => err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend(status) => write!(f, "{}: {}", self.error_code(), status.message()),
+            Self::InvalidArgument(message) => write!(f, "{}: {}", self.error_code(), message),
+            Self::Internal(err) => err.fmt(f),
+        }
+    }
+}
 
 impl IntoResponse for ServiceError {
     fn into_response(self) -> Response {
+        if self.status_code().is_server_error() {
+            tracing::error!("Request failed: {}", self);
+        }
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            self.status_code(),
             Json(json!({
-                "error": self.0.to_string(),
+                "error": {
+                    "code": self.error_code(),
+                    "message": self.message(),
+                },
             })),
         )
             .into_response()
     }
 }
 
+/// Errors that are a `tonic::Status` keep their code, so that backend failures are reported with
+/// a matching HTTP status.
 impl<E> From<E> for ServiceError
 where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        match err.into().downcast::<tonic::Status>() {
+            Ok(status) => Self::Backend(Box::new(status)),
+            Err(err) => Self::Internal(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_status_codes() {
+        let cases = [
+            (
+                tonic::Status::invalid_argument("bad"),
+                StatusCode::BAD_REQUEST,
+            ),
+            (tonic::Status::not_found("missing"), StatusCode::NOT_FOUND),
+            (tonic::Status::out_of_range("compacted"), StatusCode::GONE),
+            (tonic::Status::aborted("conflict"), StatusCode::CONFLICT),
+            (
+                tonic::Status::failed_precondition("changed"),
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (
+                tonic::Status::resource_exhausted("busy"),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                tonic::Status::unavailable("down"),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                tonic::Status::deadline_exceeded("slow"),
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            (
+                tonic::Status::internal("broken"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (status, expected) in cases {
+            assert_eq!(ServiceError::from(status).status_code(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_body() {
+        let response = ServiceError::from(tonic::Status::unavailable("no leader")).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({"error": {"code": "unavailable", "message": "no leader"}})
+        );
+
+        let err = ServiceError::InvalidArgument("limit must be between 1 and 1000".to_string());
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.error_code(), "invalid_argument");
+        assert_eq!(err.message(), "limit must be between 1 and 1000");
+
+        let err = ServiceError::from(anyhow::anyhow!("clock went backwards"));
+        assert_eq!(err.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.error_code(), "internal");
     }
 }
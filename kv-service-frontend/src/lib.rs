@@ -1,62 +1,182 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::BTreeSet, net::SocketAddr, path::Path, sync::Arc};
 
 use crate::key_value_service::key_value_service_client::KeyValueServiceClient;
 use anyhow::Context;
 use axum::Router;
-use axum_server::{
-    accept::DefaultAcceptor,
-    tls_openssl::{OpenSSLAcceptor, OpenSSLConfig},
-    Server,
-};
-use controllers::create_router;
+use axum_server::{accept::DefaultAcceptor, tls_openssl::OpenSSLConfig, Server};
+use controllers::{create_router, create_shard_admin_router, ShardState};
 use either::Either::{self, Left, Right};
-use services::key_value_service::{GrpcKeyValueService, KeyValueServiceGrpcClient};
-use tonic::transport::{Channel, ClientTlsConfig};
+use services::{key_value_service::GrpcKeyValueService, shard_service::GrpcShardService};
+use tls::ClientIdentityAcceptor;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
 
-pub mod key_value_service {
-    tonic::include_proto!("keyvalueservice");
-}
+pub use services::{
+    key_value_service::KeyValueServiceGrpcClient,
+    sharding::{HashRing, RebalanceReport, ShardLayout, ShardedClient},
+};
+
+pub use kv_service_proto::key_value_service;
 
 mod controllers;
 mod error;
 mod services;
-mod utils;
+pub mod tls;
 
-type EitherHttpsOrHttpServer = Either<Server<OpenSSLAcceptor>, Server<DefaultAcceptor>>;
+type EitherHttpsOrHttpServer = Either<Server<ClientIdentityAcceptor>, Server<DefaultAcceptor>>;
 
-pub async fn create_grpc_client(
-    grpc_server_address: &str,
+pub type ShardedGrpcClient = ShardedClient<KeyValueServiceGrpcClient>;
+
+fn grpc_endpoint(
+    address: &str,
     client_tls_config: Option<ClientTlsConfig>,
-) -> anyhow::Result<KeyValueServiceClient<Channel>> {
-    let endpoint = if let Some(client_tls_config) = client_tls_config {
-        Channel::from_shared(format!("https://{}", grpc_server_address))?
-            .tls_config(client_tls_config)?
+) -> anyhow::Result<Endpoint> {
+    Ok(if let Some(client_tls_config) = client_tls_config {
+        Channel::from_shared(format!("https://{}", address))?.tls_config(client_tls_config)?
     } else {
-        Channel::from_shared(format!("http://{}", grpc_server_address))?
-    };
+        Channel::from_shared(format!("http://{}", address))?
+    })
+}
 
-    let channel = endpoint
+/// Connects to the leader backend at `grpc_server_address`. Reads are spread over the
+/// `follower_addresses` if there are any, they may return slightly stale data.
+pub async fn create_grpc_client(
+    grpc_server_address: &str,
+    follower_addresses: &[String],
+    client_tls_config: Option<ClientTlsConfig>,
+) -> anyhow::Result<KeyValueServiceGrpcClient> {
+    let channel = grpc_endpoint(grpc_server_address, client_tls_config.clone())?
         .connect()
         .await
         .context("Couldn't connect to kv-service-backend, make sure it's running.")?;
-    Ok(KeyValueServiceClient::new(channel))
+    let followers = if follower_addresses.is_empty() {
+        None
+    } else {
+        let endpoints = follower_addresses
+            .iter()
+            .map(|address| grpc_endpoint(address, client_tls_config.clone()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Some(KeyValueServiceClient::new(Channel::balance_list(
+            endpoints.into_iter(),
+        )))
+    };
+    Ok(KeyValueServiceGrpcClient::new(
+        KeyValueServiceClient::new(channel),
+        followers,
+    ))
+}
+
+/// Connects to every backend in `shard_addresses`, which share the keyspace between them.
+///
+/// With a `state_file` the shards are the ones it lists once it exists, since shards added or
+/// removed at runtime are written to it, and a rebalance that was interrupted is finished in the
+/// background.
+pub async fn create_sharded_grpc_client(
+    shard_addresses: &[String],
+    client_tls_config: Option<ClientTlsConfig>,
+    state_file: Option<&Path>,
+) -> anyhow::Result<ShardedGrpcClient> {
+    let Some(state_file) = state_file else {
+        let clients = connect_shards(shard_addresses, client_tls_config).await?;
+        return ShardedClient::new(clients);
+    };
+    let layout = match ShardLayout::read(state_file)? {
+        Some(layout) => {
+            let configured: BTreeSet<&str> = shard_addresses.iter().map(String::as_str).collect();
+            if layout
+                .shards
+                .iter()
+                .map(String::as_str)
+                .collect::<BTreeSet<_>>()
+                != configured
+            {
+                tracing::warn!(
+                    "Using the shards {:?} of {} instead of {:?}, they were changed at runtime",
+                    layout.shards,
+                    state_file.display(),
+                    shard_addresses
+                );
+            }
+            layout
+        }
+        None => {
+            let layout = ShardLayout {
+                shards: shard_addresses.to_vec(),
+                previous: None,
+            };
+            layout.write(state_file)?;
+            layout
+        }
+    };
+    let addresses: Vec<String> = layout.addresses().into_iter().map(str::to_string).collect();
+    let clients = connect_shards(&addresses, client_tls_config).await?;
+    let client = ShardedClient::with_state_file(clients, &layout, state_file.to_path_buf())?;
+    if layout.previous.is_some() {
+        let resumed = client.clone();
+        tokio::spawn(async move {
+            tracing::info!("Resuming the interrupted rebalance");
+            if let Err(err) = resumed.resume_rebalance().await {
+                tracing::error!("Failed to resume the rebalance: {:#}", err);
+            }
+        });
+    }
+    Ok(client)
+}
+
+async fn connect_shards(
+    shard_addresses: &[String],
+    client_tls_config: Option<ClientTlsConfig>,
+) -> anyhow::Result<Vec<(String, KeyValueServiceGrpcClient)>> {
+    let mut clients = Vec::with_capacity(shard_addresses.len());
+    for address in shard_addresses {
+        let client = create_grpc_client(address, &[], client_tls_config.clone()).await?;
+        clients.push((address.clone(), client));
+    }
+    Ok(clients)
 }
 
 pub fn create_http_server(
     addr: SocketAddr,
     tls_config: Option<OpenSSLConfig>,
-    grpc_client: KeyValueServiceClient<Channel>,
+    grpc_client: KeyValueServiceGrpcClient,
+) -> anyhow::Result<(EitherHttpsOrHttpServer, Router)> {
+    let state = controllers::AppState {
+        key_value_service: Arc::new(GrpcKeyValueService::new(grpc_client)),
+    };
+    Ok((bind(addr, tls_config), create_router(state)))
+}
+
+pub fn create_sharded_http_server(
+    addr: SocketAddr,
+    tls_config: Option<OpenSSLConfig>,
+    grpc_client: ShardedGrpcClient,
 ) -> anyhow::Result<(EitherHttpsOrHttpServer, Router)> {
     let state = controllers::AppState {
-        key_value_service: Arc::new(GrpcKeyValueService::new(KeyValueServiceGrpcClient(
-            grpc_client,
-        ))),
+        key_value_service: Arc::new(GrpcKeyValueService::new(grpc_client)),
     };
-    let router = create_router(state);
-    let server = if let Some(tls_config) = tls_config {
-        Left(axum_server::bind_openssl(addr, tls_config))
+    Ok((bind(addr, tls_config), create_router(state)))
+}
+
+/// Creates the admin server whose `/api/_shards` endpoints add and remove shards. New shards
+/// are connected to with `client_tls_config`.
+pub fn create_shard_admin_server(
+    addr: SocketAddr,
+    tls_config: Option<OpenSSLConfig>,
+    grpc_client: ShardedGrpcClient,
+    client_tls_config: Option<ClientTlsConfig>,
+) -> anyhow::Result<(EitherHttpsOrHttpServer, Router)> {
+    let shard_state = ShardState {
+        shard_service: Arc::new(GrpcShardService::new(grpc_client, client_tls_config)),
+    };
+    Ok((
+        bind(addr, tls_config),
+        create_shard_admin_router(shard_state),
+    ))
+}
+
+fn bind(addr: SocketAddr, tls_config: Option<OpenSSLConfig>) -> EitherHttpsOrHttpServer {
+    if let Some(tls_config) = tls_config {
+        Left(axum_server::bind(addr).acceptor(ClientIdentityAcceptor::new(tls_config)))
     } else {
         Right(axum_server::bind(addr))
-    };
-    Ok((server, router))
+    }
 }
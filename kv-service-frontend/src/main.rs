@@ -1,8 +1,13 @@
 use anyhow::Context;
-use axum_server::tls_openssl::OpenSSLConfig;
+use axum::Router;
+use axum_server::{accept::DefaultAcceptor, tls_openssl::OpenSSLConfig, Server};
 use either::Either;
-use kv_service_frontend::create_grpc_client;
-use std::{net::SocketAddr, path::PathBuf, str::FromStr};
+use kv_service_config::Config;
+use kv_service_frontend::{
+    create_grpc_client, create_sharded_grpc_client,
+    tls::{watch_https_tls_config, ClientIdentityAcceptor, ServerIdentityFiles},
+};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
 use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -17,66 +22,142 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let tls = dotenvy::var("TLS").context("TLS must be set")?.parse()?;
+    let config = Config::load()?;
+
+    let tls = config.required("TLS")?.parse()?;
 
     let (http_server_tls_config, grpc_client_tls_config) = if tls {
-        let http_server_tls_config = create_http_server_tls_config()?;
-        let grpc_client_tls_config = create_grpc_client_tls_config()?;
+        let http_server_tls_config = create_http_server_tls_config(&config)?;
+        let grpc_client_tls_config = create_grpc_client_tls_config(&config)?;
         (Some(http_server_tls_config), Some(grpc_client_tls_config))
     } else {
         (None, None)
     };
 
-    let grpc_server_address =
-        dotenvy::var("GRPC_SERVER_ADDRESS").context("GRPC_SERVER_ADDRESS must be set")?;
+    let grpc_follower_addresses = config.list("GRPC_FOLLOWER_ADDRESSES");
+    let grpc_shard_addresses = config.list("GRPC_SHARD_ADDRESSES");
 
-    let client = create_grpc_client(&grpc_server_address, grpc_client_tls_config).await?;
-    let http_server_address =
-        dotenvy::var("HTTP_SERVER_ADDRESS").context("HTTP_SERVER_ADDRESS must be set")?;
+    let http_server_address = config.required("HTTP_SERVER_ADDRESS")?;
     let http_server_address = SocketAddr::from_str(&http_server_address)?;
 
-    let (server, router) = kv_service_frontend::create_http_server(
-        http_server_address,
-        http_server_tls_config,
-        client,
-    )?;
+    let mut admin = None;
+    let (server, router) = if grpc_shard_addresses.is_empty() {
+        let grpc_server_address = config.required("GRPC_SERVER_ADDRESS")?;
+        let client = create_grpc_client(
+            &grpc_server_address,
+            &grpc_follower_addresses,
+            grpc_client_tls_config,
+        )
+        .await?;
+        kv_service_frontend::create_http_server(
+            http_server_address,
+            http_server_tls_config,
+            client,
+        )?
+    } else {
+        anyhow::ensure!(
+            grpc_follower_addresses.is_empty(),
+            "GRPC_FOLLOWER_ADDRESSES cannot be combined with GRPC_SHARD_ADDRESSES"
+        );
+        let state_file = config
+            .var("SHARD_STATE_FILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let client = create_sharded_grpc_client(
+            &grpc_shard_addresses,
+            grpc_client_tls_config.clone(),
+            state_file.as_deref(),
+        )
+        .await?;
+        tracing::info!("Sharding keys across {:?}", client.shards().await);
+        if let Some(admin_address) = config
+            .var("SHARD_ADMIN_ADDRESS")
+            .filter(|address| !address.is_empty())
+        {
+            anyhow::ensure!(
+                state_file.is_some(),
+                "SHARD_ADMIN_ADDRESS needs SHARD_STATE_FILE, which keeps shard changes across restarts"
+            );
+            let admin_address = SocketAddr::from_str(&admin_address)?;
+            tracing::info!("Serving the shard admin API on {}", admin_address);
+            admin = Some(kv_service_frontend::create_shard_admin_server(
+                admin_address,
+                http_server_tls_config.clone(),
+                client.clone(),
+                grpc_client_tls_config,
+            )?);
+        }
+        kv_service_frontend::create_sharded_http_server(
+            http_server_address,
+            http_server_tls_config,
+            client,
+        )?
+    };
 
     tracing::info!("Listening on {}", http_server_address);
-    match server {
-        Either::Left(https_server) => https_server.serve(router.into_make_service()).await?,
-        Either::Right(http_server) => http_server.serve(router.into_make_service()).await?,
+    let admin = async {
+        match admin {
+            Some((server, router)) => serve(server, router).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = serve(server, router) => result?,
+        result = admin => result?,
     }
     Ok(())
 }
 
-pub fn create_grpc_client_tls_config() -> anyhow::Result<ClientTlsConfig> {
-    let data_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap_or(&PathBuf::new())
-        .join("tls");
-    let root_cert = std::fs::read_to_string(data_dir.join("root.crt"))?;
-    let client_cert = std::fs::read_to_string(data_dir.join("client.crt"))?;
-    let client_key = std::fs::read_to_string(data_dir.join("client.key"))?;
-    let client_identity = Identity::from_pem(client_cert, client_key);
-    let ca = Certificate::from_pem(root_cert);
-    let ca_domain_name = dotenvy::var("CA_DOMAIN_NAME").context("CA_DOMAIN_NAME must be set")?;
+async fn serve(
+    server: Either<Server<ClientIdentityAcceptor>, Server<DefaultAcceptor>>,
+    router: Router,
+) -> std::io::Result<()> {
+    match server {
+        Either::Left(https_server) => https_server.serve(router.into_make_service()).await,
+        Either::Right(http_server) => http_server.serve(router.into_make_service()).await,
+    }
+}
+
+pub fn create_grpc_client_tls_config(config: &Config) -> anyhow::Result<ClientTlsConfig> {
+    let root_cert = config.certificates("TLS_CA_FILE", "root.crt")?;
+    let client_cert = config.certificates("TLS_CLIENT_CERT_FILE", "client.crt")?;
+    let client_key = config.private_key("TLS_CLIENT_KEY_FILE", "client.key")?;
+    let ca_domain_name = config.required("CA_DOMAIN_NAME")?;
     Ok(ClientTlsConfig::new()
-        .ca_certificate(ca)
-        .identity(client_identity)
+        .ca_certificate(Certificate::from_pem(root_cert.pem))
+        .identity(Identity::from_pem(client_cert.pem, client_key.pem))
         .domain_name(ca_domain_name))
 }
 
-pub fn create_http_server_tls_config() -> anyhow::Result<OpenSSLConfig> {
-    Ok(OpenSSLConfig::from_pem_file(
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .parent()
-            .unwrap_or(&PathBuf::new())
-            .join("tls")
-            .join("client.crt"),
-        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .parent()
-            .unwrap_or(&PathBuf::new())
-            .join("tls")
-            .join("client.key"),
-    )?)
+/// The HTTPS listener presents its own certificate, `TLS_HTTPS_CERT_FILE`, and the ones of
+/// `TLS_HTTPS_SNI_CERT_FILES` to clients that ask for their names. They are reloaded when they change.
+/// Clients must present a certificate issued by `TLS_HTTPS_CLIENT_CA_FILE` if it is set.
+pub fn create_http_server_tls_config(config: &Config) -> anyhow::Result<OpenSSLConfig> {
+    let sni_certs = config.list("TLS_HTTPS_SNI_CERT_FILES");
+    let sni_keys = config.list("TLS_HTTPS_SNI_KEY_FILES");
+    anyhow::ensure!(
+        sni_certs.len() == sni_keys.len(),
+        "TLS_HTTPS_SNI_CERT_FILES and TLS_HTTPS_SNI_KEY_FILES must list as many files"
+    );
+    let mut identities = vec![ServerIdentityFiles {
+        certificate_chain: config.tls_path("TLS_HTTPS_CERT_FILE", "https.crt"),
+        private_key: config.tls_path("TLS_HTTPS_KEY_FILE", "https.key"),
+    }];
+    for (cert, key) in sni_certs.into_iter().zip(sni_keys) {
+        identities.push(ServerIdentityFiles {
+            certificate_chain: cert.into(),
+            private_key: key.into(),
+        });
+    }
+    let reload_interval_secs: u64 = config
+        .var("TLS_RELOAD_INTERVAL_SECS")
+        .unwrap_or("10".to_string())
+        .parse()
+        .context("TLS_RELOAD_INTERVAL_SECS must be a number of seconds")?;
+    let client_ca = config.var("TLS_HTTPS_CLIENT_CA_FILE").map(PathBuf::from);
+    watch_https_tls_config(
+        identities,
+        client_ca,
+        Duration::from_secs(reload_interval_secs),
+    )
 }
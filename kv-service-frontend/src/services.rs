@@ -1 +1,7 @@
+// The client trait mirrors the tonic client, whose errors are `tonic::Status`.
+#[cfg_attr(test, allow(clippy::result_large_err))]
 pub mod key_value_service;
+pub mod shard_service;
+// Routing passes the `tonic::Status` of the shards through.
+#[allow(clippy::result_large_err)]
+pub mod sharding;
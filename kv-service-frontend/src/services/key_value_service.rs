@@ -1,112 +1,595 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH},
+};
+
 use axum::async_trait;
+use kv_service_proto::{prost_to_serde_json, serde_json_to_prost, InvalidValue};
+use serde::Serialize;
 use serde_json::Value;
-use tokio::sync::Mutex;
+use tokio_stream::{Stream, StreamExt};
 use tonic::{transport::Channel, Request};
 
 use crate::{
     error::ServiceError,
     key_value_service::{
-        key_value_service_client::KeyValueServiceClient, DeleteResponse, GetResponse, KeyRequest,
-        KeyValueRequest, SetResponse,
+        self, condition::Check, key_value_service_client::KeyValueServiceClient, operation::Kind,
+        BatchDeleteRequest, BatchDeleteResponse, BatchGetRequest, BatchGetResponse,
+        BatchSetRequest, BatchSetResponse, DeleteOperation, DeleteResponse, GetResponse,
+        KeyRequest, KeyValueRequest, ScanRequest, ScanResponse, SetOperation, SetResponse,
+        TransactionRequest, TransactionResponse, WatchRequest,
     },
-    utils::{prost_to_serde_json, serde_json_to_prost},
 };
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    After(Duration),
+    At(SystemTime),
+}
+
+/// Requirement on the current state of a key for a conditional write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Exists(bool),
+    Version(u64),
+    Value(Value),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PutOptions {
+    pub expiry: Option<Expiry>,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutOutcome {
+    Written { updated: bool, version: u64 },
+    ConditionFailed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutItem {
+    pub key: String,
+    pub value: Value,
+    pub expiry: Option<Expiry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Precondition {
+    pub key: String,
+    pub condition: Condition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Set {
+        key: String,
+        value: Value,
+        expiry: Option<Expiry>,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionOutcome {
+    /// `existed` tells for every operation whether the key held a value before it.
+    Committed { version: u64, existed: Vec<bool> },
+    /// Nothing was written because the precondition at this index didn't hold.
+    Failed { precondition: usize },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// Key to watch, or key prefix if `prefix` is set.
+    pub key: String,
+    pub prefix: bool,
+    /// Replay retained events from this revision on instead of only sending new ones.
+    pub start_revision: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchEventKind {
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WatchEvent {
+    #[serde(rename = "type")]
+    pub kind: WatchEventKind,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    pub revision: u64,
+}
+
+impl TryFrom<key_value_service::WatchEvent> for WatchEvent {
+    type Error = InvalidValue;
+
+    fn try_from(event: key_value_service::WatchEvent) -> Result<Self, Self::Error> {
+        let kind = match event.r#type() {
+            key_value_service::watch_event::EventType::Put => WatchEventKind::Put,
+            key_value_service::watch_event::EventType::Delete => WatchEventKind::Delete,
+        };
+        Ok(Self {
+            kind,
+            key: event.key,
+            value: event.value.map(prost_to_serde_json).transpose()?,
+            revision: event.revision,
+        })
+    }
+}
+
+pub type WatchStream = Pin<Box<dyn Stream<Item = Result<WatchEvent, ServiceError>> + Send>>;
+pub type WatchEventStream =
+    Pin<Box<dyn Stream<Item = Result<key_value_service::WatchEvent, tonic::Status>> + Send>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionedValue {
+    pub value: Value,
+    pub version: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub prefix: String,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanPage {
+    pub items: Vec<KeyValue>,
+    pub next_cursor: Option<String>,
+}
+
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait KeyValueService: Send + Sync {
-    async fn get_value(&self, key: &str) -> Result<Option<Value>, ServiceError>;
-    async fn put_value(&self, key: &str, value: Value) -> Result<bool, ServiceError>;
+    async fn get_value(&self, key: &str) -> Result<Option<VersionedValue>, ServiceError>;
+    async fn put_value(
+        &self,
+        key: &str,
+        value: Value,
+        options: PutOptions,
+    ) -> Result<PutOutcome, ServiceError>;
     async fn delete_value(&self, key: &str) -> Result<bool, ServiceError>;
+    /// Looks up several keys in one round trip, returning one result per key in order.
+    async fn get_values(
+        &self,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<VersionedValue>>, ServiceError>;
+    /// Writes several values atomically in one round trip.
+    async fn put_values(&self, items: Vec<PutItem>) -> Result<Vec<PutOutcome>, ServiceError>;
+    /// Deletes several keys atomically in one round trip.
+    async fn delete_values(&self, keys: Vec<String>) -> Result<Vec<bool>, ServiceError>;
+    async fn scan(&self, options: ScanOptions) -> Result<ScanPage, ServiceError>;
+    /// Streams changes to a key or key prefix until the stream is dropped.
+    async fn watch(&self, options: WatchOptions) -> Result<WatchStream, ServiceError>;
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<Operation>,
+    ) -> Result<TransactionOutcome, ServiceError>;
 }
 
-pub struct KeyValueServiceGrpcClient(pub KeyValueServiceClient<Channel>);
+/// gRPC client that sends writes to the leader backend and reads to its followers.
+///
+/// Followers apply writes asynchronously, so a read may return a value, and a version to use in
+/// an `If-Match` header, that the leader has already replaced. A conditional write with such a
+/// version is checked by the leader and fails rather than overwriting the newer value.
+#[derive(Debug, Clone)]
+pub struct KeyValueServiceGrpcClient {
+    leader: KeyValueServiceClient<Channel>,
+    followers: Option<KeyValueServiceClient<Channel>>,
+}
+
+impl KeyValueServiceGrpcClient {
+    /// Reads go to the leader as well if there are no `followers`.
+    pub fn new(
+        leader: KeyValueServiceClient<Channel>,
+        followers: Option<KeyValueServiceClient<Channel>>,
+    ) -> Self {
+        Self { leader, followers }
+    }
+
+    /// Sends a read to the followers, and to the leader instead when they're unavailable, such
+    /// as a follower that hasn't received its first snapshot yet.
+    async fn read<M, T, F, Fut>(&self, request: Request<M>, call: F) -> Result<T, tonic::Status>
+    where
+        M: Clone,
+        F: Fn(KeyValueServiceClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<T, tonic::Status>>,
+    {
+        let Some(followers) = &self.followers else {
+            return call(self.leader.clone(), request).await;
+        };
+        let message = request.get_ref().clone();
+        match call(followers.clone(), request).await {
+            Err(status) if status.code() == tonic::Code::Unavailable => {
+                tracing::debug!("Follower unavailable, reading from the leader: {}", status);
+                call(self.leader.clone(), Request::new(message)).await
+            }
+            result => result,
+        }
+    }
+}
 
+/// The methods take `&self` so that requests run concurrently, tonic clients are cheap to clone
+/// and multiplex their requests over a shared HTTP/2 connection.
 #[cfg_attr(test, automock)]
 #[async_trait]
 pub trait KeyValueServiceClientTrait: Send + Sync {
     async fn get(
-        &mut self,
+        &self,
         request: Request<KeyRequest>,
     ) -> Result<tonic::Response<GetResponse>, tonic::Status>;
     async fn set(
-        &mut self,
+        &self,
         request: Request<KeyValueRequest>,
     ) -> Result<tonic::Response<SetResponse>, tonic::Status>;
     async fn delete(
-        &mut self,
+        &self,
         request: Request<KeyRequest>,
     ) -> Result<tonic::Response<DeleteResponse>, tonic::Status>;
+    async fn scan(&self, request: Request<ScanRequest>)
+        -> Result<Vec<ScanResponse>, tonic::Status>;
+    async fn transaction(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> Result<tonic::Response<TransactionResponse>, tonic::Status>;
+    async fn batch_get(
+        &self,
+        request: Request<BatchGetRequest>,
+    ) -> Result<tonic::Response<BatchGetResponse>, tonic::Status>;
+    async fn batch_set(
+        &self,
+        request: Request<BatchSetRequest>,
+    ) -> Result<tonic::Response<BatchSetResponse>, tonic::Status>;
+    async fn batch_delete(
+        &self,
+        request: Request<BatchDeleteRequest>,
+    ) -> Result<tonic::Response<BatchDeleteResponse>, tonic::Status>;
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<WatchEventStream, tonic::Status>;
 }
 
 #[async_trait]
 impl KeyValueServiceClientTrait for KeyValueServiceGrpcClient {
     async fn get(
-        &mut self,
+        &self,
         request: Request<KeyRequest>,
     ) -> Result<tonic::Response<GetResponse>, tonic::Status> {
-        self.0.get(request).await
+        self.read(request, |mut client, request| async move {
+            client.get(request).await
+        })
+        .await
     }
 
     async fn set(
-        &mut self,
+        &self,
         request: Request<KeyValueRequest>,
     ) -> Result<tonic::Response<SetResponse>, tonic::Status> {
-        self.0.set(request).await
+        self.leader.clone().set(request).await
     }
 
     async fn delete(
-        &mut self,
+        &self,
         request: Request<KeyRequest>,
     ) -> Result<tonic::Response<DeleteResponse>, tonic::Status> {
-        self.0.delete(request).await
+        self.leader.clone().delete(request).await
+    }
+
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Vec<ScanResponse>, tonic::Status> {
+        self.read(request, |mut client, request| async move {
+            let mut stream = client.scan(request).await?.into_inner();
+            let mut responses = Vec::new();
+            while let Some(response) = stream.message().await? {
+                responses.push(response);
+            }
+            Ok(responses)
+        })
+        .await
+    }
+
+    async fn transaction(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> Result<tonic::Response<TransactionResponse>, tonic::Status> {
+        self.leader.clone().transaction(request).await
+    }
+
+    async fn batch_get(
+        &self,
+        request: Request<BatchGetRequest>,
+    ) -> Result<tonic::Response<BatchGetResponse>, tonic::Status> {
+        self.read(request, |mut client, request| async move {
+            client.batch_get(request).await
+        })
+        .await
+    }
+
+    async fn batch_set(
+        &self,
+        request: Request<BatchSetRequest>,
+    ) -> Result<tonic::Response<BatchSetResponse>, tonic::Status> {
+        self.leader.clone().batch_set(request).await
+    }
+
+    async fn batch_delete(
+        &self,
+        request: Request<BatchDeleteRequest>,
+    ) -> Result<tonic::Response<BatchDeleteResponse>, tonic::Status> {
+        self.leader.clone().batch_delete(request).await
+    }
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<WatchEventStream, tonic::Status> {
+        let stream = self
+            .read(request, |mut client, request| async move {
+                client.watch(request).await
+            })
+            .await?;
+        Ok(Box::pin(stream.into_inner()))
     }
 }
 
-pub struct GrpcKeyValueService<T: KeyValueServiceClientTrait + Send> {
-    client: Mutex<T>,
+pub struct GrpcKeyValueService<T: KeyValueServiceClientTrait> {
+    client: T,
 }
 
-impl<T: KeyValueServiceClientTrait + Send> GrpcKeyValueService<T> {
+impl<T: KeyValueServiceClientTrait> GrpcKeyValueService<T> {
     pub fn new(client: T) -> Self {
-        Self {
-            client: Mutex::new(client),
-        }
+        Self { client }
+    }
+}
+
+/// Splits an expiry into the `ttl_ms` and `expires_at_ms` request fields.
+fn expiry_fields(expiry: Option<Expiry>) -> Result<(Option<u64>, Option<u64>), SystemTimeError> {
+    Ok(match expiry {
+        Some(Expiry::After(ttl)) => (Some(ttl.as_millis() as u64), None),
+        Some(Expiry::At(time)) => (
+            None,
+            Some(time.duration_since(UNIX_EPOCH)?.as_millis() as u64),
+        ),
+        None => (None, None),
+    })
+}
+
+fn versioned_value(response: GetResponse) -> Result<Option<VersionedValue>, InvalidValue> {
+    response
+        .value
+        .map(|value| {
+            Ok(VersionedValue {
+                value: prost_to_serde_json(value)?,
+                version: response.version,
+            })
+        })
+        .transpose()
+}
+
+impl From<Condition> for key_value_service::Condition {
+    fn from(condition: Condition) -> Self {
+        let check = match condition {
+            Condition::Exists(exists) => Check::Exists(exists),
+            Condition::Version(version) => Check::Version(version),
+            Condition::Value(value) => Check::Value(serde_json_to_prost(value)),
+        };
+        Self { check: Some(check) }
     }
 }
 
 #[async_trait]
-impl<T: KeyValueServiceClientTrait + Send> KeyValueService for GrpcKeyValueService<T> {
-    async fn get_value(&self, key: &str) -> Result<Option<Value>, ServiceError> {
+impl<T: KeyValueServiceClientTrait> KeyValueService for GrpcKeyValueService<T> {
+    async fn get_value(&self, key: &str) -> Result<Option<VersionedValue>, ServiceError> {
         let request = Request::new(KeyRequest {
             key: key.to_string(),
         });
-        let mut client = self.client.lock().await;
-        let response = client.get(request).await?;
-        Ok(response.into_inner().value.map(prost_to_serde_json))
+        let response = self.client.get(request).await?.into_inner();
+        Ok(versioned_value(response)?)
     }
 
-    async fn put_value(&self, key: &str, value: Value) -> Result<bool, ServiceError> {
+    async fn put_value(
+        &self,
+        key: &str,
+        value: Value,
+        options: PutOptions,
+    ) -> Result<PutOutcome, ServiceError> {
+        let (ttl_ms, expires_at_ms) = expiry_fields(options.expiry)?;
         let request = Request::new(KeyValueRequest {
             key: key.to_string(),
             value: Some(serde_json_to_prost(value)),
+            ttl_ms,
+            expires_at_ms,
+            condition: options.condition.map(Into::into),
         });
-        let mut client = self.client.lock().await;
-        let response = client.set(request).await?;
-        Ok(response.into_inner().updated)
+        match self.client.set(request).await {
+            Ok(response) => {
+                let response = response.into_inner();
+                Ok(PutOutcome::Written {
+                    updated: response.updated,
+                    version: response.version,
+                })
+            }
+            Err(status) if status.code() == tonic::Code::FailedPrecondition => {
+                Ok(PutOutcome::ConditionFailed)
+            }
+            Err(status) => Err(status.into()),
+        }
     }
 
     async fn delete_value(&self, key: &str) -> Result<bool, ServiceError> {
         let request = Request::new(KeyRequest {
             key: key.to_string(),
         });
-        let mut client = self.client.lock().await;
-        let response = client.delete(request).await?;
+        let response = self.client.delete(request).await?;
         Ok(response.into_inner().deleted)
     }
+
+    async fn get_values(
+        &self,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<VersionedValue>>, ServiceError> {
+        let request = Request::new(BatchGetRequest { keys });
+        let response = self.client.batch_get(request).await?.into_inner();
+        Ok(response
+            .values
+            .into_iter()
+            .map(versioned_value)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn put_values(&self, items: Vec<PutItem>) -> Result<Vec<PutOutcome>, ServiceError> {
+        let items = items
+            .into_iter()
+            .map(|item| {
+                let (ttl_ms, expires_at_ms) = expiry_fields(item.expiry)?;
+                Ok(SetOperation {
+                    key: item.key,
+                    value: Some(serde_json_to_prost(item.value)),
+                    ttl_ms,
+                    expires_at_ms,
+                })
+            })
+            .collect::<Result<_, SystemTimeError>>()?;
+        let request = Request::new(BatchSetRequest { items });
+        let response = self.client.batch_set(request).await?.into_inner();
+        Ok(response
+            .results
+            .into_iter()
+            .map(|result| PutOutcome::Written {
+                updated: result.updated,
+                version: result.version,
+            })
+            .collect())
+    }
+
+    async fn delete_values(&self, keys: Vec<String>) -> Result<Vec<bool>, ServiceError> {
+        let request = Request::new(BatchDeleteRequest { keys });
+        let response = self.client.batch_delete(request).await?.into_inner();
+        Ok(response
+            .results
+            .into_iter()
+            .map(|result| result.deleted)
+            .collect())
+    }
+
+    async fn scan(&self, options: ScanOptions) -> Result<ScanPage, ServiceError> {
+        // One extra entry tells us whether there is another page.
+        let request = Request::new(ScanRequest {
+            prefix: options.prefix,
+            start: options.start,
+            end: options.end,
+            limit: options.limit.saturating_add(1),
+            cursor: options.cursor.unwrap_or_default(),
+        });
+        let mut responses = self.client.scan(request).await?;
+        let next_cursor = if responses.len() > options.limit as usize {
+            responses.truncate(options.limit as usize);
+            responses.last().map(|response| response.cursor.clone())
+        } else {
+            None
+        };
+        let items = responses
+            .into_iter()
+            .map(|response| {
+                Ok(KeyValue {
+                    key: response.key,
+                    value: response
+                        .value
+                        .map(prost_to_serde_json)
+                        .transpose()?
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Result<_, InvalidValue>>()?;
+        Ok(ScanPage { items, next_cursor })
+    }
+
+    async fn watch(&self, options: WatchOptions) -> Result<WatchStream, ServiceError> {
+        let request = Request::new(WatchRequest {
+            key: options.key,
+            prefix: options.prefix,
+            start_revision: options.start_revision,
+        });
+        let stream = self.client.watch(request).await?;
+        Ok(Box::pin(stream.map(|event| {
+            WatchEvent::try_from(event?).map_err(ServiceError::from)
+        })))
+    }
+
+    async fn transaction(
+        &self,
+        preconditions: Vec<Precondition>,
+        operations: Vec<Operation>,
+    ) -> Result<TransactionOutcome, ServiceError> {
+        let preconditions = preconditions
+            .into_iter()
+            .map(|precondition| key_value_service::Precondition {
+                key: precondition.key,
+                condition: Some(precondition.condition.into()),
+            })
+            .collect();
+        let operations = operations
+            .into_iter()
+            .map(|operation| {
+                let kind = match operation {
+                    Operation::Set { key, value, expiry } => {
+                        let (ttl_ms, expires_at_ms) = expiry_fields(expiry)?;
+                        Kind::Set(SetOperation {
+                            key,
+                            value: Some(serde_json_to_prost(value)),
+                            ttl_ms,
+                            expires_at_ms,
+                        })
+                    }
+                    Operation::Delete { key } => Kind::Delete(DeleteOperation { key }),
+                };
+                Ok(key_value_service::Operation { kind: Some(kind) })
+            })
+            .collect::<Result<_, SystemTimeError>>()?;
+        let request = Request::new(TransactionRequest {
+            preconditions,
+            operations,
+        });
+        let response = self.client.transaction(request).await?.into_inner();
+        let outcome = match response.failed_precondition {
+            Some(precondition) if !response.committed => TransactionOutcome::Failed {
+                precondition: precondition as usize,
+            },
+            _ => TransactionOutcome::Committed {
+                version: response.version,
+                existed: response
+                    .results
+                    .into_iter()
+                    .map(|result| result.existed)
+                    .collect(),
+            },
+        };
+        Ok(outcome)
+    }
 }
 
 #[cfg(test)]
@@ -122,12 +605,20 @@ mod tests {
             .returning(|_| {
                 Ok(tonic::Response::new(GetResponse {
                     value: Some(serde_json_to_prost(serde_json::json!("value"))),
+                    version: 3,
+                    expires_at_ms: None,
                 }))
             });
 
         let service = GrpcKeyValueService::new(mock);
         let result = service.get_value("key").await.unwrap();
-        assert_eq!(result, Some(serde_json::json!("value")));
+        assert_eq!(
+            result,
+            Some(VersionedValue {
+                value: serde_json::json!("value"),
+                version: 3
+            })
+        );
     }
 
     #[tokio::test]
@@ -140,17 +631,98 @@ mod tests {
                         == Some(serde_json_to_prost(serde_json::json!("value")))
             })
             .times(1)
-            .returning(|_| Ok(tonic::Response::new(SetResponse { updated: true })));
+            .returning(|_| {
+                Ok(tonic::Response::new(SetResponse {
+                    updated: true,
+                    version: 2,
+                }))
+            });
 
         let service = GrpcKeyValueService::new(mock);
         let result = service
-            .put_value("key", serde_json::json!("value"))
+            .put_value("key", serde_json::json!("value"), PutOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            PutOutcome::Written {
+                updated: true,
+                version: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_value_with_condition() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_set()
+            .withf(|request| {
+                request.get_ref().condition
+                    == Some(key_value_service::Condition {
+                        check: Some(Check::Version(2)),
+                    })
+            })
+            .times(1)
+            .returning(|_| Err(tonic::Status::failed_precondition("condition failed")));
+
+        let service = GrpcKeyValueService::new(mock);
+        let result = service
+            .put_value(
+                "key",
+                serde_json::json!("value"),
+                PutOptions {
+                    condition: Some(Condition::Version(2)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, PutOutcome::ConditionFailed);
+    }
+
+    #[tokio::test]
+    async fn test_put_value_with_expiry() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_set()
+            .withf(|request| {
+                request.get_ref().ttl_ms == Some(1500) && request.get_ref().expires_at_ms.is_none()
+            })
+            .times(1)
+            .returning(|_| Ok(tonic::Response::new(SetResponse::default())));
+        mock.expect_set()
+            .withf(|request| {
+                request.get_ref().ttl_ms.is_none() && request.get_ref().expires_at_ms == Some(2000)
+            })
+            .times(1)
+            .returning(|_| Ok(tonic::Response::new(SetResponse::default())));
+
+        let service = GrpcKeyValueService::new(mock);
+        service
+            .put_value(
+                "key",
+                serde_json::json!("value"),
+                PutOptions {
+                    expiry: Some(Expiry::After(Duration::from_millis(1500))),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        service
+            .put_value(
+                "key",
+                serde_json::json!("value"),
+                PutOptions {
+                    expiry: Some(Expiry::At(UNIX_EPOCH + Duration::from_secs(2))),
+                    ..Default::default()
+                },
+            )
             .await
             .unwrap();
-        assert_eq!(result, true);
     }
 
     #[tokio::test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn test_delete_value() {
         let mut mock = MockKeyValueServiceClientTrait::new();
         mock.expect_delete()
@@ -189,7 +761,9 @@ mod tests {
             .returning(|_| Err(tonic::Status::new(tonic::Code::Internal, "Internal error")));
 
         let service = GrpcKeyValueService::new(mock);
-        let result = service.put_value("key", serde_json::json!("value")).await;
+        let result = service
+            .put_value("key", serde_json::json!("value"), PutOptions::default())
+            .await;
         assert!(result.is_err());
     }
 
@@ -205,4 +779,322 @@ mod tests {
         let result = service.delete_value("key").await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_transaction()
+            .withf(|request| {
+                let request = request.get_ref();
+                request.preconditions
+                    == vec![key_value_service::Precondition {
+                        key: "a".to_string(),
+                        condition: Some(key_value_service::Condition {
+                            check: Some(Check::Version(1)),
+                        }),
+                    }]
+                    && request.operations.len() == 2
+            })
+            .times(1)
+            .returning(|_| {
+                Ok(tonic::Response::new(TransactionResponse {
+                    committed: true,
+                    version: 2,
+                    results: vec![
+                        key_value_service::OperationResult { existed: true },
+                        key_value_service::OperationResult { existed: false },
+                    ],
+                    failed_precondition: None,
+                }))
+            });
+        mock.expect_transaction().times(1).returning(|_| {
+            Ok(tonic::Response::new(TransactionResponse {
+                failed_precondition: Some(0),
+                ..Default::default()
+            }))
+        });
+
+        let service = GrpcKeyValueService::new(mock);
+        let preconditions = vec![Precondition {
+            key: "a".to_string(),
+            condition: Condition::Version(1),
+        }];
+        let operations = vec![
+            Operation::Set {
+                key: "a".to_string(),
+                value: serde_json::json!(1),
+                expiry: None,
+            },
+            Operation::Delete {
+                key: "b".to_string(),
+            },
+        ];
+        assert_eq!(
+            service
+                .transaction(preconditions.clone(), operations.clone())
+                .await
+                .unwrap(),
+            TransactionOutcome::Committed {
+                version: 2,
+                existed: vec![true, false]
+            }
+        );
+        assert_eq!(
+            service
+                .transaction(preconditions, operations)
+                .await
+                .unwrap(),
+            TransactionOutcome::Failed { precondition: 0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_values() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_batch_get()
+            .withf(|request| request.get_ref().keys == ["a", "b"])
+            .times(1)
+            .returning(|_| {
+                Ok(tonic::Response::new(BatchGetResponse {
+                    values: vec![
+                        GetResponse {
+                            value: Some(serde_json_to_prost(serde_json::json!("a"))),
+                            version: 4,
+                            expires_at_ms: None,
+                        },
+                        GetResponse::default(),
+                    ],
+                }))
+            });
+
+        let service = GrpcKeyValueService::new(mock);
+        let result = service
+            .get_values(vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                Some(VersionedValue {
+                    value: serde_json::json!("a"),
+                    version: 4
+                }),
+                None
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_values() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_batch_set()
+            .withf(|request| {
+                let items = &request.get_ref().items;
+                items.len() == 2 && items[0].key == "a" && items[1].ttl_ms == Some(1000)
+            })
+            .times(1)
+            .returning(|_| {
+                Ok(tonic::Response::new(BatchSetResponse {
+                    results: vec![
+                        SetResponse {
+                            updated: true,
+                            version: 5,
+                        },
+                        SetResponse {
+                            updated: false,
+                            version: 5,
+                        },
+                    ],
+                }))
+            });
+
+        let service = GrpcKeyValueService::new(mock);
+        let result = service
+            .put_values(vec![
+                PutItem {
+                    key: "a".to_string(),
+                    value: serde_json::json!(1),
+                    expiry: None,
+                },
+                PutItem {
+                    key: "b".to_string(),
+                    value: serde_json::json!(2),
+                    expiry: Some(Expiry::After(Duration::from_secs(1))),
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                PutOutcome::Written {
+                    updated: true,
+                    version: 5
+                },
+                PutOutcome::Written {
+                    updated: false,
+                    version: 5
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_values() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_batch_delete()
+            .withf(|request| request.get_ref().keys == ["a", "b"])
+            .times(1)
+            .returning(|_| {
+                Ok(tonic::Response::new(BatchDeleteResponse {
+                    results: vec![
+                        DeleteResponse { deleted: false },
+                        DeleteResponse { deleted: true },
+                    ],
+                }))
+            });
+
+        let service = GrpcKeyValueService::new(mock);
+        let result = service
+            .delete_values(vec!["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(result, vec![false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_watch()
+            .withf(|request| {
+                request.get_ref()
+                    == &WatchRequest {
+                        key: "a/".to_string(),
+                        prefix: true,
+                        start_revision: Some(2),
+                    }
+            })
+            .times(1)
+            .returning(|_| {
+                let events = vec![
+                    Ok(key_value_service::WatchEvent {
+                        r#type: key_value_service::watch_event::EventType::Put.into(),
+                        key: "a/1".to_string(),
+                        value: Some(serde_json_to_prost(serde_json::json!("one"))),
+                        revision: 2,
+                    }),
+                    Ok(key_value_service::WatchEvent {
+                        r#type: key_value_service::watch_event::EventType::Delete.into(),
+                        key: "a/1".to_string(),
+                        value: None,
+                        revision: 3,
+                    }),
+                    Err(tonic::Status::aborted("watcher fell behind")),
+                ];
+                Ok(Box::pin(tokio_stream::iter(events)))
+            });
+
+        let service = GrpcKeyValueService::new(mock);
+        let mut stream = service
+            .watch(WatchOptions {
+                key: "a/".to_string(),
+                prefix: true,
+                start_revision: Some(2),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            WatchEvent {
+                kind: WatchEventKind::Put,
+                key: "a/1".to_string(),
+                value: Some(serde_json::json!("one")),
+                revision: 2,
+            }
+        );
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            WatchEvent {
+                kind: WatchEventKind::Delete,
+                key: "a/1".to_string(),
+                value: None,
+                revision: 3,
+            }
+        );
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+
+    fn scan_response(key: &str) -> ScanResponse {
+        ScanResponse {
+            key: key.to_string(),
+            value: Some(serde_json_to_prost(serde_json::json!(key))),
+            cursor: format!("cursor-{key}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_scan()
+            .withf(|request| {
+                request.get_ref().prefix == "a/"
+                    && request.get_ref().limit == 3
+                    && request.get_ref().cursor == "cursor-a/0"
+            })
+            .times(1)
+            .returning(|_| {
+                Ok(vec![
+                    scan_response("a/1"),
+                    scan_response("a/2"),
+                    scan_response("a/3"),
+                ])
+            });
+
+        let service = GrpcKeyValueService::new(mock);
+        let page = service
+            .scan(ScanOptions {
+                prefix: "a/".to_string(),
+                cursor: Some("cursor-a/0".to_string()),
+                limit: 2,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            page,
+            ScanPage {
+                items: vec![
+                    KeyValue {
+                        key: "a/1".to_string(),
+                        value: serde_json::json!("a/1"),
+                    },
+                    KeyValue {
+                        key: "a/2".to_string(),
+                        value: serde_json::json!("a/2"),
+                    },
+                ],
+                next_cursor: Some("cursor-a/2".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_last_page() {
+        let mut mock = MockKeyValueServiceClientTrait::new();
+        mock.expect_scan()
+            .times(1)
+            .returning(|_| Ok(vec![scan_response("a")]));
+
+        let service = GrpcKeyValueService::new(mock);
+        let page = service
+            .scan(ScanOptions {
+                limit: 2,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next_cursor, None);
+    }
 }
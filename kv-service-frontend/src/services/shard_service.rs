@@ -0,0 +1,51 @@
+use axum::async_trait;
+use tonic::transport::ClientTlsConfig;
+
+use super::{
+    key_value_service::KeyValueServiceGrpcClient,
+    sharding::{RebalanceReport, ShardedClient},
+};
+use crate::{create_grpc_client, error::ServiceError};
+
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ShardService: Send + Sync {
+    async fn shards(&self) -> Vec<String>;
+    /// Adds the backend at `address` as a shard and moves the keys it now owns to it.
+    async fn add_shard(&self, address: &str) -> Result<RebalanceReport, ServiceError>;
+    /// Moves the keys of the backend at `address` to the other shards and removes it.
+    async fn remove_shard(&self, address: &str) -> Result<RebalanceReport, ServiceError>;
+}
+
+pub struct GrpcShardService {
+    client: ShardedClient<KeyValueServiceGrpcClient>,
+    tls_config: Option<ClientTlsConfig>,
+}
+
+impl GrpcShardService {
+    pub fn new(
+        client: ShardedClient<KeyValueServiceGrpcClient>,
+        tls_config: Option<ClientTlsConfig>,
+    ) -> Self {
+        Self { client, tls_config }
+    }
+}
+
+#[async_trait]
+impl ShardService for GrpcShardService {
+    async fn shards(&self) -> Vec<String> {
+        self.client.shards().await
+    }
+
+    async fn add_shard(&self, address: &str) -> Result<RebalanceReport, ServiceError> {
+        let client = create_grpc_client(address, &[], self.tls_config.clone()).await?;
+        Ok(self.client.add_shard(address.to_string(), client).await?)
+    }
+
+    async fn remove_shard(&self, address: &str) -> Result<RebalanceReport, ServiceError> {
+        Ok(self.client.remove_shard(address).await?)
+    }
+}
@@ -0,0 +1,836 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard, RwLock};
+use tokio_stream::StreamExt;
+use tonic::{Code, Request, Response, Status};
+
+use super::key_value_service::{KeyValueServiceClientTrait, WatchEventStream};
+use crate::key_value_service::{
+    condition::Check, operation::Kind, watch_event::EventType, BatchDeleteRequest,
+    BatchDeleteResponse, BatchGetRequest, BatchGetResponse, BatchSetRequest, BatchSetResponse,
+    Condition, DeleteResponse, GetResponse, KeyRequest, KeyValueRequest, ScanRequest, ScanResponse,
+    SetResponse, TransactionRequest, TransactionResponse, WatchRequest,
+};
+
+/// Points every shard takes on the ring, more points spread the keyspace more evenly.
+const VIRTUAL_NODES: u32 = 128;
+const MIGRATION_LOCKS: usize = 64;
+const REBALANCE_PAGE_SIZE: u32 = 100;
+
+/// Consistent-hash ring that places every shard at `VIRTUAL_NODES` points. A key belongs to
+/// the shard of the first point at or after its hash, so adding or removing a shard only moves
+/// the keys next to the points it gains or loses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new<S: AsRef<str>>(shards: impl IntoIterator<Item = S>) -> Self {
+        let shards: BTreeSet<String> = shards
+            .into_iter()
+            .map(|shard| shard.as_ref().to_string())
+            .collect();
+        let mut points = BTreeMap::new();
+        // Shards are placed in order so that the rare colliding point always goes to the same one.
+        for shard in shards {
+            for replica in 0..VIRTUAL_NODES {
+                points
+                    .entry(hash(format!("{shard}#{replica}").as_bytes()))
+                    .or_insert_with(|| shard.clone());
+            }
+        }
+        Self { points }
+    }
+
+    pub fn shards(&self) -> BTreeSet<&str> {
+        self.points.values().map(String::as_str).collect()
+    }
+
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        self.points
+            .range(hash(key.as_bytes())..)
+            .chain(&self.points)
+            .next()
+            .map(|(_, shard)| shard.as_str())
+    }
+}
+
+/// FNV-1a followed by the SplitMix64 finalizer. Unlike `DefaultHasher` it is stable across
+/// builds, so every frontend places keys on the same shards.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Shards of a [`ShardedClient`] as kept in its state file, so that a restarted frontend looks
+/// for the keys where the last rebalance put them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardLayout {
+    pub shards: Vec<String>,
+    /// Shards before the rebalance in progress, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Vec<String>>,
+}
+
+impl ShardLayout {
+    fn new(ring: &HashRing, previous: Option<&HashRing>) -> Self {
+        let shards = |ring: &HashRing| ring.shards().into_iter().map(str::to_string).collect();
+        Self {
+            shards: shards(ring),
+            previous: previous.map(shards),
+        }
+    }
+
+    /// Reads the layout of a state file, `None` if there is no file yet.
+    pub fn read(path: &Path) -> anyhow::Result<Option<Self>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read shard state {}", path.display()))
+            }
+        };
+        let layout = serde_json::from_slice(&bytes)
+            .with_context(|| format!("invalid shard state {}", path.display()))?;
+        Ok(Some(layout))
+    }
+
+    /// Replaces the state file, which is never left half written.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)
+            .and_then(|()| std::fs::rename(&temporary, path))
+            .with_context(|| format!("failed to write shard state {}", path.display()))
+    }
+
+    /// Every shard that may hold keys.
+    pub fn addresses(&self) -> BTreeSet<&str> {
+        self.shards
+            .iter()
+            .chain(self.previous.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RebalanceReport {
+    pub shards: Vec<String>,
+    /// Number of keys that moved to another shard.
+    pub moved: u64,
+}
+
+struct Shards<T> {
+    ring: HashRing,
+    /// Ring before the rebalance in progress, some keys may still be on their old shard.
+    previous: Option<HashRing>,
+    /// Clients of the shards of both rings.
+    clients: HashMap<String, T>,
+}
+
+/// Where a key belongs, `from` is the shard it is moving away from during a rebalance.
+struct Placement<T> {
+    client: T,
+    from: Option<T>,
+}
+
+impl<T: Clone> Shards<T> {
+    fn client(&self, shard: &str) -> T {
+        self.clients[shard].clone()
+    }
+
+    fn owner(&self, key: &str) -> &str {
+        self.ring
+            .owner(key)
+            .expect("a sharded client has at least one shard")
+    }
+
+    /// The shard `key` is moving away from if a rebalance is in progress and it changes owner.
+    fn previous_owner(&self, key: &str) -> Option<&str> {
+        let owner = self.owner(key);
+        self.previous
+            .as_ref()
+            .and_then(|previous| previous.owner(key))
+            .filter(|previous| *previous != owner)
+    }
+
+    fn placement(&self, key: &str) -> Placement<T> {
+        Placement {
+            client: self.client(self.owner(key)),
+            from: self
+                .previous_owner(key)
+                .map(|previous| self.client(previous)),
+        }
+    }
+
+    /// Groups the indices of `keys` by the shard that owns them.
+    fn group<'a>(&self, keys: impl Iterator<Item = &'a str>) -> BTreeMap<&str, Vec<usize>> {
+        let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (index, key) in keys.enumerate() {
+            groups.entry(self.owner(key)).or_default().push(index);
+        }
+        groups
+    }
+}
+
+/// Puts the results every shard returned for its part of a batch back in the order of the
+/// batch.
+fn scatter<R: Default + Clone>(
+    len: usize,
+    parts: Vec<(Vec<usize>, Vec<R>)>,
+) -> Result<Vec<R>, Status> {
+    let mut results = vec![R::default(); len];
+    for (indices, part) in parts {
+        if indices.len() != part.len() {
+            return Err(Status::internal(format!(
+                "shard returned {} results for {} keys",
+                part.len(),
+                indices.len()
+            )));
+        }
+        for (index, result) in indices.into_iter().zip(part) {
+            results[index] = result;
+        }
+    }
+    Ok(results)
+}
+
+fn transaction_keys(request: &TransactionRequest) -> Vec<&str> {
+    let preconditions = request
+        .preconditions
+        .iter()
+        .map(|precondition| precondition.key.as_str());
+    let operations = request
+        .operations
+        .iter()
+        .filter_map(|operation| match &operation.kind {
+            Some(Kind::Set(operation)) => Some(operation.key.as_str()),
+            Some(Kind::Delete(operation)) => Some(operation.key.as_str()),
+            None => None,
+        });
+    preconditions.chain(operations).collect()
+}
+
+/// Copies `key` from the shard it is moving away from to its new owner and deletes the
+/// original. Returns whether there was a value to move.
+async fn migrate_key<T: KeyValueServiceClientTrait>(
+    key: &str,
+    from: T,
+    to: T,
+) -> Result<bool, Status> {
+    let request = Request::new(KeyRequest {
+        key: key.to_string(),
+    });
+    let response = from.get(request).await?.into_inner();
+    let Some(value) = response.value else {
+        return Ok(false);
+    };
+    if response
+        .expires_at_ms
+        .is_none_or(|expires_at| expires_at > now_millis())
+    {
+        // A value the new owner already holds was written after the rebalance started.
+        let request = Request::new(KeyValueRequest {
+            key: key.to_string(),
+            value: Some(value),
+            ttl_ms: None,
+            expires_at_ms: response.expires_at_ms,
+            condition: Some(Condition {
+                check: Some(Check::Exists(false)),
+            }),
+        });
+        match to.set(request).await {
+            Err(status) if status.code() != Code::FailedPrecondition => return Err(status),
+            _ => {}
+        }
+    }
+    let request = Request::new(KeyRequest {
+        key: key.to_string(),
+    });
+    from.delete(request).await?;
+    Ok(true)
+}
+
+/// Spreads the keyspace over several backends, sending every request to the shard that owns
+/// its keys on a `HashRing`.
+///
+/// When a shard is added or removed the keys that change owner are moved in the background,
+/// and a request for a key that hasn't moved yet moves it first. Batches are split by shard
+/// and are only atomic within a shard, transactions must stay within one shard. Shards can only
+/// be added and removed with a state file, which the new layout is written to before any key
+/// moves.
+pub struct ShardedClient<T> {
+    shards: Arc<RwLock<Shards<T>>>,
+    state_file: Option<PathBuf>,
+    /// Keep a rebalance from moving a key while a request for it runs, by key hash.
+    migration_locks: Arc<Vec<Mutex<()>>>,
+    /// Held for the whole rebalance, only one runs at a time.
+    rebalance: Arc<Mutex<()>>,
+}
+
+impl<T> Clone for ShardedClient<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.clone(),
+            state_file: self.state_file.clone(),
+            migration_locks: self.migration_locks.clone(),
+            rebalance: self.rebalance.clone(),
+        }
+    }
+}
+
+impl<T: KeyValueServiceClientTrait + Clone + 'static> ShardedClient<T> {
+    /// Creates a client for the shards in `clients`, keyed by shard address.
+    pub fn new(clients: impl IntoIterator<Item = (String, T)>) -> anyhow::Result<Self> {
+        let clients: HashMap<String, T> = clients.into_iter().collect();
+        anyhow::ensure!(!clients.is_empty(), "at least one shard is required");
+        let shards = Shards {
+            ring: HashRing::new(clients.keys()),
+            previous: None,
+            clients,
+        };
+        Ok(Self {
+            shards: Arc::new(RwLock::new(shards)),
+            state_file: None,
+            migration_locks: Arc::new((0..MIGRATION_LOCKS).map(|_| Mutex::new(())).collect()),
+            rebalance: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Creates a client for the shards of `layout`, read from `state_file`, which later changes
+    /// are written to. `clients` has to cover every address of the layout. A rebalance the
+    /// layout was interrupted in is finished with [`Self::resume_rebalance`].
+    pub fn with_state_file(
+        clients: impl IntoIterator<Item = (String, T)>,
+        layout: &ShardLayout,
+        state_file: PathBuf,
+    ) -> anyhow::Result<Self> {
+        let clients: HashMap<String, T> = clients.into_iter().collect();
+        anyhow::ensure!(!layout.shards.is_empty(), "at least one shard is required");
+        for address in layout.addresses() {
+            anyhow::ensure!(
+                clients.contains_key(address),
+                "no client for shard {address}"
+            );
+        }
+        let shards = Shards {
+            ring: HashRing::new(&layout.shards),
+            previous: layout.previous.as_ref().map(HashRing::new),
+            clients,
+        };
+        Ok(Self {
+            shards: Arc::new(RwLock::new(shards)),
+            state_file: Some(state_file),
+            migration_locks: Arc::new((0..MIGRATION_LOCKS).map(|_| Mutex::new(())).collect()),
+            rebalance: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Finishes a rebalance that was interrupted, returns the number of keys moved.
+    pub async fn resume_rebalance(&self) -> anyhow::Result<u64> {
+        let _rebalance = self.rebalance.lock().await;
+        self.finish_rebalance().await
+    }
+
+    /// Writes the layout to the state file before the shards change, so that a restart never
+    /// looks for keys on shards it doesn't know about.
+    fn save_layout(&self, ring: &HashRing, previous: Option<&HashRing>) -> anyhow::Result<()> {
+        let Some(state_file) = &self.state_file else {
+            anyhow::bail!(Status::failed_precondition(
+                "shards can only be changed with a shard state file, which keeps the change \
+                 across restarts"
+            ));
+        };
+        ShardLayout::new(ring, previous).write(state_file)
+    }
+
+    pub async fn shards(&self) -> Vec<String> {
+        let shards = self.shards.read().await;
+        shards
+            .ring
+            .shards()
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Adds a shard and moves the keys it now owns to it.
+    pub async fn add_shard(&self, shard: String, client: T) -> anyhow::Result<RebalanceReport> {
+        let _rebalance = self.rebalance.lock().await;
+        // An interrupted rebalance has to finish before the ring changes again.
+        let mut moved = self.finish_rebalance().await?;
+        {
+            let mut shards = self.shards.write().await;
+            anyhow::ensure!(
+                !shards.clients.contains_key(&shard),
+                Status::already_exists(format!("{shard} is already a shard"))
+            );
+            let ring = HashRing::new(shards.clients.keys().chain([&shard]));
+            self.save_layout(&ring, Some(&shards.ring))?;
+            shards.clients.insert(shard, client);
+            shards.previous = Some(std::mem::replace(&mut shards.ring, ring));
+        }
+        moved += self.finish_rebalance().await?;
+        Ok(RebalanceReport {
+            shards: self.shards().await,
+            moved,
+        })
+    }
+
+    /// Moves the keys of a shard to the remaining ones and removes it.
+    pub async fn remove_shard(&self, shard: &str) -> anyhow::Result<RebalanceReport> {
+        let _rebalance = self.rebalance.lock().await;
+        let mut moved = self.finish_rebalance().await?;
+        {
+            let mut shards = self.shards.write().await;
+            anyhow::ensure!(
+                shards.clients.contains_key(shard),
+                Status::not_found(format!("{shard} is not a shard"))
+            );
+            anyhow::ensure!(
+                shards.clients.len() > 1,
+                Status::failed_precondition("the last shard cannot be removed")
+            );
+            let ring = HashRing::new(shards.clients.keys().filter(|other| *other != shard));
+            self.save_layout(&ring, Some(&shards.ring))?;
+            shards.previous = Some(std::mem::replace(&mut shards.ring, ring));
+        }
+        moved += self.finish_rebalance().await?;
+        Ok(RebalanceReport {
+            shards: self.shards().await,
+            moved,
+        })
+    }
+
+    /// Moves every key that changed owner in the rebalance in progress, if any, then forgets
+    /// the previous ring and the shards that left. Returns the number of keys moved.
+    async fn finish_rebalance(&self) -> anyhow::Result<u64> {
+        let sources: Vec<(String, T)> = {
+            let shards = self.shards.read().await;
+            let Some(previous) = &shards.previous else {
+                return Ok(0);
+            };
+            previous
+                .shards()
+                .into_iter()
+                .map(|shard| (shard.to_string(), shards.client(shard)))
+                .collect()
+        };
+        let mut moved = 0;
+        for (shard, client) in sources {
+            tracing::info!("Moving keys away from shard {}", shard);
+            let mut cursor = String::new();
+            loop {
+                let request = Request::new(ScanRequest {
+                    limit: REBALANCE_PAGE_SIZE,
+                    cursor,
+                    ..Default::default()
+                });
+                let page = client.scan(request).await?;
+                for entry in &page {
+                    let Placement {
+                        client: to,
+                        from: Some(from),
+                    } = self.shards.read().await.placement(&entry.key)
+                    else {
+                        continue;
+                    };
+                    let _lock = self.lock_key(&entry.key).await;
+                    if migrate_key(&entry.key, from, to).await? {
+                        moved += 1;
+                    }
+                }
+                match page.last() {
+                    Some(last) if page.len() == REBALANCE_PAGE_SIZE as usize => {
+                        cursor = last.cursor.clone();
+                    }
+                    _ => break,
+                }
+            }
+        }
+        let mut shards = self.shards.write().await;
+        self.save_layout(&shards.ring, None)?;
+        shards.previous = None;
+        let Shards { ring, clients, .. } = &mut *shards;
+        let remaining = ring.shards();
+        clients.retain(|shard, _| remaining.contains(shard.as_str()));
+        tracing::info!("Rebalance moved {} keys", moved);
+        Ok(moved)
+    }
+
+    async fn lock_key(&self, key: &str) -> MutexGuard<'_, ()> {
+        self.migration_locks[hash(key.as_bytes()) as usize % MIGRATION_LOCKS]
+            .lock()
+            .await
+    }
+
+    /// Moves `keys` to their new owner first if a rebalance is in progress. The returned guards
+    /// keep the rebalance from moving them again until the request is done.
+    async fn prepare(
+        &self,
+        shards: &Shards<T>,
+        keys: &[&str],
+    ) -> Result<Vec<MutexGuard<'_, ()>>, Status> {
+        if shards.previous.is_none() {
+            return Ok(Vec::new());
+        }
+        // Locks are taken in order so that concurrent requests can't deadlock.
+        let mut locks: Vec<usize> = keys
+            .iter()
+            .map(|key| hash(key.as_bytes()) as usize % MIGRATION_LOCKS)
+            .collect();
+        locks.sort_unstable();
+        locks.dedup();
+        let mut guards = Vec::with_capacity(locks.len());
+        for lock in locks {
+            guards.push(self.migration_locks[lock].lock().await);
+        }
+        for key in keys {
+            if let Placement {
+                client,
+                from: Some(from),
+            } = shards.placement(key)
+            {
+                migrate_key(key, from, client).await?;
+            }
+        }
+        Ok(guards)
+    }
+}
+
+#[async_trait]
+impl<T: KeyValueServiceClientTrait + Clone + 'static> KeyValueServiceClientTrait
+    for ShardedClient<T>
+{
+    async fn get(&self, request: Request<KeyRequest>) -> Result<Response<GetResponse>, Status> {
+        let shards = self.shards.read().await;
+        let _guards = self.prepare(&shards, &[&request.get_ref().key]).await?;
+        let client = shards.client(shards.owner(&request.get_ref().key));
+        client.get(request).await
+    }
+
+    async fn set(
+        &self,
+        request: Request<KeyValueRequest>,
+    ) -> Result<Response<SetResponse>, Status> {
+        let shards = self.shards.read().await;
+        let _guards = self.prepare(&shards, &[&request.get_ref().key]).await?;
+        let client = shards.client(shards.owner(&request.get_ref().key));
+        client.set(request).await
+    }
+
+    async fn delete(
+        &self,
+        request: Request<KeyRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let shards = self.shards.read().await;
+        let _guards = self.prepare(&shards, &[&request.get_ref().key]).await?;
+        let client = shards.client(shards.owner(&request.get_ref().key));
+        client.delete(request).await
+    }
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Vec<ScanResponse>, Status> {
+        let shards = self.shards.read().await;
+        let request = request.into_inner();
+        // Cursors are keys, so every shard resumes from the same one, and the page is made of
+        // the first keys across the first keys of every shard.
+        let mut responses = Vec::new();
+        for client in shards.clients.values() {
+            let client = client.clone();
+            responses.extend(client.scan(Request::new(request.clone())).await?);
+        }
+        responses.sort_by(|a, b| a.key.cmp(&b.key));
+        // A key that is moving may briefly be on two shards.
+        responses.dedup_by(|a, b| a.key == b.key);
+        if request.limit > 0 {
+            responses.truncate(request.limit as usize);
+        }
+        Ok(responses)
+    }
+
+    async fn transaction(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> Result<Response<TransactionResponse>, Status> {
+        let shards = self.shards.read().await;
+        let keys = transaction_keys(request.get_ref());
+        let owners: BTreeSet<&str> = keys.iter().map(|key| shards.owner(key)).collect();
+        if owners.len() > 1 {
+            return Err(Status::invalid_argument(
+                "the keys of a transaction must belong to the same shard",
+            ));
+        }
+        let _guards = self.prepare(&shards, &keys).await?;
+        let client = shards.client(shards.owner(keys.first().copied().unwrap_or_default()));
+        client.transaction(request).await
+    }
+
+    async fn batch_get(
+        &self,
+        request: Request<BatchGetRequest>,
+    ) -> Result<Response<BatchGetResponse>, Status> {
+        let shards = self.shards.read().await;
+        let keys = request.into_inner().keys;
+        let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let _guards = self.prepare(&shards, &key_refs).await?;
+        let mut parts = Vec::new();
+        for (shard, indices) in shards.group(key_refs.into_iter()) {
+            let request = BatchGetRequest {
+                keys: indices.iter().map(|index| keys[*index].clone()).collect(),
+            };
+            let client = shards.client(shard);
+            let response = client.batch_get(Request::new(request)).await?.into_inner();
+            parts.push((indices, response.values));
+        }
+        Ok(Response::new(BatchGetResponse {
+            values: scatter(keys.len(), parts)?,
+        }))
+    }
+
+    async fn batch_set(
+        &self,
+        request: Request<BatchSetRequest>,
+    ) -> Result<Response<BatchSetResponse>, Status> {
+        let shards = self.shards.read().await;
+        let items = request.into_inner().items;
+        let keys: Vec<&str> = items.iter().map(|item| item.key.as_str()).collect();
+        let _guards = self.prepare(&shards, &keys).await?;
+        let mut parts = Vec::new();
+        for (shard, indices) in shards.group(keys.into_iter()) {
+            let request = BatchSetRequest {
+                items: indices.iter().map(|index| items[*index].clone()).collect(),
+            };
+            let client = shards.client(shard);
+            let response = client.batch_set(Request::new(request)).await?.into_inner();
+            parts.push((indices, response.results));
+        }
+        Ok(Response::new(BatchSetResponse {
+            results: scatter(items.len(), parts)?,
+        }))
+    }
+
+    async fn batch_delete(
+        &self,
+        request: Request<BatchDeleteRequest>,
+    ) -> Result<Response<BatchDeleteResponse>, Status> {
+        let shards = self.shards.read().await;
+        let keys = request.into_inner().keys;
+        let key_refs: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let _guards = self.prepare(&shards, &key_refs).await?;
+        let mut parts = Vec::new();
+        for (shard, indices) in shards.group(key_refs.into_iter()) {
+            let request = BatchDeleteRequest {
+                keys: indices.iter().map(|index| keys[*index].clone()).collect(),
+            };
+            let client = shards.client(shard);
+            let response = client
+                .batch_delete(Request::new(request))
+                .await?
+                .into_inner();
+            parts.push((indices, response.results));
+        }
+        Ok(Response::new(BatchDeleteResponse {
+            results: scatter(keys.len(), parts)?,
+        }))
+    }
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<WatchEventStream, Status> {
+        let shards = self.shards.read().await;
+        let request = request.into_inner();
+        let watched: Vec<(String, T)> = if request.prefix {
+            shards
+                .clients
+                .iter()
+                .map(|(shard, client)| (shard.clone(), client.clone()))
+                .collect()
+        } else {
+            [
+                Some(shards.owner(&request.key)),
+                shards.previous_owner(&request.key),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|shard| (shard.to_string(), shards.client(shard)))
+            .collect()
+        };
+        // Revisions are counted by every shard separately, so the merged stream is only ordered
+        // per shard and a single revision can't say where to resume on all of them.
+        if watched.len() > 1 && request.start_revision.is_some() {
+            return Err(Status::invalid_argument(
+                "a watch over several shards can't start at a revision, every shard counts its \
+                 own revisions",
+            ));
+        }
+        let mut merged: Option<WatchEventStream> = None;
+        for (shard, client) in watched {
+            let stream = client.watch(Request::new(request.clone())).await?;
+            let stream = without_move_deletes(stream, shard, self.shards.clone());
+            merged = Some(match merged {
+                Some(merged) => Box::pin(merged.merge(stream)),
+                None => stream,
+            });
+        }
+        Ok(merged.expect("a sharded client has at least one shard"))
+    }
+}
+
+/// Drops the deletes that moving a key causes on the shard it leaves, the key lives on at its
+/// new owner. Writes always go to the owner of a key, so a delete from another shard comes from
+/// a move, or from the key expiring there before it was moved.
+fn without_move_deletes<T: Send + Sync + 'static>(
+    stream: WatchEventStream,
+    shard: String,
+    shards: Arc<RwLock<Shards<T>>>,
+) -> WatchEventStream {
+    Box::pin(
+        stream
+            .then(move |event| {
+                let shard = shard.clone();
+                let shards = shards.clone();
+                async move {
+                    let moved = match &event {
+                        Ok(event) if event.r#type() == EventType::Delete => {
+                            shards.read().await.ring.owner(&event.key) != Some(shard.as_str())
+                        }
+                        _ => false,
+                    };
+                    (!moved).then_some(event)
+                }
+            })
+            .filter_map(|event| event),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_value_service::WatchEvent;
+
+    use super::*;
+
+    fn keys() -> impl Iterator<Item = String> {
+        (0..10_000).map(|i| format!("key-{i}"))
+    }
+
+    #[test]
+    fn test_hash_ring_spreads_keys() {
+        let ring = HashRing::new(["a:1", "b:1", "c:1"]);
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for key in keys() {
+            *counts.entry(ring.owner(&key).unwrap()).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        for count in counts.values() {
+            assert!((2_500..4_200).contains(count), "{counts:?}");
+        }
+        assert_eq!(ring, HashRing::new(["c:1", "a:1", "b:1"]));
+        assert_eq!(HashRing::default().owner("key"), None);
+    }
+
+    #[test]
+    fn test_hash_ring_moves_keys_to_new_shard_only() {
+        let ring = HashRing::new(["a:1", "b:1", "c:1"]);
+        let grown = HashRing::new(["a:1", "b:1", "c:1", "d:1"]);
+        let mut moved = 0;
+        for key in keys() {
+            let (before, after) = (ring.owner(&key).unwrap(), grown.owner(&key).unwrap());
+            if before != after {
+                assert_eq!(after, "d:1");
+                moved += 1;
+            }
+        }
+        assert!((1_800..3_200).contains(&moved), "{moved}");
+        assert_eq!(grown.shards(), BTreeSet::from(["a:1", "b:1", "c:1", "d:1"]));
+    }
+
+    #[test]
+    fn test_shard_layout_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shards.json");
+        assert_eq!(ShardLayout::read(&path).unwrap(), None);
+
+        let layout = ShardLayout::new(
+            &HashRing::new(["b:1", "c:1"]),
+            Some(&HashRing::new(["a:1", "b:1"])),
+        );
+        layout.write(&path).unwrap();
+        assert_eq!(ShardLayout::read(&path).unwrap(), Some(layout.clone()));
+        assert_eq!(layout.addresses(), BTreeSet::from(["a:1", "b:1", "c:1"]));
+
+        std::fs::write(&path, "[").unwrap();
+        assert!(ShardLayout::read(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_watch_drops_deletes_of_moved_keys() {
+        let shards = Arc::new(RwLock::new(Shards::<()> {
+            ring: HashRing::new(["b:1"]),
+            previous: Some(HashRing::new(["a:1", "b:1"])),
+            clients: HashMap::new(),
+        }));
+        let event = |kind: EventType, revision| {
+            Ok(WatchEvent {
+                r#type: kind as i32,
+                key: "key".to_string(),
+                value: None,
+                revision,
+            })
+        };
+        let watch = |shard: &str, events: Vec<Result<WatchEvent, Status>>| {
+            without_move_deletes(
+                Box::pin(tokio_stream::iter(events)),
+                shard.to_string(),
+                shards.clone(),
+            )
+        };
+
+        // The key moved from a:1 to b:1, which only shows up on b:1.
+        let events: Vec<_> = watch(
+            "a:1",
+            vec![event(EventType::Put, 1), event(EventType::Delete, 2)],
+        )
+        .collect()
+        .await;
+        assert_eq!(
+            events.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![event(EventType::Put, 1).unwrap()]
+        );
+        let events: Vec<_> = watch(
+            "b:1",
+            vec![event(EventType::Put, 1), event(EventType::Delete, 2)],
+        )
+        .collect()
+        .await;
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn test_scatter() {
+        let parts = vec![(vec![0, 2], vec!["a", "c"]), (vec![1], vec!["b"])];
+        assert_eq!(scatter(3, parts).unwrap(), vec!["a", "b", "c"]);
+        assert!(scatter(2, vec![(vec![0, 1], vec!["a"])]).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum_server::tls_openssl::OpenSSLConfig;
+use kv_service_config::{spawn_tls_reloader, PemFile};
+use openssl::{
+    nid::Nid,
+    pkey::PKey,
+    ssl::{
+        self, AlpnError, NameType, SniError, SslAcceptor, SslContext, SslMethod, SslRef,
+        SslVerifyMode,
+    },
+    stack::Stack,
+    x509::X509,
+};
+
+pub use client_auth::{AddClientIdentity, ClientIdentity, ClientIdentityAcceptor};
+
+mod client_auth;
+
+/// Certificate chain and private key the HTTPS listener presents, in PEM. The chain starts with
+/// the certificate of the listener, followed by the intermediates.
+#[derive(Debug, Clone)]
+pub struct ServerIdentity {
+    pub certificate_chain: String,
+    pub private_key: String,
+}
+
+/// Files of a [`ServerIdentity`].
+#[derive(Debug, Clone)]
+pub struct ServerIdentityFiles {
+    pub certificate_chain: PathBuf,
+    pub private_key: PathBuf,
+}
+
+impl ServerIdentityFiles {
+    /// Reads the files, errors name the settings of the default identity or of an SNI one.
+    fn load(&self, default: bool) -> anyhow::Result<ServerIdentity> {
+        let (cert_name, key_name) = if default {
+            ("TLS_HTTPS_CERT_FILE", "TLS_HTTPS_KEY_FILE")
+        } else {
+            ("TLS_HTTPS_SNI_CERT_FILES", "TLS_HTTPS_SNI_KEY_FILES")
+        };
+        Ok(ServerIdentity {
+            certificate_chain: PemFile::certificates(cert_name, self.certificate_chain.clone())?
+                .pem,
+            private_key: PemFile::private_key(key_name, self.private_key.clone())?.pem,
+        })
+    }
+}
+
+/// Like [`https_tls_config`] with the identities and client CA of the files, which are reloaded
+/// when they change, checking them every `interval`, or on SIGHUP. New connections get the
+/// latest ones.
+pub fn watch_https_tls_config(
+    files: Vec<ServerIdentityFiles>,
+    client_ca: Option<PathBuf>,
+    interval: Duration,
+) -> anyhow::Result<OpenSSLConfig> {
+    let paths = files
+        .iter()
+        .flat_map(|files| [files.certificate_chain.clone(), files.private_key.clone()])
+        .chain(client_ca.clone())
+        .collect();
+    let load = move || -> anyhow::Result<SslAcceptor> {
+        let client_ca = client_ca
+            .clone()
+            .map(|path| PemFile::certificates("TLS_HTTPS_CLIENT_CA_FILE", path))
+            .transpose()?;
+        https_acceptor(
+            &load_identities(&files)?,
+            client_ca.as_ref().map(|file| file.pem.as_str()),
+        )
+    };
+    let tls_config = OpenSSLConfig::from_acceptor(Arc::new(load()?));
+    let reloaded = tls_config.clone();
+    spawn_tls_reloader(paths, interval, move || {
+        reloaded.reload_from_acceptor(Arc::new(load()?));
+        Ok(())
+    });
+    Ok(tls_config)
+}
+
+fn load_identities(files: &[ServerIdentityFiles]) -> anyhow::Result<Vec<ServerIdentity>> {
+    files
+        .iter()
+        .enumerate()
+        .map(|(index, files)| files.load(index == 0))
+        .collect()
+}
+
+/// Creates the TLS config of the HTTPS listener from one or more identities. Clients are
+/// presented the first identity, unless they ask over SNI for a name that only a later
+/// identity's certificate is issued to. With a `client_ca` bundle, clients must present a
+/// certificate it issued, which [`ClientIdentityAcceptor`] passes on to the handlers.
+pub fn https_tls_config(
+    identities: &[ServerIdentity],
+    client_ca: Option<&str>,
+) -> anyhow::Result<OpenSSLConfig> {
+    Ok(OpenSSLConfig::from_acceptor(Arc::new(https_acceptor(
+        identities, client_ca,
+    )?)))
+}
+
+fn https_acceptor(
+    identities: &[ServerIdentity],
+    client_ca: Option<&str>,
+) -> anyhow::Result<SslAcceptor> {
+    let (default, others) = identities
+        .split_first()
+        .context("the HTTPS listener needs a certificate")?;
+    let client_ca = client_ca
+        .map(|pem| X509::stack_from_pem(pem.as_bytes()))
+        .transpose()
+        .context("invalid client CA bundle")?;
+    let client_ca = client_ca.as_deref();
+    let default_names = certificate_names(default)?;
+    let mut contexts = Vec::with_capacity(others.len());
+    for identity in others {
+        let names = certificate_names(identity)?;
+        contexts.push((
+            names,
+            acceptor_builder(identity, client_ca)?
+                .build()
+                .into_context(),
+        ));
+    }
+
+    let mut builder = acceptor_builder(default, client_ca)?;
+    if !contexts.is_empty() {
+        builder.set_servername_callback(move |ssl, _alert| {
+            let Some(server_name) = ssl.servername(NameType::HOST_NAME) else {
+                return Ok(());
+            };
+            if default_names
+                .iter()
+                .any(|name| name_matches(name, server_name))
+            {
+                return Ok(());
+            }
+            let context = contexts.iter().find_map(|(names, context)| {
+                names
+                    .iter()
+                    .any(|name| name_matches(name, server_name))
+                    .then_some(context)
+            });
+            if let Some(context) = context {
+                switch_context(ssl, context)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(builder.build())
+}
+
+fn switch_context(ssl: &mut SslRef, context: &SslContext) -> Result<(), SniError> {
+    ssl.set_ssl_context(context)
+        .map_err(|_| SniError::ALERT_FATAL)
+}
+
+fn acceptor_builder(
+    identity: &ServerIdentity,
+    client_ca: Option<&[X509]>,
+) -> anyhow::Result<ssl::SslAcceptorBuilder> {
+    let mut chain = X509::stack_from_pem(identity.certificate_chain.as_bytes())
+        .context("invalid certificate chain")?
+        .into_iter();
+    let certificate = chain.next().context("no certificate in the chain")?;
+    let key = PKey::private_key_from_pem(identity.private_key.as_bytes())
+        .context("invalid private key")?;
+
+    let mut builder = SslAcceptor::mozilla_modern_v5(SslMethod::tls())?;
+    builder.set_certificate(&certificate)?;
+    for certificate in chain {
+        builder.add_extra_chain_cert(certificate)?;
+    }
+    builder.set_private_key(&key)?;
+    builder
+        .check_private_key()
+        .context("the private key doesn't belong to the certificate")?;
+    builder.set_alpn_select_callback(alpn_select);
+    // Every SNI context verifies clients the same way, the handshake may switch to any of them.
+    if let Some(client_ca) = client_ca {
+        let mut names = Stack::new()?;
+        for certificate in client_ca {
+            builder.cert_store_mut().add_cert(certificate.clone())?;
+            names.push(certificate.subject_name().to_owned()?)?;
+        }
+        builder.set_client_ca_list(names);
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        // Sessions are only resumed by the listener that verified them.
+        builder.set_session_id_context(b"kv-service-frontend")?;
+    }
+    Ok(builder)
+}
+
+fn alpn_select<'a>(_ssl: &mut SslRef, client: &'a [u8]) -> Result<&'a [u8], AlpnError> {
+    ssl::select_next_proto(b"\x02h2\x08http/1.1", client).ok_or(AlpnError::NOACK)
+}
+
+/// DNS names the certificate of the identity is issued to, from its subject alternative names
+/// or else its common name.
+fn certificate_names(identity: &ServerIdentity) -> anyhow::Result<Vec<String>> {
+    let certificate = X509::from_pem(identity.certificate_chain.as_bytes())
+        .context("invalid certificate chain")?;
+    let names: Vec<_> = certificate
+        .subject_alt_names()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.dnsname().map(str::to_string))
+        .collect();
+    if !names.is_empty() {
+        return Ok(names);
+    }
+    Ok(certificate
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| Some(entry.data().as_utf8().ok()?.to_string()))
+        .collect())
+}
+
+/// Whether a certificate name, which may start with a `*.` wildcard for a single label, covers
+/// the server name.
+fn name_matches(name: &str, server_name: &str) -> bool {
+    match name.strip_prefix("*.") {
+        Some(suffix) => server_name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(suffix)),
+        None => name.eq_ignore_ascii_case(server_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("api.example.com", "API.example.com"));
+        assert!(!name_matches("api.example.com", "example.com"));
+        assert!(name_matches("*.example.com", "api.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "v1.api.example.com"));
+        assert!(!name_matches("*.example.com", ".example.com"));
+    }
+}
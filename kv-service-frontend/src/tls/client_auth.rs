@@ -0,0 +1,181 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::http::Request;
+use axum_server::{
+    accept::Accept,
+    tls_openssl::{OpenSSLAcceptor, OpenSSLConfig},
+};
+use openssl::x509::{X509NameRef, X509Ref};
+use tokio_openssl::SslStream;
+use tower_service::Service;
+
+/// Certificate a client authenticated the HTTPS connection with, verified against the client CA
+/// of the listener. Handlers take it as `Option<Extension<ClientIdentity>>`, it is missing when
+/// the listener doesn't ask for client certificates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Distinguished name of the subject, such as `CN=reporting,O=Example`.
+    pub subject: String,
+    /// DNS names, email addresses, URIs and IP addresses of the subject alternative names.
+    pub alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+    pub fn from_certificate(certificate: &X509Ref) -> Self {
+        let alt_names = certificate
+            .subject_alt_names()
+            .into_iter()
+            .flatten()
+            .filter_map(|name| {
+                name.dnsname()
+                    .or(name.email())
+                    .or(name.uri())
+                    .map(str::to_string)
+                    .or_else(|| name.ipaddress().and_then(ip_address))
+            })
+            .collect();
+        Self {
+            subject: distinguished_name(certificate.subject_name()),
+            alt_names,
+        }
+    }
+}
+
+fn distinguished_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            format!("{key}={value}")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn ip_address(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => Some(std::net::Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string()),
+        16 => Some(std::net::Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+/// Accepts HTTPS connections like [`OpenSSLAcceptor`] and adds the [`ClientIdentity`] of the
+/// connection, if the client presented a certificate, to each of its requests.
+#[derive(Clone)]
+pub struct ClientIdentityAcceptor {
+    inner: OpenSSLAcceptor,
+}
+
+impl ClientIdentityAcceptor {
+    pub fn new(config: OpenSSLConfig) -> Self {
+        Self {
+            inner: OpenSSLAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientIdentityAcceptor
+where
+    OpenSSLAcceptor: Accept<I, S, Stream = SslStream<I>, Service = S>,
+    <OpenSSLAcceptor as Accept<I, S>>::Future: Send + 'static,
+    I: Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = SslStream<I>;
+    type Service = AddClientIdentity<S>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            let identity = stream
+                .ssl()
+                .peer_certificate()
+                .map(|certificate| ClientIdentity::from_certificate(&certificate));
+            Ok((
+                stream,
+                AddClientIdentity {
+                    inner: service,
+                    identity,
+                },
+            ))
+        })
+    }
+}
+
+/// Service of a connection accepted by [`ClientIdentityAcceptor`].
+#[derive(Clone)]
+pub struct AddClientIdentity<S> {
+    inner: S,
+    identity: Option<ClientIdentity>,
+}
+
+impl<S, B> Service<Request<B>> for AddClientIdentity<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if let Some(identity) = &self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::x509::X509;
+
+    use super::*;
+
+    #[test]
+    fn test_client_identity() {
+        let mut params = rcgen::CertificateParams::new(vec!["reporting.example.com".to_string()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "reporting");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Example");
+        params.subject_alt_names.extend([
+            rcgen::SanType::Rfc822Name("ops@example.com".to_string()),
+            rcgen::SanType::URI("spiffe://example.com/reporting".to_string()),
+            rcgen::SanType::IpAddress([10, 0, 0, 1].into()),
+        ]);
+        let certificate = rcgen::Certificate::from_params(params).unwrap();
+        let certificate = X509::from_pem(certificate.serialize_pem().unwrap().as_bytes()).unwrap();
+
+        assert_eq!(
+            ClientIdentity::from_certificate(&certificate),
+            ClientIdentity {
+                subject: "CN=reporting,O=Example".to_string(),
+                alt_names: vec![
+                    "reporting.example.com".to_string(),
+                    "ops@example.com".to_string(),
+                    "spiffe://example.com/reporting".to_string(),
+                    "10.0.0.1".to_string(),
+                ],
+            }
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! The gRPC API of kv-service, shared by its backend, its frontend and Rust clients.
+//!
+//! Values are sent as [`key_value_service::JsonValue`] messages, which [`prost_to_serde_json`] and
+//! [`serde_json_to_prost`] convert from and to `serde_json::Value` without losing the exact
+//! value of numbers, as long as `serde_json::Number` can hold it. Without the
+//! `arbitrary_precision` feature it holds only 64-bit integers and doubles, so larger integers and
+//! longer decimals are rounded to the nearest double. The feature turns on the feature of the same
+//! name of serde_json, which changes how every crate in the build parses JSON numbers.
+
+pub mod key_value_service {
+    tonic::include_proto!("keyvalueservice");
+}
+
+mod value;
+
+pub use value::{prost_to_serde_json, serde_json_to_prost, InvalidValue};
@@ -0,0 +1,426 @@
+use std::fmt;
+
+use crate::key_value_service::{json_value::Kind, JsonArray, JsonObject, JsonValue};
+
+/// A value with no JSON representation, such as a NaN or infinite number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidValue {
+    /// JSONPath of the invalid value within the converted one, such as `$.prices[2]`.
+    pub path: String,
+    pub reason: &'static str,
+}
+
+impl InvalidValue {
+    fn new(reason: &'static str) -> Self {
+        Self {
+            path: "$".to_string(),
+            reason,
+        }
+    }
+
+    /// Prefixes the path with the segment of the parent value that holds it.
+    fn within(mut self, segment: &str) -> Self {
+        self.path.insert_str(1, segment);
+        self
+    }
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.reason, self.path)
+    }
+}
+
+impl std::error::Error for InvalidValue {}
+
+fn key_segment(key: &str) -> String {
+    let mut chars = key.chars();
+    let identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if identifier {
+        format!(".{key}")
+    } else {
+        format!("[{}]", serde_json::Value::from(key))
+    }
+}
+
+pub fn prost_to_serde_json(value: JsonValue) -> Result<serde_json::Value, InvalidValue> {
+    use serde_json::Value::*;
+
+    Ok(match value.kind {
+        Some(Kind::NullValue(_)) | None => Null,
+        Some(Kind::BoolValue(b)) => Bool(b),
+        Some(Kind::IntValue(n)) => Number(n.into()),
+        Some(Kind::UintValue(n)) => Number(n.into()),
+        Some(Kind::DoubleValue(n)) => Number(
+            serde_json::Number::from_f64(n)
+                .ok_or_else(|| InvalidValue::new("number is not finite"))?,
+        ),
+        Some(Kind::DecimalValue(n)) => Number(
+            n.parse()
+                .map_err(|_| InvalidValue::new("decimal is not a JSON number"))?,
+        ),
+        Some(Kind::StringValue(s)) => String(s),
+        Some(Kind::ObjectValue(object)) => Object(
+            object
+                .fields
+                .into_iter()
+                .map(|(k, v)| match prost_to_serde_json(v) {
+                    Ok(v) => Ok((k, v)),
+                    Err(err) => Err(err.within(&key_segment(&k))),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        Some(Kind::ArrayValue(array)) => Array(
+            array
+                .values
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| prost_to_serde_json(v).map_err(|err| err.within(&format!("[{i}]"))))
+                .collect::<Result<_, _>>()?,
+        ),
+    })
+}
+
+/// Numbers are sent as integers or doubles only if that gives back exactly the same JSON text,
+/// and as decimal text otherwise.
+fn number_kind(n: serde_json::Number) -> Kind {
+    if let Some(i) = n.as_i64().filter(|i| serde_json::Number::from(*i) == n) {
+        Kind::IntValue(i)
+    } else if let Some(u) = n.as_u64().filter(|u| serde_json::Number::from(*u) == n) {
+        Kind::UintValue(u)
+    } else if let Some(d) = n
+        .as_f64()
+        .filter(|d| serde_json::Number::from_f64(*d).as_ref() == Some(&n))
+    {
+        Kind::DoubleValue(d)
+    } else {
+        Kind::DecimalValue(n.to_string())
+    }
+}
+
+pub fn serde_json_to_prost(value: serde_json::Value) -> JsonValue {
+    use serde_json::Value::*;
+
+    let kind = match value {
+        Null => Kind::NullValue(0),
+        Bool(b) => Kind::BoolValue(b),
+        Number(n) => number_kind(n),
+        String(s) => Kind::StringValue(s),
+        Object(map) => Kind::ObjectValue(JsonObject {
+            fields: map
+                .into_iter()
+                .map(|(k, v)| (k, serde_json_to_prost(v)))
+                .collect(),
+        }),
+        Array(vec) => Kind::ArrayValue(JsonArray {
+            values: vec.into_iter().map(serde_json_to_prost).collect(),
+        }),
+    };
+    JsonValue { kind: Some(kind) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use prost::Message;
+
+    #[cfg(not(feature = "arbitrary_precision"))]
+    fn json_number() -> impl Strategy<Value = serde_json::Number> {
+        prop_oneof![
+            any::<i64>().prop_map(serde_json::Number::from),
+            any::<u64>().prop_map(serde_json::Number::from),
+            any::<f64>().prop_filter_map("not finite", serde_json::Number::from_f64),
+        ]
+    }
+
+    /// Also generates numbers that only keep their exact text with arbitrary precision.
+    #[cfg(feature = "arbitrary_precision")]
+    fn json_number() -> impl Strategy<Value = serde_json::Number> {
+        prop_oneof![
+            any::<i64>().prop_map(serde_json::Number::from),
+            any::<u64>().prop_map(serde_json::Number::from),
+            any::<f64>().prop_filter_map("not finite", serde_json::Number::from_f64),
+            "-?(0|[1-9][0-9]{0,40})(\\.[0-9]{1,30})?([eE][+-]?[0-9]{1,3})?"
+                .prop_map(|n| n.parse().unwrap()),
+        ]
+    }
+
+    fn json_value() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(serde_json::Value::Bool),
+            json_number().prop_map(serde_json::Value::Number),
+            ".*".prop_map(serde_json::Value::String),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(serde_json::Value::Array),
+                prop::collection::btree_map(".*", inner, 0..8)
+                    .prop_map(|fields| serde_json::Value::Object(fields.into_iter().collect())),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(value in json_value()) {
+            let message = serde_json_to_prost(value.clone());
+            let decoded = JsonValue::decode(message.encode_to_vec().as_slice()).unwrap();
+            prop_assert_eq!(prost_to_serde_json(decoded).unwrap(), value);
+        }
+
+        /// Without arbitrary precision serde_json may parse a double to a neighbouring one.
+        #[cfg(feature = "arbitrary_precision")]
+        #[test]
+        fn test_round_trip_json_text(value in json_value()) {
+            let text = value.to_string();
+            let parsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+            prop_assert_eq!(prost_to_serde_json(serde_json_to_prost(parsed)).unwrap().to_string(), text);
+        }
+    }
+
+    fn assert_exact(text: &str, kind: Kind) {
+        let value: serde_json::Value = serde_json::from_str(text).unwrap();
+        let message = serde_json_to_prost(value);
+        assert_eq!(message.kind, Some(kind));
+        assert_eq!(prost_to_serde_json(message).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn test_exact_numbers() {
+        assert_exact("9007199254740993", Kind::IntValue(9_007_199_254_740_993));
+        assert_exact("-9223372036854775808", Kind::IntValue(i64::MIN));
+        assert_exact("18446744073709551615", Kind::UintValue(u64::MAX));
+        assert_exact("1.5", Kind::DoubleValue(1.5));
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_exact_big_numbers() {
+        assert_exact(
+            "18446744073709551616",
+            Kind::DecimalValue("18446744073709551616".to_string()),
+        );
+        assert_exact(
+            "0.10000000000000000000001",
+            Kind::DecimalValue("0.10000000000000000000001".to_string()),
+        );
+        assert_exact("1e+30", Kind::DoubleValue(1e30));
+    }
+
+    #[test]
+    fn test_prost_to_serde_json() {
+        let value = JsonValue {
+            kind: Some(Kind::StringValue("test".to_string())),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(result, serde_json::Value::String("test".to_string()));
+    }
+
+    #[test]
+    fn test_serde_json_to_prost() {
+        let value = serde_json::Value::String("test".to_string());
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::StringValue("test".to_string())),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_null() {
+        let value = JsonValue {
+            kind: Some(Kind::NullValue(0)),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(result, serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_serde_json_to_prost_null() {
+        let value = serde_json::Value::Null;
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::NullValue(0)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_number() {
+        let value = JsonValue {
+            kind: Some(Kind::DoubleValue(1.0)),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(
+            result,
+            serde_json::Value::Number(serde_json::Number::from_f64(1.0).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_serde_json_to_prost_number() {
+        let value = serde_json::Value::Number(serde_json::Number::from_f64(1.0).unwrap());
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::DoubleValue(1.0)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_bool() {
+        let value = JsonValue {
+            kind: Some(Kind::BoolValue(true)),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(result, serde_json::Value::Bool(true));
+    }
+
+    #[test]
+    fn test_serde_json_to_prost_bool() {
+        let value = serde_json::Value::Bool(true);
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::BoolValue(true)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_struct() {
+        let value = JsonValue {
+            kind: Some(Kind::ObjectValue(JsonObject {
+                fields: vec![(
+                    "test".to_string(),
+                    JsonValue {
+                        kind: Some(Kind::StringValue("test".to_string())),
+                    },
+                )]
+                .into_iter()
+                .collect(),
+            })),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "test": "test"
+            })
+        );
+    }
+
+    #[test]
+    fn test_serde_json_to_prost_struct() {
+        let value = serde_json::json!({
+            "test": "test"
+        });
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::ObjectValue(JsonObject {
+                    fields: vec![(
+                        "test".to_string(),
+                        JsonValue {
+                            kind: Some(Kind::StringValue("test".to_string())),
+                        }
+                    )]
+                    .into_iter()
+                    .collect(),
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_list() {
+        let value = JsonValue {
+            kind: Some(Kind::ArrayValue(JsonArray {
+                values: vec![JsonValue {
+                    kind: Some(Kind::StringValue("test".to_string())),
+                }]
+                .into_iter()
+                .collect(),
+            })),
+        };
+        let result = prost_to_serde_json(value).unwrap();
+        assert_eq!(result, serde_json::json!(["test"]));
+    }
+
+    #[test]
+    fn test_serde_json_to_prost_list() {
+        let value = serde_json::json!(["test"]);
+        let result = serde_json_to_prost(value);
+        assert_eq!(
+            result,
+            JsonValue {
+                kind: Some(Kind::ArrayValue(JsonArray {
+                    values: vec![JsonValue {
+                        kind: Some(Kind::StringValue("test".to_string())),
+                    }]
+                    .into_iter()
+                    .collect(),
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn test_prost_to_serde_json_invalid_numbers() {
+        let value = serde_json_to_prost(serde_json::json!({
+            "prices": [1.5, 2.5],
+            "a b": {"c": 1},
+        }));
+        let replace = |value: &mut JsonValue, path: &[&str], kind: Kind| {
+            let mut value = value;
+            for segment in path {
+                value = match value.kind.as_mut().unwrap() {
+                    Kind::ObjectValue(object) => object.fields.get_mut(*segment).unwrap(),
+                    Kind::ArrayValue(array) => &mut array.values[segment.parse::<usize>().unwrap()],
+                    _ => unreachable!(),
+                };
+            }
+            value.kind = Some(kind);
+        };
+
+        let mut nan = value.clone();
+        replace(&mut nan, &["prices", "1"], Kind::DoubleValue(f64::NAN));
+        let err = prost_to_serde_json(nan).unwrap_err();
+        assert_eq!(err.path, "$.prices[1]");
+        assert_eq!(err.to_string(), "number is not finite at $.prices[1]");
+
+        let mut infinite = value.clone();
+        replace(
+            &mut infinite,
+            &["a b", "c"],
+            Kind::DoubleValue(f64::INFINITY),
+        );
+        assert_eq!(
+            prost_to_serde_json(infinite).unwrap_err().path,
+            r#"$["a b"].c"#
+        );
+
+        let mut decimal = value;
+        replace(&mut decimal, &[], Kind::DecimalValue("NaN".to_string()));
+        assert_eq!(
+            prost_to_serde_json(decimal).unwrap_err(),
+            InvalidValue {
+                path: "$".to_string(),
+                reason: "decimal is not a JSON number",
+            }
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Throughput of the frontend under concurrent load, with the frontend and the backend running
+//! in process. Run with `cargo bench -p kv-service-tests`.
+
+use std::net::TcpListener;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use either::Either;
+use futures_util::future::join_all;
+use tokio::runtime::Runtime;
+
+const CONCURRENCY: [usize; 4] = [1, 8, 32, 128];
+const KEYS: usize = 100;
+
+fn local_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Starts a backend and a frontend and returns the URL of the frontend API.
+async fn spawn_services() -> String {
+    let grpc_server_address = local_address();
+    let grpc_server = kv_service_backend::create_grpc_server(None).unwrap();
+    let address = grpc_server_address.parse().unwrap();
+    tokio::spawn(async move { grpc_server.serve(address).await.unwrap() });
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    let http_server_address = local_address();
+    let grpc_client = kv_service_frontend::create_grpc_client(&grpc_server_address, &[], None)
+        .await
+        .unwrap();
+    let (server, router) = kv_service_frontend::create_http_server(
+        http_server_address.parse().unwrap(),
+        None,
+        grpc_client,
+    )
+    .unwrap();
+    tokio::spawn(async move {
+        match server {
+            Either::Left(https_server) => https_server
+                .serve(router.into_make_service())
+                .await
+                .unwrap(),
+            Either::Right(http_server) => {
+                http_server.serve(router.into_make_service()).await.unwrap()
+            }
+        }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    format!("http://{http_server_address}/api")
+}
+
+fn concurrent_requests(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let api_address = runtime.block_on(spawn_services());
+    let client = reqwest::Client::new();
+    runtime.block_on(async {
+        for key in 0..KEYS {
+            client
+                .put(format!("{api_address}/key-{key}"))
+                .json(&"value")
+                .send()
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut group = c.benchmark_group("get");
+    for concurrency in CONCURRENCY {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    join_all((0..concurrency).map(|i| {
+                        let request = client.get(format!("{api_address}/key-{}", i % KEYS));
+                        async move { assert!(request.send().await.unwrap().status().is_success()) }
+                    }))
+                })
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("put");
+    for concurrency in CONCURRENCY {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    join_all((0..concurrency).map(|i| {
+                        let request = client
+                            .put(format!("{api_address}/key-{}", i % KEYS))
+                            .json(&i);
+                        async move { assert!(request.send().await.unwrap().status().is_success()) }
+                    }))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_requests);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Throughput of the backend's in-memory storage with a single lock, as before it was striped,
+//! against the default number of stripes, with concurrent tasks reading and writing different
+//! keys. Run with `cargo bench -p kv-service-tests --bench storage_stripes`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::future::join_all;
+use kv_service_backend::storage::{in_memory::DEFAULT_STRIPES, InMemoryStorage, StorageEngine};
+use tokio::runtime::Runtime;
+
+const CONCURRENCY: [usize; 3] = [1, 8, 64];
+const STRIPES: [usize; 2] = [1, DEFAULT_STRIPES];
+/// Operations per task, of which every fourth is a write.
+const OPERATIONS: usize = 100;
+
+async fn run_task(storage: Arc<InMemoryStorage>, task: usize) {
+    for i in 0..OPERATIONS {
+        let key = format!("task-{task}/key-{}", i % 10);
+        if i % 4 == 0 {
+            storage
+                .set(key, serde_json::json!(i).into(), None)
+                .await
+                .unwrap();
+        } else {
+            storage.get(&key).await.unwrap();
+        }
+    }
+}
+
+fn storage_stripes(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("storage");
+    for stripes in STRIPES {
+        let storage = Arc::new(InMemoryStorage::with_stripes(stripes));
+        for concurrency in CONCURRENCY {
+            group.throughput(Throughput::Elements((concurrency * OPERATIONS) as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("stripes-{stripes}"), concurrency),
+                &concurrency,
+                |b, &concurrency| {
+                    b.to_async(&runtime).iter(|| {
+                        join_all((0..concurrency).map(|task| {
+                            let storage = storage.clone();
+                            // Spawned so that the tasks run in parallel on the runtime's workers.
+                            async move { tokio::spawn(run_task(storage, task)).await.unwrap() }
+                        }))
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, storage_stripes);
+criterion_main!(benches);
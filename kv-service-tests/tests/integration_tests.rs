@@ -1,15 +1,49 @@
-use std::net::TcpListener;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
+use axum_server::tls_openssl::OpenSSLConfig;
+use clap::Parser;
 use either::Either;
-use kv_service_backend;
-use kv_service_frontend;
+use futures_util::StreamExt;
+use kv_service_backend::{
+    key_value_service::{
+        admin_service_client::AdminServiceClient, cluster_service_client::ClusterServiceClient,
+        condition::Check, json_value, key_value_service_client::KeyValueServiceClient,
+        AddLearnerRequest, ChangeMembershipRequest, Condition, JsonValue, KeyRequest,
+        KeyValueRequest, ScanRequest, SetResponse, SnapshotRequest,
+    },
+    raft::ClusterConfig,
+    storage::ClusterStorage,
+    tls::{tls_incoming, ReloadableServerTls, ServerTlsFiles},
+};
+use kv_service_client::{
+    Client, Error as ClientError, PutOptions, ScanOptions, Versioned, WatchEvent, WatchOptions,
+};
+use kv_service_frontend::tls::{
+    https_tls_config, watch_https_tls_config, ClientIdentity, ClientIdentityAcceptor,
+    ServerIdentity, ServerIdentityFiles,
+};
 use reqwest::StatusCode;
 use serde_json::Value;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tonic::{
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+    Code, Status,
+};
 
 fn get_available_port() -> Option<u16> {
     (10000..20000).find(|port| port_is_available(*port))
 }
 
+#[allow(clippy::redundant_pattern_matching)]
 fn port_is_available(port: u16) -> bool {
     match TcpListener::bind(("127.0.0.1", port)) {
         Ok(_) => true,
@@ -18,25 +52,35 @@ fn port_is_available(port: u16) -> bool {
 }
 
 async fn spawn_services() -> String {
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    spawn_http_server(grpc_server_address, Vec::new()).await
+}
+
+async fn spawn_grpc_server(grpc_server: tonic::transport::server::Router) -> String {
     let grpc_server_address = format!("127.0.0.1:{}", get_available_port().unwrap());
     let cloned_grpc_server_address = grpc_server_address.clone();
     tokio::spawn(async move {
-        let grpc_server = kv_service_backend::create_grpc_server(None).unwrap();
         grpc_server
             .serve(cloned_grpc_server_address.parse().unwrap())
             .await
             .unwrap();
     });
     tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    grpc_server_address
+}
 
-    let cloned_grpc_server_address = grpc_server_address.clone();
+async fn spawn_http_server(grpc_server_address: String, follower_addresses: Vec<String>) -> String {
     let http_server_address = format!("127.0.0.1:{}", get_available_port().unwrap());
     let cloned_http_server_address = http_server_address.clone();
     tokio::spawn(async move {
-        let grpc_client =
-            kv_service_frontend::create_grpc_client(&cloned_grpc_server_address, None)
-                .await
-                .unwrap();
+        let grpc_client = kv_service_frontend::create_grpc_client(
+            &grpc_server_address,
+            &follower_addresses,
+            None,
+        )
+        .await
+        .unwrap();
         let (server, router) = kv_service_frontend::create_http_server(
             cloned_http_server_address.parse().unwrap(),
             None,
@@ -58,6 +102,187 @@ async fn spawn_services() -> String {
     format!("http://{}/api", http_server_address)
 }
 
+/// Serves the REST API over HTTPS and returns the address it listens on.
+async fn spawn_https_server(grpc_server_address: String, tls_config: OpenSSLConfig) -> String {
+    let https_server_address = format!("127.0.0.1:{}", get_available_port().unwrap());
+    let cloned_https_server_address = https_server_address.clone();
+    tokio::spawn(async move {
+        let grpc_client = kv_service_frontend::create_grpc_client(&grpc_server_address, &[], None)
+            .await
+            .unwrap();
+        let (server, router) = kv_service_frontend::create_http_server(
+            cloned_https_server_address.parse().unwrap(),
+            Some(tls_config),
+            grpc_client,
+        )
+        .unwrap();
+        server
+            .unwrap_left()
+            .serve(router.into_make_service())
+            .await
+            .unwrap();
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    https_server_address
+}
+
+/// Certificate authority that issues the certificates of TLS tests.
+struct TestCa(rcgen::Certificate);
+
+impl TestCa {
+    fn new() -> Self {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "kv-service test CA");
+        Self(rcgen::Certificate::from_params(params).unwrap())
+    }
+
+    fn pem(&self) -> String {
+        self.0.serialize_pem().unwrap()
+    }
+
+    /// Returns the certificate and private key of a server or client with the names, in PEM.
+    fn issue(&self, names: &[&str]) -> ServerIdentity {
+        let mut params = rcgen::CertificateParams::new(
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        );
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, names[0]);
+        let certificate = rcgen::Certificate::from_params(params).unwrap();
+        ServerIdentity {
+            certificate_chain: certificate.serialize_pem_with_signer(&self.0).unwrap(),
+            private_key: certificate.serialize_private_key_pem(),
+        }
+    }
+}
+
+/// Sends a request to `server_name`, which resolves to `address`, trusting only `ca`.
+async fn https_get(
+    ca: &TestCa,
+    server_name: &str,
+    address: &str,
+) -> reqwest::Result<reqwest::Response> {
+    https_get_path(ca, None, server_name, address, "/api/missing").await
+}
+
+/// Like [`https_get`] for `path`, authenticating with the `client` certificate if there is one.
+async fn https_get_path(
+    ca: &TestCa,
+    client: Option<&ServerIdentity>,
+    server_name: &str,
+    address: &str,
+    path: &str,
+) -> reqwest::Result<reqwest::Response> {
+    let mut builder = reqwest::Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap())
+        .resolve(server_name, address.parse().unwrap());
+    if let Some(client) = client {
+        builder = builder.identity(
+            reqwest::Identity::from_pkcs8_pem(
+                client.certificate_chain.as_bytes(),
+                client.private_key.as_bytes(),
+            )
+            .unwrap(),
+        );
+    }
+    let port = address.rsplit_once(':').unwrap().1;
+    builder
+        .build()
+        .unwrap()
+        .get(format!("https://{}:{}{}", server_name, port, path))
+        .send()
+        .await
+}
+
+/// Sends a gRPC request over a new mutual TLS connection to `address`, trusting only `ca`.
+async fn grpc_tls_get(ca: &TestCa, client: &ServerIdentity, address: &str) -> Result<(), Status> {
+    let tls_config = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(ca.pem()))
+        .identity(Identity::from_pem(
+            &client.certificate_chain,
+            &client.private_key,
+        ))
+        .domain_name("kv.test");
+    let channel = Endpoint::from_shared(format!("https://{}", address))
+        .unwrap()
+        .tls_config(tls_config)
+        .unwrap()
+        .connect()
+        .await
+        .map_err(|err| Status::unavailable(err.to_string()))?;
+    let mut client = KeyValueServiceClient::new(channel);
+    match get_key(&mut client, "missing").await {
+        Ok(_) => Ok(()),
+        Err(status) if status.code() == Code::NotFound => Ok(()),
+        Err(status) => Err(status),
+    }
+}
+
+fn write_identity(dir: &std::path::Path, identity: &ServerIdentity) {
+    // The key goes first, a reload that sees only the new key fails and the next one succeeds.
+    std::fs::write(dir.join("server.key"), &identity.private_key).unwrap();
+    std::fs::write(dir.join("server.crt"), &identity.certificate_chain).unwrap();
+}
+
+/// Serves the REST API over the shards and the shard admin API, returns their addresses.
+async fn spawn_sharded_http_server(
+    shard_addresses: Vec<String>,
+    state_file: std::path::PathBuf,
+) -> (String, String) {
+    let http_server_address = format!("127.0.0.1:{}", get_available_port().unwrap());
+    let admin_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin_address = admin_listener.local_addr().unwrap();
+    let cloned_http_server_address = http_server_address.clone();
+    tokio::spawn(async move {
+        let grpc_client = kv_service_frontend::create_sharded_grpc_client(
+            &shard_addresses,
+            None,
+            Some(&state_file),
+        )
+        .await
+        .unwrap();
+        let (_, admin_router) = kv_service_frontend::create_shard_admin_server(
+            admin_address,
+            None,
+            grpc_client.clone(),
+            None,
+        )
+        .unwrap();
+        tokio::spawn(axum_server::from_tcp(admin_listener).serve(admin_router.into_make_service()));
+        let (server, router) = kv_service_frontend::create_sharded_http_server(
+            cloned_http_server_address.parse().unwrap(),
+            None,
+            grpc_client,
+        )
+        .unwrap();
+        match server {
+            Either::Left(https_server) => https_server
+                .serve(router.into_make_service())
+                .await
+                .unwrap(),
+            Either::Right(http_server) => {
+                http_server.serve(router.into_make_service()).await.unwrap()
+            }
+        }
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    (
+        format!("http://{}/api", http_server_address),
+        format!("http://{}/api", admin_address),
+    )
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_kv_services() {
@@ -79,6 +304,179 @@ async fn test_kv_services() {
     assert_eq!(response_get.json::<Value>().await.unwrap(), "value");
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_exact_numbers() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let body = r#"{"big":123456789012345678901234567890,"id":9007199254740993,"max":18446744073709551615,"price":0.10000000000000000000001,"ratio":1.5,"values":[-1,1e+30]}"#;
+    let response_put = client
+        .put(format!("{}/numbers", api_address))
+        .header("content-type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+    let response_get = client
+        .get(format!("{}/numbers", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::OK);
+    assert_eq!(response_get.text().await.unwrap(), body);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_https_sni() {
+    let ca_a = TestCa::new();
+    let ca_b = TestCa::new();
+    let tls_config =
+        https_tls_config(&[ca_a.issue(&["a.test"]), ca_b.issue(&["*.b.test"])], None).unwrap();
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let address = spawn_https_server(grpc_server_address, tls_config).await;
+
+    // Each name is only trusted with the CA of the certificate SNI selects for it.
+    let response = https_get(&ca_a, "a.test", &address).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = https_get(&ca_b, "api.b.test", &address).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(https_get(&ca_a, "api.b.test", &address).await.is_err());
+    assert!(https_get(&ca_b, "a.test", &address).await.is_err());
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_https_client_auth() {
+    let server_ca = TestCa::new();
+    let client_ca = TestCa::new();
+    let other_ca = TestCa::new();
+    let client = client_ca.issue(&["reporting.example.com"]);
+    let tls_config =
+        https_tls_config(&[server_ca.issue(&["kv.test"])], Some(&client_ca.pem())).unwrap();
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let address = spawn_https_server(grpc_server_address, tls_config.clone()).await;
+
+    // Only clients with a certificate of the client CA get through the handshake.
+    let response = https_get_path(
+        &server_ca,
+        Some(&client),
+        "kv.test",
+        &address,
+        "/api/missing",
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(https_get(&server_ca, "kv.test", &address).await.is_err());
+    let other = other_ca.issue(&["reporting.example.com"]);
+    assert!(https_get_path(
+        &server_ca,
+        Some(&other),
+        "kv.test",
+        &address,
+        "/api/missing"
+    )
+    .await
+    .is_err());
+
+    // Handlers see the identity of the certificate.
+    let router = axum::Router::new().route(
+        "/whoami",
+        axum::routing::get(
+            |identity: Option<axum::Extension<ClientIdentity>>| async move {
+                let axum::Extension(identity) = identity.unwrap();
+                format!("{} {}", identity.subject, identity.alt_names.join(","))
+            },
+        ),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let whoami_address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(
+        axum_server::from_tcp(listener)
+            .acceptor(ClientIdentityAcceptor::new(tls_config))
+            .serve(router.into_make_service()),
+    );
+    let response = https_get_path(
+        &server_ca,
+        Some(&client),
+        "kv.test",
+        &whoami_address,
+        "/whoami",
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        response.text().await.unwrap(),
+        "CN=reporting.example.com reporting.example.com"
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_tls_reload() {
+    let old_ca = TestCa::new();
+    let new_ca = TestCa::new();
+    let client_ca = TestCa::new();
+    let client = client_ca.issue(&["client"]);
+    let dir = tempfile::tempdir().unwrap();
+    write_identity(dir.path(), &old_ca.issue(&["kv.test"]));
+    std::fs::write(dir.path().join("root.crt"), client_ca.pem()).unwrap();
+    let interval = Duration::from_millis(100);
+
+    let server_tls = ReloadableServerTls::watch(
+        ServerTlsFiles {
+            certificate_chain: dir.path().join("server.crt"),
+            private_key: dir.path().join("server.key"),
+            client_ca: dir.path().join("root.crt"),
+        },
+        interval,
+    )
+    .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let grpc_tls_address = listener.local_addr().unwrap().to_string();
+    let grpc_server = kv_service_backend::create_grpc_server(None).unwrap();
+    tokio::spawn(grpc_server.serve_with_incoming(tls_incoming(listener, server_tls)));
+
+    let https_tls_config = watch_https_tls_config(
+        vec![ServerIdentityFiles {
+            certificate_chain: dir.path().join("server.crt"),
+            private_key: dir.path().join("server.key"),
+        }],
+        None,
+        interval,
+    )
+    .unwrap();
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let https_address = spawn_https_server(grpc_server_address, https_tls_config).await;
+
+    https_get(&old_ca, "kv.test", &https_address).await.unwrap();
+    grpc_tls_get(&old_ca, &client, &grpc_tls_address)
+        .await
+        .unwrap();
+
+    // New connections get the rotated certificate once the servers notice the change.
+    write_identity(dir.path(), &new_ca.issue(&["kv.test"]));
+    let mut attempts = 0;
+    while https_get(&new_ca, "kv.test", &https_address).await.is_err()
+        || grpc_tls_get(&new_ca, &client, &grpc_tls_address)
+            .await
+            .is_err()
+    {
+        attempts += 1;
+        assert!(attempts < 50, "the certificates weren't reloaded");
+        tokio::time::sleep(interval).await;
+    }
+    assert!(https_get(&old_ca, "kv.test", &https_address).await.is_err());
+    assert!(grpc_tls_get(&old_ca, &client, &grpc_tls_address)
+        .await
+        .is_err());
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_kv_services_get_nonexistent_key() {
@@ -165,4 +563,1278 @@ async fn kest_kv_services_put_null_value() {
         .await
         .unwrap();
     assert_eq!(response_put.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response_put.json::<Value>().await.unwrap()["error"]["code"],
+        "invalid_argument"
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_put_with_ttl() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let response_put = client
+        .put(format!("{}/test?ttl=1", api_address))
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+    let response_get = client
+        .get(format!("{}/test", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::OK);
+    tokio::time::sleep(tokio::time::Duration::from_millis(1500)).await;
+    let response_get = client
+        .get(format!("{}/test", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_scan_with_pagination() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    for key in ["a/1", "a/2", "a/3", "b/1"] {
+        let response_put = client
+            .put(format!("{}/{}", api_address, key.replace('/', "%2F")))
+            .json(&key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response_put.status(), StatusCode::CREATED);
+    }
+
+    let response_scan = client
+        .get(&api_address)
+        .query(&[("prefix", "a/"), ("limit", "2")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_scan.status(), StatusCode::OK);
+    let page = response_scan.json::<Value>().await.unwrap();
+    assert_eq!(
+        page["items"],
+        serde_json::json!([{"key": "a/1", "value": "a/1"}, {"key": "a/2", "value": "a/2"}])
+    );
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let response_scan = client
+        .get(&api_address)
+        .query(&[("prefix", "a/"), ("limit", "2"), ("cursor", cursor)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_scan.status(), StatusCode::OK);
+    let page = response_scan.json::<Value>().await.unwrap();
+    assert_eq!(
+        page,
+        serde_json::json!({"items": [{"key": "a/3", "value": "a/3"}], "next_cursor": null})
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_conditional_put() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let response_put = client
+        .put(format!("{}/test", api_address))
+        .header("If-None-Match", "*")
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+    let etag = response_put.headers()["ETag"].clone();
+    let response_put = client
+        .put(format!("{}/test", api_address))
+        .header("If-None-Match", "*")
+        .json(&"other")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::PRECONDITION_FAILED);
+    let response_get = client
+        .get(format!("{}/test", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.headers()["ETag"], etag);
+    let response_put = client
+        .put(format!("{}/test", api_address))
+        .header("If-Match", etag.clone())
+        .json(&"value2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::NO_CONTENT);
+    assert_ne!(response_put.headers()["ETag"], etag);
+    let response_put = client
+        .put(format!("{}/test", api_address))
+        .header("If-Match", etag)
+        .json(&"value3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_transaction() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let response_put = client
+        .put(format!("{}/a", api_address))
+        .json(&"a")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+    let transaction = |expected: &str| {
+        serde_json::json!({
+            "preconditions": [{"key": "a", "value": expected}],
+            "operations": [
+                {"set": {"key": "b", "value": "b"}},
+                {"delete": {"key": "a"}},
+            ],
+        })
+    };
+    let response_txn = client
+        .post(format!("{}/_txn", api_address))
+        .json(&transaction("other"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_txn.status(), StatusCode::CONFLICT);
+    let response_get = client
+        .get(format!("{}/b", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::NOT_FOUND);
+    let response_txn = client
+        .post(format!("{}/_txn", api_address))
+        .json(&transaction("a"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_txn.status(), StatusCode::OK);
+    let body: Value = response_txn.json().await.unwrap();
+    assert_eq!(body["committed"], Value::Bool(true));
+    let response_get = client
+        .get(format!("{}/a", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::NOT_FOUND);
+    let response_get = client
+        .get(format!("{}/b", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_batch() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let items: Vec<Value> = (0..100)
+        .map(|i| serde_json::json!({"set": {"key": format!("key{i}"), "value": i}}))
+        .chain([
+            serde_json::json!({"get": {"key": "key42"}}),
+            serde_json::json!({"delete": {"key": "key42"}}),
+            serde_json::json!({"get": {"key": "key42"}}),
+        ])
+        .collect();
+    let response_batch = client
+        .post(format!("{}/_batch", api_address))
+        .json(&items)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_batch.status(), StatusCode::OK);
+    let results: Vec<Value> = response_batch.json().await.unwrap();
+    assert_eq!(results.len(), 103);
+    assert_eq!(results[0]["status"], 201);
+    assert_eq!(results[100]["status"], 200);
+    assert_eq!(results[100]["value"], 42.0);
+    assert_eq!(results[101]["status"], 200);
+    assert_eq!(results[102]["status"], 404);
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_watch() {
+    let api_address = spawn_services().await;
+    let client = reqwest::Client::new();
+    let mut response_watch = client
+        .get(format!("{}?prefix=watch-&watch=true", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_watch.status(), StatusCode::OK);
+
+    client
+        .put(format!("{}/watch-a", api_address))
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    client
+        .put(format!("{}/other", api_address))
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    client
+        .delete(format!("{}/watch-a", api_address))
+        .send()
+        .await
+        .unwrap();
+
+    let mut body = String::new();
+    while body.matches("\n\n").count() < 2 {
+        let chunk =
+            tokio::time::timeout(tokio::time::Duration::from_secs(5), response_watch.chunk())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        body.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    let events: Vec<Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            serde_json::json!({"type": "put", "key": "watch-a", "value": "value", "revision": 1}),
+            serde_json::json!({"type": "delete", "key": "watch-a", "revision": 3}),
+        ]
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_client() {
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let api_address = spawn_http_server(grpc_server_address.clone(), Vec::new()).await;
+    let clients = [
+        (
+            "grpc",
+            Client::grpc(format!("http://{}", grpc_server_address))
+                .build()
+                .unwrap(),
+        ),
+        (
+            "http",
+            Client::http(api_address.trim_end_matches("/api"))
+                .build()
+                .unwrap(),
+        ),
+    ];
+    for (name, client) in clients {
+        let prefix = format!("{}/", name);
+        let key = format!("{}client", prefix);
+        let mut watch = client
+            .watch::<Value>(
+                &prefix,
+                WatchOptions {
+                    prefix: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(client.get::<Value>(&key).await.unwrap(), None);
+        let create = PutOptions {
+            condition: Some(kv_service_client::Condition::Exists(false)),
+            ..Default::default()
+        };
+        let value: Value = serde_json::from_str(r#"{"price":0.10000000000000000000001}"#).unwrap();
+        let version = client.put_with(&key, &value, create.clone()).await.unwrap();
+        assert_eq!(
+            client.get_versioned(&key).await.unwrap(),
+            Some(Versioned {
+                value: value.clone(),
+                version
+            })
+        );
+        assert!(matches!(
+            client.put_with(&key, &value, create).await,
+            Err(ClientError::ConditionFailed)
+        ));
+
+        client.put(&format!("{}other", prefix), &2).await.unwrap();
+        let page = client
+            .scan::<Value>(ScanOptions {
+                prefix: prefix.clone(),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].key, key);
+        let page = client
+            .scan::<u64>(ScanOptions {
+                prefix: prefix.clone(),
+                cursor: page.next_cursor,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(page.items[0].value, 2);
+        assert_eq!(page.next_cursor, None);
+
+        assert!(client.delete(&key).await.unwrap());
+        assert!(!client.delete(&key).await.unwrap());
+
+        let mut events = Vec::new();
+        while events.len() < 3 {
+            let event = tokio::time::timeout(Duration::from_secs(5), watch.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            events.push(event);
+        }
+        assert!(
+            matches!(&events[0], WatchEvent::Put { value: event_value, .. } if *event_value == value)
+        );
+        assert_eq!(events[1].key(), format!("{}other", prefix));
+        assert!(
+            matches!(&events[2], WatchEvent::Delete { key: event_key, .. } if *event_key == key)
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_kvctl() {
+    let grpc_server_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let api_address = spawn_http_server(grpc_server_address.clone(), Vec::new()).await;
+    let http_address = api_address.trim_end_matches("/api").to_string();
+    let kvctl = |protocol: &str, args: &[&str]| {
+        let address = match protocol {
+            "grpc" => &grpc_server_address,
+            _ => &http_address,
+        };
+        let mut cli = vec!["kvctl", "--protocol", protocol, "--address", address];
+        cli.extend(args);
+        let cli = kv_service_cli::Cli::parse_from(cli);
+        async move {
+            let mut out = Vec::new();
+            kv_service_cli::run(cli, &mut out)
+                .await
+                .map(|()| String::from_utf8(out).unwrap())
+        }
+    };
+    let json_lines = |output: String| -> Vec<Value> {
+        output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let export = dir.path().join("export.jsonl");
+    let export = export.to_str().unwrap();
+    for protocol in ["http", "grpc"] {
+        let a = format!("{}/a", protocol);
+        let b = format!("{}/b", protocol);
+        let output = kvctl(protocol, &["put", &a, r#"{"n":1.10}"#, "--if-absent"])
+            .await
+            .unwrap();
+        let version = json_lines(output)[0]["version"].as_u64().unwrap();
+        assert!(kvctl(protocol, &["put", &a, "2", "--if-absent"])
+            .await
+            .is_err());
+        kvctl(protocol, &["put", &b, r#""b""#]).await.unwrap();
+        assert_eq!(
+            kvctl(protocol, &["get", &a]).await.unwrap(),
+            format!(
+                "{{\"key\":\"{}\",\"value\":{{\"n\":1.10}},\"version\":{}}}\n",
+                a, version
+            )
+        );
+        let output = kvctl(protocol, &["scan", "--prefix", protocol, "--limit", "1"])
+            .await
+            .unwrap();
+        assert_eq!(
+            output,
+            format!("{{\"key\":\"{}\",\"value\":{{\"n\":1.10}}}}\n", a)
+        );
+
+        kvctl(
+            protocol,
+            &["export", "--prefix", protocol, "--file", export],
+        )
+        .await
+        .unwrap();
+        kvctl(protocol, &["delete", &a]).await.unwrap();
+        kvctl(protocol, &["delete", &b]).await.unwrap();
+        assert!(kvctl(protocol, &["get", &a]).await.is_err());
+        let output = kvctl(protocol, &["import", export]).await.unwrap();
+        assert_eq!(json_lines(output), vec![serde_json::json!({"imported": 2})]);
+        assert_eq!(
+            kvctl(
+                protocol,
+                &["--output", "table", "scan", "--prefix", protocol]
+            )
+            .await
+            .unwrap(),
+            format!("KEY     VALUE\n{}  {{\"n\":1.10}}\n{}  \"b\"\n", a, b)
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_websocket() {
+    let api_address = spawn_services().await;
+    let ws_address = format!("{}/_ws", api_address.replacen("http", "ws", 1));
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_address).await.unwrap();
+    // The event of the put is delivered independently of the command replies, so the
+    // subscription is only dropped once it has arrived.
+    let event = serde_json::json!({
+        "event": {"subscription": 1, "type": "put", "key": "ws-a", "value": "value", "revision": 1}
+    });
+    let replies = send_ws_requests(
+        &mut socket,
+        vec![
+            serde_json::json!({"subscribe": {"id": 1, "key": "ws-", "prefix": true}}),
+            serde_json::json!({"put": {"id": 2, "key": "ws-a", "value": "value"}}),
+            serde_json::json!({"get": {"id": 3, "key": "ws-a"}}),
+        ],
+        Some(&event),
+    )
+    .await;
+    assert_eq!(
+        replies,
+        vec![
+            serde_json::json!({"result": {"id": 1, "status": 200}}),
+            serde_json::json!({"result": {"id": 2, "status": 201, "version": 1}}),
+            serde_json::json!({"result": {"id": 3, "status": 200, "value": "value", "version": 1}}),
+        ]
+    );
+
+    let replies = send_ws_requests(
+        &mut socket,
+        vec![
+            serde_json::json!({"unsubscribe": {"id": 4, "subscription": 1}}),
+            serde_json::json!({"delete": {"id": 5, "key": "ws-a"}}),
+        ],
+        None,
+    )
+    .await;
+    assert_eq!(
+        replies,
+        vec![
+            serde_json::json!({"result": {"id": 4, "status": 200}}),
+            serde_json::json!({"result": {"id": 5, "status": 200}}),
+        ]
+    );
+}
+
+/// Sends the requests and returns their replies, waiting for `event` as well if it's set.
+async fn send_ws_requests<S>(
+    socket: &mut S,
+    requests: Vec<Value>,
+    event: Option<&Value>,
+) -> Vec<Value>
+where
+    S: futures_util::Sink<tokio_tungstenite::tungstenite::Message>
+        + futures_util::Stream<
+            Item = Result<
+                tokio_tungstenite::tungstenite::Message,
+                tokio_tungstenite::tungstenite::Error,
+            >,
+        > + Unpin,
+    S::Error: std::fmt::Debug,
+{
+    use futures_util::SinkExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let expected = requests.len();
+    for request in requests {
+        socket
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+    }
+    let mut event_received = event.is_none();
+    let mut replies = Vec::new();
+    while replies.len() < expected || !event_received {
+        let message = tokio::time::timeout(tokio::time::Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let message = serde_json::from_str::<Value>(message.to_text().unwrap()).unwrap();
+        if Some(&message) == event {
+            event_received = true;
+        } else {
+            replies.push(message);
+        }
+    }
+    replies
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_follower_unavailable() {
+    let leader_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    // The follower never gets a snapshot, so it rejects every read.
+    let follower_address = spawn_grpc_server(
+        kv_service_backend::create_follower_grpc_server("127.0.0.1:1", None, None).unwrap(),
+    )
+    .await;
+    let api_address = spawn_http_server(leader_address, vec![follower_address]).await;
+    let client = reqwest::Client::new();
+
+    let response_put = client
+        .put(format!("{}/fallback", api_address))
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+
+    let response_get = client
+        .get(format!("{}/fallback", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.status(), StatusCode::OK);
+    assert_eq!(response_get.json::<Value>().await.unwrap(), "value");
+
+    let response_scan = client
+        .get(&api_address)
+        .query(&[("prefix", "fall")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_scan.status(), StatusCode::OK);
+    assert_eq!(
+        response_scan.json::<Value>().await.unwrap()["items"],
+        serde_json::json!([{"key": "fallback", "value": "value"}])
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_replication() {
+    let leader_address =
+        spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await;
+    let follower_address = spawn_grpc_server(
+        kv_service_backend::create_follower_grpc_server(&leader_address, None, None).unwrap(),
+    )
+    .await;
+    let api_address = spawn_http_server(leader_address, vec![follower_address]).await;
+    let client = reqwest::Client::new();
+
+    let response_put = client
+        .put(format!("{}/replicated", api_address))
+        .json(&"value")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_put.status(), StatusCode::CREATED);
+
+    // Reads are served by the follower, which receives the write asynchronously.
+    let get_status = || async {
+        client
+            .get(format!("{}/replicated", api_address))
+            .send()
+            .await
+            .unwrap()
+            .status()
+    };
+    let mut attempts = 0;
+    while get_status().await != StatusCode::OK {
+        attempts += 1;
+        assert!(attempts < 50, "the write wasn't replicated");
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    }
+    let response_get = client
+        .get(format!("{}/replicated", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_get.headers()["etag"], "\"1\"");
+    assert_eq!(response_get.json::<Value>().await.unwrap(), "value");
+
+    let response_delete = client
+        .delete(format!("{}/replicated", api_address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response_delete.status(), StatusCode::OK);
+    let mut attempts = 0;
+    while get_status().await != StatusCode::NOT_FOUND {
+        attempts += 1;
+        assert!(attempts < 50, "the delete wasn't replicated");
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    }
+}
+
+struct ClusterNode {
+    storage: Arc<ClusterStorage>,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<()>,
+}
+
+/// A Raft cluster running in the test process, whose nodes keep their data in a temporary
+/// directory so they can be killed and restarted.
+struct TestCluster {
+    addresses: BTreeMap<u64, String>,
+    voters: BTreeMap<u64, String>,
+    nodes: BTreeMap<u64, ClusterNode>,
+    dir: tempfile::TempDir,
+}
+
+impl TestCluster {
+    async fn start(size: u64) -> Self {
+        let mut cluster = Self {
+            addresses: BTreeMap::new(),
+            voters: BTreeMap::new(),
+            nodes: BTreeMap::new(),
+            dir: tempfile::tempdir().unwrap(),
+        };
+        for id in 1..=size {
+            let address = cluster.allocate_address(id);
+            cluster.voters.insert(id, address);
+        }
+        for id in 1..=size {
+            cluster.start_node(id).await;
+        }
+        cluster.wait_for_leader().await;
+        cluster
+    }
+
+    fn allocate_address(&mut self, id: u64) -> String {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let address = format!("127.0.0.1:{port}");
+        self.addresses.insert(id, address.clone());
+        address
+    }
+
+    /// Starts node `id`, a node that isn't one of the initial voters waits to be added.
+    async fn start_node(&mut self, id: u64) {
+        let peers = if self.voters.contains_key(&id) {
+            self.voters.clone()
+        } else {
+            BTreeMap::new()
+        };
+        let storage = Arc::new(
+            ClusterStorage::start(ClusterConfig {
+                node_id: id,
+                peers,
+                data_dir: Some(self.dir.path().join(id.to_string())),
+                peer_tls_config: None,
+            })
+            .await
+            .unwrap(),
+        );
+        let router = kv_service_backend::create_cluster_grpc_server(storage.clone(), None).unwrap();
+        let address = self.addresses[&id].parse().unwrap();
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            router
+                .serve_with_shutdown(address, async {
+                    let _ = signal.await;
+                })
+                .await
+                .unwrap();
+        });
+        self.nodes.insert(
+            id,
+            ClusterNode {
+                storage,
+                shutdown,
+                server,
+            },
+        );
+    }
+
+    async fn kill(&mut self, id: u64) {
+        let mut node = self.nodes.remove(&id).unwrap();
+        node.storage.shutdown().await.unwrap();
+        let _ = node.shutdown.send(());
+        if tokio::time::timeout(Duration::from_secs(5), &mut node.server)
+            .await
+            .is_err()
+        {
+            node.server.abort();
+        }
+    }
+
+    async fn restart(&mut self, id: u64) {
+        self.start_node(id).await;
+    }
+
+    async fn wait_for_leader(&self) -> u64 {
+        for _ in 0..100 {
+            let leader = self.nodes.iter().find_map(|(id, node)| {
+                let metrics = node.storage.raft().metrics();
+                let metrics = metrics.borrow();
+                (metrics.state.is_leader() && metrics.current_leader == Some(*id)).then_some(*id)
+            });
+            if let Some(leader) = leader {
+                return leader;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the cluster didn't elect a leader");
+    }
+
+    fn channel(&self, id: u64) -> Channel {
+        Endpoint::from_shared(format!("http://{}", self.addresses[&id]))
+            .unwrap()
+            .connect_lazy()
+    }
+
+    fn client(&self, id: u64) -> KeyValueServiceClient<Channel> {
+        KeyValueServiceClient::new(self.channel(id))
+    }
+}
+
+fn string_value(value: &str) -> JsonValue {
+    JsonValue {
+        kind: Some(json_value::Kind::StringValue(value.to_string())),
+    }
+}
+
+async fn set_key(
+    client: &mut KeyValueServiceClient<Channel>,
+    key: &str,
+    value: &str,
+    condition: Option<Check>,
+) -> Result<SetResponse, Status> {
+    let request = KeyValueRequest {
+        key: key.to_string(),
+        value: Some(string_value(value)),
+        condition: condition.map(|check| Condition { check: Some(check) }),
+        ..Default::default()
+    };
+    Ok(client.set(request).await?.into_inner())
+}
+
+/// Returns the value of `key`, which must be a string, and its version.
+async fn get_key(
+    client: &mut KeyValueServiceClient<Channel>,
+    key: &str,
+) -> Result<Option<(String, u64)>, Status> {
+    let response = client
+        .get(KeyRequest {
+            key: key.to_string(),
+        })
+        .await?
+        .into_inner();
+    Ok(response.value.map(|value| match value.kind {
+        Some(json_value::Kind::StringValue(value)) => (value, response.version),
+        kind => panic!("expected a string value, got {kind:?}"),
+    }))
+}
+
+/// Retries `request` while the cluster is electing a leader or a node is catching up.
+async fn retry<T, F, Fut>(mut request: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    let mut attempts = 0;
+    loop {
+        match request().await {
+            Ok(result) => return result,
+            Err(status) => {
+                attempts += 1;
+                assert!(attempts < 50, "request kept failing: {status:?}");
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_cluster_leader_failure() {
+    let mut cluster = TestCluster::start(3).await;
+    let leader = cluster.wait_for_leader().await;
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+
+    // A write sent to a follower is forwarded to the leader, and once it's acknowledged every
+    // node returns it.
+    set_key(&mut cluster.client(follower), "a", "1", None)
+        .await
+        .unwrap();
+    for id in 1..=3 {
+        let value = get_key(&mut cluster.client(id), "a").await.unwrap();
+        assert_eq!(value.map(|(value, _)| value).as_deref(), Some("1"));
+    }
+
+    cluster.kill(leader).await;
+    let new_leader = cluster.wait_for_leader().await;
+    assert_ne!(new_leader, leader);
+    let client = cluster.client(follower);
+    retry(|| {
+        let mut client = client.clone();
+        async move { set_key(&mut client, "b", "2", None).await }
+    })
+    .await;
+    for id in cluster.nodes.keys() {
+        let mut client = cluster.client(*id);
+        assert!(get_key(&mut client, "a").await.unwrap().is_some());
+        assert!(get_key(&mut client, "b").await.unwrap().is_some());
+    }
+
+    // The old leader reloads its log and catches up on the write it missed.
+    cluster.restart(leader).await;
+    let client = cluster.client(leader);
+    let value = retry(|| {
+        let mut client = client.clone();
+        async move { get_key(&mut client, "b").await }
+    })
+    .await;
+    assert_eq!(value.map(|(value, _)| value).as_deref(), Some("2"));
+}
+
+/// Increments a counter with compare-and-set through every node in turn until `stop` is set.
+/// Returns how many increments were acknowledged and how many failed in a way that leaves
+/// their outcome unknown.
+async fn increment_counter(
+    clients: Vec<KeyValueServiceClient<Channel>>,
+    first_client: usize,
+    stop: Arc<AtomicBool>,
+) -> (u64, u64) {
+    let (mut acknowledged, mut ambiguous) = (0, 0);
+    let mut last_seen = 0;
+    for attempt in first_client.. {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let mut client = clients[attempt % clients.len()].clone();
+        let Ok(Some((value, version))) = get_key(&mut client, "counter").await else {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            continue;
+        };
+        let value: u64 = value.parse().unwrap();
+        // Reads are linearizable, so they never go back in time even when they are served by
+        // different nodes.
+        assert!(value >= last_seen, "read {value} after {last_seen}");
+        last_seen = value;
+        let next = (value + 1).to_string();
+        match set_key(&mut client, "counter", &next, Some(Check::Version(version))).await {
+            Ok(_) => {
+                acknowledged += 1;
+                last_seen = value + 1;
+            }
+            Err(status) if status.code() == Code::FailedPrecondition => {}
+            Err(_) => {
+                ambiguous += 1;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        }
+    }
+    (acknowledged, ambiguous)
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_cluster_linearizable_counter() {
+    let mut cluster = TestCluster::start(3).await;
+    let clients: Vec<_> = (1..=3).map(|id| cluster.client(id)).collect();
+    set_key(&mut clients[0].clone(), "counter", "0", None)
+        .await
+        .unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let tasks: Vec<_> = (0..clients.len())
+        .map(|first_client| {
+            tokio::spawn(increment_counter(
+                clients.clone(),
+                first_client,
+                stop.clone(),
+            ))
+        })
+        .collect();
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let leader = cluster.wait_for_leader().await;
+    cluster.kill(leader).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    cluster.restart(leader).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let leader = cluster.wait_for_leader().await;
+    let follower = (1..=3).find(|id| *id != leader).unwrap();
+    cluster.kill(follower).await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    cluster.restart(follower).await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    stop.store(true, Ordering::Relaxed);
+
+    let (mut acknowledged, mut ambiguous) = (0, 0);
+    for task in tasks {
+        let (task_acknowledged, task_ambiguous) = task.await.unwrap();
+        acknowledged += task_acknowledged;
+        ambiguous += task_ambiguous;
+    }
+    assert!(acknowledged > 0);
+
+    // Every acknowledged increment was applied exactly once, the ambiguous ones at most once.
+    for id in 1..=3 {
+        let client = cluster.client(id);
+        let value = retry(|| {
+            let mut client = client.clone();
+            async move { get_key(&mut client, "counter").await }
+        })
+        .await;
+        let value: u64 = value.unwrap().0.parse().unwrap();
+        assert!(
+            acknowledged <= value && value <= acknowledged + ambiguous,
+            "counter is {value} after {acknowledged} acknowledged and {ambiguous} ambiguous increments"
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_cluster_without_majority() {
+    let mut cluster = TestCluster::start(3).await;
+    let leader = cluster.wait_for_leader().await;
+    let followers: Vec<u64> = (1..=3).filter(|id| *id != leader).collect();
+    cluster.kill(followers[0]).await;
+    cluster.kill(followers[1]).await;
+
+    let mut client = cluster.client(leader);
+    let status = set_key(&mut client, "a", "1", None).await.unwrap_err();
+    assert!(
+        matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+        "{status:?}"
+    );
+    let status = get_key(&mut client, "a").await.unwrap_err();
+    assert!(
+        matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded),
+        "{status:?}"
+    );
+
+    // With a majority again the cluster accepts writes.
+    cluster.restart(followers[0]).await;
+    retry(|| {
+        let mut client = client.clone();
+        async move { set_key(&mut client, "b", "2", None).await }
+    })
+    .await;
+    let value = get_key(&mut client, "b").await.unwrap();
+    assert_eq!(value.map(|(value, _)| value).as_deref(), Some("2"));
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_cluster_membership_change() {
+    let mut cluster = TestCluster::start(3).await;
+    let leader = cluster.wait_for_leader().await;
+    let mut client = cluster.client(leader);
+    for i in 0..20 {
+        set_key(&mut client, &format!("key-{i}"), &i.to_string(), None)
+            .await
+            .unwrap();
+    }
+
+    // Compacting the leader's log leaves a new node no choice but to install the snapshot.
+    let snapshot = AdminServiceClient::new(cluster.channel(leader))
+        .snapshot(SnapshotRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(snapshot.keys, 20);
+
+    let address = cluster.allocate_address(4);
+    cluster.start_node(4).await;
+    let mut cluster_client = ClusterServiceClient::new(cluster.channel(leader));
+    cluster_client
+        .add_learner(AddLearnerRequest {
+            node_id: 4,
+            address,
+        })
+        .await
+        .unwrap();
+    cluster.nodes[&4]
+        .storage
+        .raft()
+        .wait(Some(Duration::from_secs(5)))
+        .metrics(
+            |metrics| metrics.snapshot.map(|log_id| log_id.index) >= Some(snapshot.sequence),
+            "snapshot installed",
+        )
+        .await
+        .unwrap();
+    let value = get_key(&mut cluster.client(4), "key-7").await.unwrap();
+    assert_eq!(value.map(|(value, _)| value).as_deref(), Some("7"));
+
+    // Node 4 replaces a follower.
+    let removed = (1..=3).find(|id| *id != leader).unwrap();
+    let voters: BTreeSet<u64> = (1..=4).filter(|id| *id != removed).collect();
+    let members = cluster_client
+        .change_membership(ChangeMembershipRequest {
+            voters: voters.iter().copied().collect(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .members;
+    assert!(members.iter().all(|member| member.voter));
+    assert_eq!(
+        members
+            .iter()
+            .map(|member| member.node_id)
+            .collect::<BTreeSet<_>>(),
+        voters
+    );
+
+    cluster.kill(removed).await;
+    set_key(&mut cluster.client(4), "after", "x", None)
+        .await
+        .unwrap();
+    let value = get_key(&mut client, "after").await.unwrap();
+    assert_eq!(value.map(|(value, _)| value).as_deref(), Some("x"));
+}
+
+/// Returns the keys stored on the backend at `address`.
+async fn backend_keys(address: &str) -> BTreeSet<String> {
+    let mut client = KeyValueServiceClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let mut stream = client
+        .scan(ScanRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let mut keys = BTreeSet::new();
+    while let Some(response) = stream.message().await.unwrap() {
+        keys.insert(response.key);
+    }
+    keys
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_kv_services_sharding() {
+    let mut shards = Vec::new();
+    for _ in 0..3 {
+        shards.push(spawn_grpc_server(kv_service_backend::create_grpc_server(None).unwrap()).await);
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("shards.json");
+    let (api_address, admin_address) =
+        spawn_sharded_http_server(shards[..2].to_vec(), state_file.clone()).await;
+    let client = reqwest::Client::new();
+    let keys: BTreeSet<String> = (0..60).map(|i| format!("key-{i:02}")).collect();
+    for key in &keys {
+        let response = client
+            .put(format!("{api_address}/{key}"))
+            .json(&key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    // Every key is stored once, on one of the shards.
+    let (first, second) = (
+        backend_keys(&shards[0]).await,
+        backend_keys(&shards[1]).await,
+    );
+    assert!(!first.is_empty() && !second.is_empty());
+    assert!(first.is_disjoint(&second));
+    assert_eq!(&first | &second, keys);
+
+    // Scans merge the shards in key order.
+    let mut scanned = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut request = client.get(&api_address).query(&[("limit", "25")]);
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let page: Value = request.send().await.unwrap().json().await.unwrap();
+        scanned.extend(
+            page["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["key"].as_str().unwrap().to_string()),
+        );
+        match page["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(scanned, keys.iter().cloned().collect::<Vec<_>>());
+
+    // Every shard counts its own revisions, so a watch over all of them can't resume at one.
+    let response = client
+        .get(&api_address)
+        .query(&[
+            ("prefix", "key-"),
+            ("watch", "true"),
+            ("start_revision", "1"),
+        ])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let mut watch = Client::http(api_address.trim_end_matches("/api"))
+        .build()
+        .unwrap()
+        .watch::<Value>(
+            "key-",
+            WatchOptions {
+                prefix: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    // Shards are only managed on the admin listener.
+    let response = client
+        .post(format!("{api_address}/_shards"))
+        .json(&serde_json::json!({"address": shards[2]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    // The new shard receives its share of the keys.
+    let response = client
+        .post(format!("{admin_address}/_shards"))
+        .json(&serde_json::json!({"address": shards[2]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: Value = response.json().await.unwrap();
+    let third = backend_keys(&shards[2]).await;
+    assert!(!third.is_empty());
+    assert_eq!(report["moved"], third.len());
+    assert_eq!(report["shards"].as_array().unwrap().len(), 3);
+
+    // The removed shard hands its keys over to the others.
+    let response = client
+        .delete(format!("{admin_address}/_shards/{}", shards[0]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let shard_list: Value = client
+        .get(format!("{admin_address}/_shards"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    // The shards are listed in address order, not in the order they were added.
+    let mut remaining = vec![shards[1].clone(), shards[2].clone()];
+    remaining.sort();
+    assert_eq!(shard_list["shards"], serde_json::json!(remaining));
+    assert!(backend_keys(&shards[0]).await.is_empty());
+    let (second, third) = (
+        backend_keys(&shards[1]).await,
+        backend_keys(&shards[2]).await,
+    );
+    assert!(second.is_disjoint(&third));
+    assert_eq!(&second | &third, keys);
+    for key in &keys {
+        let response = client
+            .get(format!("{api_address}/{key}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<Value>().await.unwrap(), key.as_str());
+    }
+    let response = client
+        .delete(format!("{admin_address}/_shards/{}", shards[0]))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.json::<Value>().await.unwrap()["error"]["code"],
+        "not_found"
+    );
+
+    // A frontend restarted with the shards it was first given still finds every key.
+    let (restarted_address, _) = spawn_sharded_http_server(shards[..2].to_vec(), state_file).await;
+    for key in &keys {
+        let response = client
+            .get(format!("{restarted_address}/{key}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // Transactions can't span shards.
+    let operations: Vec<Value> = [second.iter().next(), third.iter().next()]
+        .into_iter()
+        .map(|key| serde_json::json!({"delete": {"key": key.unwrap()}}))
+        .collect();
+    let response = client
+        .post(format!("{api_address}/_txn"))
+        .json(&serde_json::json!({"preconditions": [], "operations": operations}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.json::<Value>().await.unwrap()["error"]["code"],
+        "invalid_argument"
+    );
+
+    // The watch saw the keys that moved arrive, but not leave the shard they moved from.
+    let deleted = second.iter().next().unwrap();
+    let response = client
+        .delete(format!("{api_address}/{deleted}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), watch.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match event {
+            WatchEvent::Put { .. } => {}
+            WatchEvent::Delete { key, .. } => {
+                assert_eq!(&key, deleted);
+                break;
+            }
+        }
+    }
 }